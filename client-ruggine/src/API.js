@@ -1,5 +1,8 @@
 import axios from "axios";
-const APIURL = "http://localhost:3000";
+// Server base URL, overridable at build time (e.g. VITE_API_URL=https://chat.example.com)
+const APIURL = import.meta.env.VITE_API_URL ?? "http://localhost:3000";
+// The WebSocket endpoint follows the scheme of the API: ws:// for http://, wss:// for https://
+const WSURL = `${APIURL.replace(/^http/, "ws")}/ws`;
 
 async function loginUser({ username, password }) {
   try {
@@ -18,6 +21,21 @@ async function loginUser({ username, password }) {
   }
 }
 
+// Second step of the login for users with two-factor authentication: a code from the app or a recovery code
+async function verifyLoginCode({ code }) {
+  try {
+    const response = await axios.post(
+      `${APIURL}/login/2fa`,
+      { code },
+      { withCredentials: true }
+    );
+    return response.data;
+  } catch (error) {
+    console.error("Error verifying the login code:", error);
+    throw error;
+  }
+}
+
 async function registerUser({ username, name, surname, password }) {
   
   try {
@@ -102,11 +120,12 @@ async function getChatMessages(chatId) {
 }
 
 // Send a new message in a chat
-async function sendMessage(chatId, msg) {
+// Retries must reuse the same clientId, so the server stores the message only once
+async function sendMessage(chatId, msg, clientId = window.crypto.randomUUID()) {
   try {
     const response = await axios.post(
       `${APIURL}/chats/${chatId}/messages`,
-      { msg },
+      { msg, client_id: clientId },
       { withCredentials: true }
     );
     return response.data; // Server response
@@ -185,7 +204,9 @@ async function leaveGroup(chatId) {
 }
 
 export {
+  WSURL,
   loginUser,
+  verifyLoginCode,
   registerUser,
   getUserChats,
   getUserRequests,
@@ -86,7 +86,7 @@ export default function InviteForm({ onClose, onChatCreated }) {
         setShowAlert(true);
         setTimeout(() => setShowAlert(false), 3000); // close after 3s
       } else {
-        const errorMessage = error.response?.data?.message || 
+        const errorMessage = error.response?.data?.error?.message || 
                            error.message || 
                            "Error creating private chat. Please try again.";
         setAlertMessage(errorMessage);
@@ -131,7 +131,7 @@ export default function InviteForm({ onClose, onChatCreated }) {
       }, 1500);
     } catch (error) {
       console.error("Error creating group chat:", error);
-      const errorMessage = error.response?.data?.message || 
+      const errorMessage = error.response?.data?.error?.message || 
                          error.message || 
                          "Error creating group. Please try again.";
       setAlertMessage(errorMessage);
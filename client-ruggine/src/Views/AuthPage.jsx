@@ -17,9 +17,10 @@ import {
   FaSignInAlt,
   FaEye,
   FaEyeSlash,
+  FaKey,
 } from "react-icons/fa";
 import logo from "../assets/logo.png";
-import { loginUser, registerUser } from "../API";
+import { loginUser, registerUser, verifyLoginCode } from "../API";
 import { useNavigate } from "react-router-dom";
 
 export default function AuthPage({ onLoginSuccess }) {
@@ -30,7 +31,10 @@ export default function AuthPage({ onLoginSuccess }) {
     surname: "",
     password: "",
     confirmPassword: "",
+    code: "",
   });
+  // Set once the password is accepted for an account with two-factor authentication
+  const [needsCode, setNeedsCode] = useState(false);
   const [showAlert, setShowAlert] = useState(false);
   const [alertMessage, setAlertMessage] = useState("");
   const [alertVariant, setAlertVariant] = useState("success");
@@ -83,11 +87,22 @@ export default function AuthPage({ onLoginSuccess }) {
     setLoading(true);
     try {
       if (isLogin) {
-        // API call for login
-        const response = await loginUser({
-          username: formData.username,
-          password: formData.password,
-        });
+        // API call for login, followed by the code when two-factor authentication is enabled
+        if (needsCode) {
+          await verifyLoginCode({ code: formData.code });
+        } else {
+          const response = await loginUser({
+            username: formData.username,
+            password: formData.password,
+          });
+          if (response.two_factor_required) {
+            setNeedsCode(true);
+            setAlertMessage("Enter the code from your authenticator app or a recovery code");
+            setAlertVariant("info");
+            setShowAlert(true);
+            return;
+          }
+        }
         setAlertMessage("Login successful!");
         setAlertVariant("success");
         setShowAlert(true);
@@ -120,7 +135,7 @@ export default function AuthPage({ onLoginSuccess }) {
       }
     } catch (error) {
       // Extract error message from server if available
-      const errorMessage = error.response?.data?.message || 
+      const errorMessage = error.response?.data?.error?.message || 
                           error.message || 
                           "Authentication failed. Please try again.";
       
@@ -134,12 +149,14 @@ export default function AuthPage({ onLoginSuccess }) {
 
   const toggleAuthMode = () => {
     setIsLogin(!isLogin);
+    setNeedsCode(false);
     setFormData({
       username: "",
       name: "",
       surname: "",
       password: "",
       confirmPassword: "",
+      code: "",
     });
     setShowAlert(false);
   };
@@ -270,6 +287,25 @@ export default function AuthPage({ onLoginSuccess }) {
                     </InputGroup>
                   </Form.Group>
 
+                  {isLogin && needsCode && (
+                    <Form.Group className="mb-4">
+                      <Form.Label>
+                        <FaKey className="me-2" />
+                        Authentication code
+                      </Form.Label>
+                      <Form.Control
+                        type="text"
+                        name="code"
+                        inputMode="numeric"
+                        autoComplete="one-time-code"
+                        value={formData.code}
+                        onChange={handleInputChange}
+                        autoFocus
+                        required
+                      />
+                    </Form.Group>
+                  )}
+
                   {!isLogin && (
                     <Form.Group className="mb-4">
                       <Form.Label>Confirm Password</Form.Label>
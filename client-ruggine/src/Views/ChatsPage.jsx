@@ -6,6 +6,7 @@ import {
   sendMessage,
   inviteUsersToGroup,
   leaveGroup,
+  WSURL,
 } from "../API";
 import InviteModal from "../Components/InviteModal";
 import ChatSidebar from "../Components/ChatSidebar";
@@ -123,10 +124,15 @@ export default function ChatsPage() {
   useEffect(() => {
     // Open connection only if authenticated
     if (!username) return;
-    wsRef.current = new window.WebSocket("ws://localhost:3000/ws");
+    wsRef.current = new window.WebSocket(WSURL);
     wsRef.current.onmessage = (event) => {
       try {
         const data = JSON.parse(event.data);
+        // Control frames (sync_complete, resync_required) carry a type, chat messages don't
+        if (data.type) {
+          if (data.type === "resync_required") fetchChats();
+          return;
+        }
         const chatId = data.chat_id;
         // If it's an auto-generated message, update chats
         // All messages (including is_auto) increment the counter if the chat is not selected
@@ -180,6 +186,8 @@ export default function ChatsPage() {
       from_user: username,
       msg: newMessage,
       send_at: null,
+      // Lets the server recognise a retried send
+      client_id: window.crypto.randomUUID(),
     });
     setNewMessage("");
   };
@@ -251,7 +259,7 @@ export default function ChatsPage() {
       if (error.response?.status === 404) {
         errorMessage = "One or more users not found. Please check the usernames.";
       } else {
-        errorMessage = error.response?.data?.message ||
+        errorMessage = error.response?.data?.error?.message ||
                       error.message ||
                       "Error inviting users. Please try again.";
       }
@@ -272,7 +280,7 @@ export default function ChatsPage() {
       fetchChats();
     } catch (error) {
       setShowLeaveConfirm(false);
-      const errorMessage = error.response?.data?.message || 
+      const errorMessage = error.response?.data?.error?.message || 
                          error.message || 
                          "Error leaving group";
       alert(errorMessage);
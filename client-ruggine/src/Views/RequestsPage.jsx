@@ -24,7 +24,7 @@ export default function RequestsPage() {
             setRequests(response || []);
         } catch (error) {
             console.error('Error loading requests:', error);
-            const errorMessage = error.response?.data?.message || 
+            const errorMessage = error.response?.data?.error?.message || 
                                 error.message || 
                                 'Error loading requests. Please try again.';
             setAlertMessage(errorMessage);
@@ -57,7 +57,7 @@ export default function RequestsPage() {
             }, 800); // brief delay to show alert
         } catch (error) {
             console.error('Error accepting invitation:', error);
-            const errorMessage = error.response?.data?.message || 
+            const errorMessage = error.response?.data?.error?.message || 
                                 error.message || 
                                 'Error accepting invitation. Please try again.';
             setAlertMessage(errorMessage);
@@ -86,7 +86,7 @@ export default function RequestsPage() {
             }, 3000);
         } catch (error) {
             console.error('Error rejecting invitation:', error);
-            const errorMessage = error.response?.data?.message || 
+            const errorMessage = error.response?.data?.error?.message || 
                                 error.message || 
                                 'Error rejecting invitation. Please try again.';
             setAlertMessage(errorMessage);
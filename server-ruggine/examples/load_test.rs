@@ -0,0 +1,135 @@
+// Load benchmark for a running server.
+//
+//     cargo run --release --example load_test -- [base_url] [workers] [seconds]
+//
+// Registers `workers` users (all members of one group chat), then each of them repeatedly sends a
+// message and reads its chat list and pending requests for the given time. Prints the throughput
+// and the latency percentiles of the requests, split between reads and writes.
+//
+// The rate limits would throttle the workers: start the server with
+// `RUGGINE_RATE_REGISTER=off RUGGINE_RATE_LOGIN=off RUGGINE_RATE_MESSAGE=off RUGGINE_RATE_INVITE=off`.
+use reqwest::Client;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+const PASSWORD: &str = "Passw0rd!load";
+
+// Registers (if needed) and logs in a user, returning a client holding its session cookie
+async fn login(base: &str, username: &str) -> Client {
+    let client = Client::builder().cookie_store(true).build().unwrap();
+    let user = json!({ "username": username, "name": "Load", "surname": "Test", "password": PASSWORD });
+    let _ = client.post(format!("{}/users", base)).json(&user).send().await;
+    let res = client
+        .post(format!("{}/login", base))
+        .json(&json!({ "username": username, "password": PASSWORD }))
+        .send()
+        .await
+        .expect("login request failed");
+    assert!(res.status().is_success(), "login failed for {}: {}", username, res.status());
+    client
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn report(label: &str, mut latencies: Vec<Duration>, elapsed: Duration) {
+    latencies.sort();
+    println!(
+        "{:<6} {:>7} req  {:>9.1} req/s  p50 {:>8.2?}  p99 {:>8.2?}  max {:>8.2?}",
+        label,
+        latencies.len(),
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let base = args.get(1).cloned().unwrap_or_else(|| "http://localhost:3000".to_string());
+    let workers: usize = args.get(2).and_then(|v| v.parse().ok()).unwrap_or(32);
+    let seconds: u64 = args.get(3).and_then(|v| v.parse().ok()).unwrap_or(10);
+
+    // Setup: one user per worker, all in the same group
+    let run = std::process::id();
+    let usernames: Vec<String> = (0..workers).map(|i| format!("load{}_{}", run, i)).collect();
+    let mut clients = Vec::new();
+    for username in &usernames {
+        clients.push(login(&base, username).await);
+    }
+
+    let group: serde_json::Value = clients[0]
+        .post(format!("{}/groups", base))
+        .json(&json!({ "name": format!("load {}", run), "is_group": true, "participants": &usernames[1..] }))
+        .send()
+        .await
+        .expect("group creation failed")
+        .json()
+        .await
+        .expect("invalid group response");
+    let chat_id = group["chat_id"].as_i64().expect("missing chat_id");
+    for client in &clients[1..] {
+        client
+            .post(format!("{}/requests/{}/accept", base, chat_id))
+            .send()
+            .await
+            .expect("accept failed");
+    }
+
+    println!("{} workers for {}s against {}", workers, seconds, base);
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let started = Instant::now();
+
+    let tasks: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(worker, client)| {
+            let base = base.clone();
+            tokio::spawn(async move {
+                let (mut reads, mut writes, mut errors) = (Vec::new(), Vec::new(), 0usize);
+                let mut n = 0u64;
+                while Instant::now() < deadline {
+                    n += 1;
+                    let start = Instant::now();
+                    let res = client
+                        .post(format!("{}/chats/{}/messages", base, chat_id))
+                        .json(&json!({ "msg": format!("message {} from worker {}", n, worker) }))
+                        .send()
+                        .await;
+                    match res {
+                        Ok(res) if res.status().is_success() => writes.push(start.elapsed()),
+                        _ => errors += 1,
+                    }
+
+                    for path in ["chats", "requests", "chats"] {
+                        let start = Instant::now();
+                        match client.get(format!("{}/{}", base, path)).send().await {
+                            Ok(res) if res.status().is_success() => reads.push(start.elapsed()),
+                            _ => errors += 1,
+                        }
+                    }
+                }
+                (reads, writes, errors)
+            })
+        })
+        .collect();
+
+    let (mut reads, mut writes, mut errors) = (Vec::new(), Vec::new(), 0);
+    for task in tasks {
+        let (r, w, e) = task.await.unwrap();
+        reads.extend(r);
+        writes.extend(w);
+        errors += e;
+    }
+    let elapsed = started.elapsed();
+
+    report("reads", reads, elapsed);
+    report("writes", writes, elapsed);
+    println!("errors {}", errors);
+}
@@ -0,0 +1,41 @@
+-- Initial schema, equivalent to the SQLite one once all its migrations are applied.
+CREATE TABLE IF NOT EXISTS users (
+    username TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    surname TEXT NOT NULL,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS chats (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT, -- NULL for private chats
+    is_group BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS users_joined (
+    username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    PRIMARY KEY (username, chat_id)
+);
+
+CREATE TABLE IF NOT EXISTS messages (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    msg TEXT NOT NULL,
+    from_user TEXT REFERENCES users(username) ON DELETE SET NULL,
+    is_auto BOOLEAN NOT NULL DEFAULT FALSE,
+    send_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    client_id TEXT,
+    UNIQUE (from_user, client_id)
+);
+
+CREATE INDEX IF NOT EXISTS messages_chat_id ON messages (chat_id, id);
+
+CREATE TABLE IF NOT EXISTS requests (
+    id BIGSERIAL PRIMARY KEY,
+    from_user TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+    to_user TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    send_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,12 @@
+-- Audit of the sign-in attempts, also used to delay and lock out repeated failures.
+-- The username is stored as sent by the client: it may not exist.
+CREATE TABLE IF NOT EXISTS login_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL,
+    ip TEXT,
+    user_agent TEXT,
+    success BOOLEAN NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS login_attempts_username ON login_attempts (username, id);
@@ -0,0 +1,17 @@
+-- TOTP second factor. The secret is needed to compute the codes, so it is stored as is; `last_step` is the
+-- time step of the last code accepted, so that no code can be used twice. Unconfirmed rows are pending enrolments.
+CREATE TABLE IF NOT EXISTS two_factor (
+    username TEXT PRIMARY KEY REFERENCES users(username) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT FALSE,
+    last_step BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- One-time recovery codes, stored as SHA-256 hashes
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ,
+    PRIMARY KEY (username, code_hash)
+);
@@ -0,0 +1,23 @@
+-- System messages are stored as events (type and parameters, as JSON), rendered in the language of each reader.
+-- Their `msg` keeps the English text.
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS event TEXT;
+
+-- Language chosen by the user ('en', 'it'); NULL follows the Accept-Language header
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locale TEXT;
+
+-- Recover the events of the system messages stored as text
+UPDATE messages SET event = json_build_object('type', 'group_created', 'user', from_user)::text
+WHERE is_auto AND event IS NULL AND msg = from_user || ' created the group';
+
+UPDATE messages SET event = json_build_object('type', 'member_joined', 'user', from_user)::text
+WHERE is_auto AND event IS NULL AND msg = from_user || ' joined the group';
+
+UPDATE messages SET event = json_build_object('type', 'member_left', 'user', from_user)::text
+WHERE is_auto AND event IS NULL AND msg = from_user || ' has left the group';
+
+UPDATE messages SET event = json_build_object(
+    'type', 'private_chat_started',
+    'user', from_user,
+    'with', substr(msg, length(from_user || ' started a private chat with ') + 1)
+)::text
+WHERE is_auto AND event IS NULL AND starts_with(msg, from_user || ' started a private chat with ');
@@ -0,0 +1,21 @@
+-- Public profile of the users: display name (defaulting to name and surname), bio and joined date
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS bio TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ;
+
+-- Existing users joined at the latest with their first message, else now
+UPDATE users SET created_at = COALESCE(
+    (SELECT MIN(send_at) FROM messages WHERE from_user = users.username),
+    now()
+)
+WHERE created_at IS NULL;
+
+ALTER TABLE users ALTER COLUMN created_at SET DEFAULT now();
+ALTER TABLE users ALTER COLUMN created_at SET NOT NULL;
+
+-- Avatars, already resized and encoded as PNG. Kept apart so that reading users does not load them
+CREATE TABLE IF NOT EXISTS avatars (
+    username TEXT PRIMARY KEY REFERENCES users(username) ON DELETE CASCADE,
+    image BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
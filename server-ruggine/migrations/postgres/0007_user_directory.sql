@@ -0,0 +1,12 @@
+-- Users can keep out of the directory search, except for the users they already share a chat with
+ALTER TABLE users ADD COLUMN IF NOT EXISTS discoverable BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Users blocked by each user. Blocked users and their blockers do not find each other in the directory
+CREATE TABLE IF NOT EXISTS blocked_users (
+    blocker TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+    blocked TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (blocker, blocked)
+);
+
+CREATE INDEX IF NOT EXISTS blocked_users_blocked ON blocked_users (blocked);
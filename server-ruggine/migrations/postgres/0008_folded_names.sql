@@ -0,0 +1,4 @@
+-- Lowercase copies of name and surname matched by the directory search, set by the server so that
+-- both backends fold them in the same way. Existing users get them on the next start
+ALTER TABLE users ADD COLUMN IF NOT EXISTS folded_name TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS folded_surname TEXT;
@@ -0,0 +1,44 @@
+-- Initial schema. Tables are created only if missing, so existing databases are left untouched.
+CREATE TABLE IF NOT EXISTS USER (
+    username TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    surname TEXT NOT NULL,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS CHAT (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT, -- può essere NULL per chat private
+    isGroup BOOLEAN NOT NULL,
+    createdAt DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS USERS_JOINED (
+    username TEXT NOT NULL,
+    chatId INTEGER NOT NULL,
+    FOREIGN KEY (username) REFERENCES USER(username) ON DELETE CASCADE,
+    FOREIGN KEY (chatId) REFERENCES CHAT(ID) ON DELETE CASCADE,
+    PRIMARY KEY (username, chatId)
+);
+
+CREATE TABLE IF NOT EXISTS MESSAGE (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    chatID INTEGER NOT NULL,
+    msg TEXT NOT NULL,
+    fromUser TEXT NOT NULL,
+    isAuto BOOLEAN NOT NULL DEFAULT 0,
+    sendAt DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (chatID) REFERENCES CHAT(ID) ON DELETE CASCADE,
+    FOREIGN KEY (fromUser) REFERENCES USER(username) ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS REQUEST (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    fromUser TEXT NOT NULL,
+    toUser TEXT NOT NULL,
+    chatID INTEGER NOT NULL,
+    sendAt DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (fromUser) REFERENCES USER(username) ON DELETE CASCADE,
+    FOREIGN KEY (toUser) REFERENCES USER(username) ON DELETE CASCADE,
+    FOREIGN KEY (chatID) REFERENCES CHAT(ID) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- Client-generated id of a message, used to deduplicate retried sends.
+ALTER TABLE MESSAGE ADD COLUMN clientId TEXT;
+
+-- NULL ids (automatic messages, clients not sending one) never conflict
+CREATE UNIQUE INDEX IF NOT EXISTS MESSAGE_SENDER_CLIENT_ID ON MESSAGE (fromUser, clientId);
@@ -0,0 +1,13 @@
+-- All timestamps are stored in UTC, as 'YYYY-MM-DD HH:MM:SS' (the CURRENT_TIMESTAMP format).
+-- Chats used to be created with datetime('now', 'localtime'): convert them back to UTC.
+-- The 'utc' modifier uses the timezone of the host running the migration, which is the one they were written with.
+UPDATE CHAT SET createdAt = datetime(createdAt, 'utc') WHERE createdAt IS NOT NULL;
+
+-- Messages and requests were already written in UTC: only normalise their format
+UPDATE MESSAGE SET sendAt = datetime(sendAt) WHERE sendAt IS NOT NULL;
+UPDATE REQUEST SET sendAt = datetime(sendAt) WHERE sendAt IS NOT NULL;
+
+-- Rows missing a timestamp get the migration time
+UPDATE CHAT SET createdAt = CURRENT_TIMESTAMP WHERE createdAt IS NULL;
+UPDATE MESSAGE SET sendAt = CURRENT_TIMESTAMP WHERE sendAt IS NULL;
+UPDATE REQUEST SET sendAt = CURRENT_TIMESTAMP WHERE sendAt IS NULL;
@@ -0,0 +1,12 @@
+-- Audit of the sign-in attempts, also used to delay and lock out repeated failures.
+-- The username is stored as sent by the client: it may not exist.
+CREATE TABLE IF NOT EXISTS LOGIN_ATTEMPT (
+    ID INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    ip TEXT,
+    userAgent TEXT,
+    success BOOLEAN NOT NULL,
+    attemptedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS LOGIN_ATTEMPT_USERNAME ON LOGIN_ATTEMPT (username, ID);
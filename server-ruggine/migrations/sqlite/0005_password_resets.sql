@@ -0,0 +1,8 @@
+-- Pending password resets. Only the SHA-256 hash of each token is stored: the token itself is sent to the user.
+CREATE TABLE IF NOT EXISTS PASSWORD_RESET (
+    tokenHash TEXT PRIMARY KEY,
+    username TEXT NOT NULL,
+    expiresAt DATETIME NOT NULL,
+    createdAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (username) REFERENCES USER(username) ON DELETE CASCADE
+);
@@ -0,0 +1,19 @@
+-- TOTP second factor. The secret is needed to compute the codes, so it is stored as is; `lastStep` is the
+-- time step of the last code accepted, so that no code can be used twice. Unconfirmed rows are pending enrolments.
+CREATE TABLE IF NOT EXISTS TWO_FACTOR (
+    username TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT 0,
+    lastStep INTEGER NOT NULL DEFAULT 0,
+    createdAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (username) REFERENCES USER(username) ON DELETE CASCADE
+);
+
+-- One-time recovery codes, stored as SHA-256 hashes
+CREATE TABLE IF NOT EXISTS RECOVERY_CODE (
+    username TEXT NOT NULL,
+    codeHash TEXT NOT NULL,
+    usedAt DATETIME,
+    PRIMARY KEY (username, codeHash),
+    FOREIGN KEY (username) REFERENCES USER(username) ON DELETE CASCADE
+);
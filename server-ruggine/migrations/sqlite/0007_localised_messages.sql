@@ -0,0 +1,23 @@
+-- System messages are stored as events (type and parameters, as JSON), rendered in the language of each reader.
+-- Their `msg` keeps the English text.
+ALTER TABLE MESSAGE ADD COLUMN event TEXT;
+
+-- Language chosen by the user ('en', 'it'); NULL follows the Accept-Language header
+ALTER TABLE USER ADD COLUMN locale TEXT;
+
+-- Recover the events of the system messages stored as text
+UPDATE MESSAGE SET event = json_object('type', 'group_created', 'user', fromUser)
+WHERE isAuto AND event IS NULL AND msg = fromUser || ' created the group';
+
+UPDATE MESSAGE SET event = json_object('type', 'member_joined', 'user', fromUser)
+WHERE isAuto AND event IS NULL AND msg = fromUser || ' joined the group';
+
+UPDATE MESSAGE SET event = json_object('type', 'member_left', 'user', fromUser)
+WHERE isAuto AND event IS NULL AND msg = fromUser || ' has left the group';
+
+UPDATE MESSAGE SET event = json_object(
+    'type', 'private_chat_started',
+    'user', fromUser,
+    'with', substr(msg, length(fromUser || ' started a private chat with ') + 1)
+)
+WHERE isAuto AND event IS NULL AND substr(msg, 1, length(fromUser || ' started a private chat with ')) = fromUser || ' started a private chat with ';
@@ -0,0 +1,19 @@
+-- Public profile of the users: display name (defaulting to name and surname), bio and joined date
+ALTER TABLE USER ADD COLUMN displayName TEXT;
+ALTER TABLE USER ADD COLUMN bio TEXT;
+ALTER TABLE USER ADD COLUMN createdAt DATETIME;
+
+-- Existing users joined at the latest with their first message, else now
+UPDATE USER SET createdAt = COALESCE(
+    (SELECT MIN(sendAt) FROM MESSAGE WHERE fromUser = USER.username),
+    CURRENT_TIMESTAMP
+)
+WHERE createdAt IS NULL;
+
+-- Avatars, already resized and encoded as PNG. Kept apart so that reading users does not load them
+CREATE TABLE IF NOT EXISTS AVATAR (
+    username TEXT PRIMARY KEY,
+    image BLOB NOT NULL,
+    updatedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (username) REFERENCES USER(username) ON DELETE CASCADE
+);
@@ -0,0 +1,14 @@
+-- Users can keep out of the directory search, except for the users they already share a chat with
+ALTER TABLE USER ADD COLUMN discoverable BOOLEAN NOT NULL DEFAULT 1;
+
+-- Users blocked by each user. Blocked users and their blockers do not find each other in the directory
+CREATE TABLE IF NOT EXISTS BLOCKED_USER (
+    blocker TEXT NOT NULL,
+    blocked TEXT NOT NULL,
+    createdAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (blocker, blocked),
+    FOREIGN KEY (blocker) REFERENCES USER(username) ON DELETE CASCADE,
+    FOREIGN KEY (blocked) REFERENCES USER(username) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS BLOCKED_USER_BLOCKED ON BLOCKED_USER (blocked);
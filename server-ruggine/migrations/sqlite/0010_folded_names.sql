@@ -0,0 +1,5 @@
+-- Lowercase copies of name and surname matched by the directory search, set by the server so that
+-- they fold non-ASCII letters as PostgreSQL does (SQLite's `lower` and `LIKE` only fold ASCII).
+-- Existing users get them on the next start
+ALTER TABLE USER ADD COLUMN foldedName TEXT;
+ALTER TABLE USER ADD COLUMN foldedSurname TEXT;
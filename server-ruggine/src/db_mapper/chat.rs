@@ -1,6 +1,13 @@
+use axum::async_trait;
 use serde::Serialize;
-use sqlx::{FromRow, SqlitePool};
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use crate::db_mapper::Repositories;
+use crate::db_mapper::message::{Message, SystemEvent};
 use crate::utilities::error::MyError;
+use crate::utilities::metrics::time_db;
+use crate::events::Event;
+use crate::state::EventBus;
 use crate::route_handlers::user_handler::AuthUser;
 use std::collections::HashSet;
 
@@ -8,8 +15,10 @@ use std::collections::HashSet;
 pub struct ChatRaw {
     pub id: i64,
     pub name: Option<String>,
-    pub isGroup: bool,
-    pub createdAt: String,
+    #[sqlx(rename = "isGroup")]
+    pub is_group: bool,
+    #[sqlx(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
     pub participants: String,
 }
 
@@ -18,35 +27,18 @@ pub struct Chat {
     pub id: i64,
     pub name: Option<String>,
     pub is_group: bool,
-    pub created_at: String,
+    // Creation time in UTC (serialized as RFC 3339)
+    pub created_at: DateTime<Utc>,
     pub participants: Vec<String>,
 }
 
-pub async fn get_user_chats(pool: &SqlitePool, user: AuthUser) -> Result<Vec<Chat>, MyError> {
-    let username = user.0;
-    let raw_chats = sqlx::query_as::<_, ChatRaw>(
-        "SELECT c.id AS id, c.name, c.isGroup, c.createdAt, GROUP_CONCAT(u.username, ',') AS participants,
-                MAX(m.sendAt) as last_message_at
-         FROM CHAT c
-         JOIN USERS_JOINED uj ON uj.chatId = c.id
-         JOIN USER u ON u.username = uj.username
-         LEFT JOIN MESSAGE m ON m.chatID = c.id
-         WHERE c.id IN (
-             SELECT chatId FROM USERS_JOINED WHERE username = ?
-         )
-         GROUP BY c.id, c.name, c.isGroup, c.createdAt
-         ORDER BY COALESCE(last_message_at, c.createdAt) DESC;"
-    )
-        .bind(&username)
-        .fetch_all(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    let chats = raw_chats.into_iter().map(|raw| {
+impl Chat {
+    // Builds a chat from its raw row, listing the participants other than `username`
+    pub fn from_raw(raw: ChatRaw, username: &str) -> Self {
         let participants: Vec<String> = raw.participants
             .split(',')
             .map(|s| s.trim().to_string())
-            .filter(|p| p != &username)
+            .filter(|p| p != username)
             .collect::<HashSet<_>>() // remove duplicates
             .into_iter()
             .collect();
@@ -54,16 +46,52 @@ pub async fn get_user_chats(pool: &SqlitePool, user: AuthUser) -> Result<Vec<Cha
         Chat {
             id: raw.id,
             name: raw.name,
-            is_group: raw.isGroup,
-            created_at: raw.createdAt,
+            is_group: raw.is_group,
+            created_at: raw.created_at,
             participants,
         }
-    }).collect();
+    }
+}
 
-    Ok(chats)
+#[async_trait]
+pub trait ChatRepository: Send + Sync {
+    // Returns the chats the user is a member of, most recently active first
+    async fn user_chats(&self, username: &str) -> Result<Vec<Chat>, MyError>;
+
+    // Creates a chat with the creator as only member, invites the others and stores the creation message
+    async fn create_group(
+        &self,
+        name: Option<&str>,
+        is_group: bool,
+        creator: &str,
+        invitees: &[String],
+        creation: &SystemEvent,
+    ) -> Result<(i64, Message), MyError>;
+
+    // Returns the private chat between the two users, creating it (with its creation message) if needed.
+    // The message is None when the chat already existed.
+    async fn create_private_chat(
+        &self,
+        creator: &str,
+        other_username: &str,
+        creation: &SystemEvent,
+    ) -> Result<(i64, Option<Message>), MyError>;
+
+    // Removes the user from a group, storing the leave message and deleting the group once empty.
+    // Returns the message and whether the group still exists.
+    async fn leave_group(&self, chat_id: i64, username: &str, leave: &SystemEvent) -> Result<(Message, bool), MyError>;
+
+    // Returns the usernames of the members of a chat
+    async fn participants(&self, chat_id: i64) -> Result<Vec<String>, MyError>;
 }
+
+pub async fn get_user_chats(db: &Repositories, user: AuthUser) -> Result<Vec<Chat>, MyError> {
+    time_db("chat.user_chats", db.chats.user_chats(&user.0)).await
+}
+
 pub async fn create_group(
-    pool: &SqlitePool,
+    db: &Repositories,
+    events: &EventBus,
     name: Option<String>,
     is_group: bool,
     creator: AuthUser,
@@ -71,186 +99,53 @@ pub async fn create_group(
 ) -> Result<i64, MyError> {
     let creator_username = creator.0;
 
-    // Insert new chat
-    let chat_id = sqlx::query(
-        "INSERT INTO CHAT (name, isGroup, createdAt) VALUES (?, ?, datetime('now', 'localtime'))"
-    )
-        .bind(&name)
-        .bind(is_group)
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?
-        .last_insert_rowid();
-
-    // Insert only the creator into USERS_JOINED
-    sqlx::query(
-        "INSERT INTO USERS_JOINED (chatId, username) VALUES (?, ?)"
-    )
-        .bind(chat_id)
-        .bind(&creator_username)
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    // For each participant, check if they exist and are not the creator, then insert them
+    // Invite every participant other than the creator
     let to_list: Vec<String> = participants.into_iter().filter(|u| u != &creator_username).collect();
-    if !to_list.is_empty() {
-        crate::db_mapper::request::insert_requests_per_user(pool, chat_id, AuthUser(creator_username.clone()), &to_list).await?;
-    }
 
     // Automatic message indicating group creation
-    let msg = format!("{} created the group", &creator_username);
-    crate::db_mapper::message::insert_message(pool, chat_id, &creator_username, &msg, true).await?;
+    let creation = SystemEvent::GroupCreated { user: creator_username.clone() };
+    let (chat_id, message) = time_db(
+        "chat.create_group",
+        db.chats.create_group(name.as_deref(), is_group, &creator_username, &to_list, &creation),
+    ).await?;
+
+    events.publish(Event::MessageCreated(message)).await;
 
     Ok(chat_id)
 }
 
 pub async fn create_private_chat(
-    pool: &SqlitePool,
+    db: &Repositories,
+    events: &EventBus,
     creator: AuthUser,
     other_username: String,
 ) -> Result<(i64, bool), MyError> {
-    // Verify other user exists
-    let other_exists = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM USER WHERE username = ?"
-    )
-        .bind(&other_username)
-        .fetch_one(pool)
-        .await
-        .map_err(MyError::from)? > 0;
-
-    if !other_exists {
-        return Err(MyError::UserNotFound);
-    }
-
-    // See if a private chat between these two users already exists
-    let existing_chat_id = sqlx::query_scalar::<_, Option<i64>>(
-        r#"
-        SELECT c.id
-        FROM CHAT c
-        JOIN USERS_JOINED uj1 ON uj1.chatId = c.id AND uj1.username = ?
-        JOIN USERS_JOINED uj2 ON uj2.chatId = c.id AND uj2.username = ?
-        WHERE c.isGroup = 0
-        "#,
-    )
-        .bind(&creator.0)
-        .bind(&other_username)
-        .fetch_optional(pool)
-        .await
-        .map_err(MyError::from)?;
+    let creation = SystemEvent::PrivateChatStarted { user: creator.0.clone(), with: other_username.clone() };
+    let (chat_id, message) = time_db("chat.create_private_chat", db.chats.create_private_chat(&creator.0, &other_username, &creation)).await?;
 
-    if let Some(Some(chat_id)) = existing_chat_id {
-        return Ok((chat_id, true)); // <-- già esiste
+    match message {
+        Some(message) => {
+            events.publish(Event::MessageCreated(message)).await;
+            Ok((chat_id, false)) // just created
+        }
+        None => Ok((chat_id, true)), // already existed
     }
-
-    // Create new private chat
-    let chat_id = sqlx::query(
-        "INSERT INTO CHAT (isGroup, createdAt) VALUES (0, datetime('now', 'localtime'))"
-    )
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?
-        .last_insert_rowid();
-
-    // Insert new chat participants
-    sqlx::query(
-        "INSERT INTO USERS_JOINED (chatId, username) VALUES (?, ?), (?, ?)"
-    )
-        .bind(chat_id)
-        .bind(&creator.0)
-        .bind(chat_id)
-        .bind(&other_username)
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    // Insert automatic message
-    let msg = format!("{} started a private chat with {}", &creator.0, &other_username);
-    crate::db_mapper::message::insert_message(pool, chat_id, &creator.0, &msg, true).await?;
-
-    Ok((chat_id, false)) // <-- appena creata
 }
 
 pub async fn leave_group(
-    pool: &SqlitePool,
+    db: &Repositories,
+    events: &EventBus,
     chat_id: i64,
     user: AuthUser,
 ) -> Result<(), MyError> {
     let username = &user.0;
+    let leave = SystemEvent::MemberLeft { user: username.clone() };
+    let (message, still_exists) = time_db("chat.leave_group", db.chats.leave_group(chat_id, username, &leave)).await?;
 
-    // Verify the chat exists and is a group
-    let is_group = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM CHAT WHERE id = ? AND isGroup = 1",
-    )
-    .bind(chat_id)
-    .fetch_one(pool)
-    .await
-    .map_err(MyError::from)?;
-
-    if is_group == 0 {
-        return Err(MyError::ChatNotFound);
-    }
-
-    // Verify user is in the group
-    let is_member = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM USERS_JOINED WHERE username = ? AND chatId = ?",
-    )
-    .bind(username)
-    .bind(chat_id)
-    .fetch_one(pool)
-    .await
-    .map_err(MyError::from)?;
-
-    if is_member == 0 {
-        return Err(MyError::UserDoesNotBelongToGroup);
-    }
-
-    // Insert leaving message
-    let msg = format!("{} has left the group", username);
-    crate::db_mapper::message::insert_message(pool, chat_id, username, &msg, true).await?;
-
-    // Remove user from a group
-    sqlx::query(
-        "DELETE FROM USERS_JOINED WHERE username = ? AND chatId = ?",
-    )
-        .bind(username)
-        .bind(chat_id)
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    // Check if only one person remains in the group
-    let remaining_members = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM USERS_JOINED WHERE chatId = ?",
-    )
-    .bind(chat_id)
-    .fetch_one(pool)
-    .await
-    .map_err(MyError::from)?;
-
-    // If only one member remains, delete the group
-    if remaining_members <= 0 {
-        // Delete all messages from the chat
-        sqlx::query("DELETE FROM MESSAGE WHERE chatId = ?")
-            .bind(chat_id)
-            .execute(pool)
-            .await
-            .map_err(MyError::from)?;
-
-        // Delete remaining user from USERS_JOINED
-        sqlx::query("DELETE FROM USERS_JOINED WHERE chatId = ?")
-            .bind(chat_id)
-            .execute(pool)
-            .await
-            .map_err(MyError::from)?;
-
-        // Delete the chat itself
-        sqlx::query("DELETE FROM CHAT WHERE id = ?")
-            .bind(chat_id)
-            .execute(pool)
-            .await
-            .map_err(MyError::from)?;
+    // Notify the remaining members, if the group still exists
+    if still_exists {
+        events.publish(Event::MessageCreated(message)).await;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
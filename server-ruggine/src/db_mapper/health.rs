@@ -0,0 +1,25 @@
+use axum::async_trait;
+use sqlx::migrate::Migrator;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+pub trait HealthRepository: Send + Sync {
+    // Runs a trivial query, failing if the database cannot be reached
+    async fn ping(&self) -> Result<(), MyError>;
+
+    // Returns the versions of the migrations embedded in the server that have not been applied
+    async fn pending_migrations(&self) -> Result<Vec<i64>, MyError>;
+
+    // Waits for the connections in use to be returned, then closes the pools
+    async fn close(&self);
+}
+
+// Versions of the migrations of `migrator` missing from `applied`
+pub fn pending_versions(migrator: &Migrator, applied: &[i64]) -> Vec<i64> {
+    migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect()
+}
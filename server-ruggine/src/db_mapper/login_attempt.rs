@@ -0,0 +1,123 @@
+use axum::async_trait;
+use std::time::Duration;
+use serde::Serialize;
+use chrono::{DateTime, Utc};
+use crate::db_mapper::Repositories;
+use crate::db_mapper::two_factor;
+use crate::db_mapper::user::verify_user;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+use crate::utilities::metrics::time_db;
+use crate::utilities::rate_limit::retry_after_secs;
+
+// A sign-in attempt, as shown in the user's history
+#[derive(Debug, Serialize)]
+pub struct LoginAttempt {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    // In UTC (serialized as RFC 3339)
+    pub attempted_at: DateTime<Utc>,
+}
+
+// An attempt to be recorded
+pub struct NewLoginAttempt<'a> {
+    pub username: &'a str,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub success: bool,
+}
+
+// Failed attempts since the last successful one
+#[derive(Debug, Default)]
+pub struct FailureStreak {
+    pub failures: i64,
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait LoginAttemptRepository: Send + Sync {
+    async fn insert_attempt(&self, attempt: &NewLoginAttempt<'_>) -> Result<(), MyError>;
+
+    // Failed attempts on the username since its last successful one, ignoring those older than `window`
+    async fn failure_streak(&self, username: &str, window: Duration) -> Result<FailureStreak, MyError>;
+
+    // The most recent attempts on the username, newest first
+    async fn user_attempts(&self, username: &str, limit: i64) -> Result<Vec<LoginAttempt>, MyError>;
+}
+
+// Outcome of a sign-in with username and password
+#[derive(Debug, PartialEq, Eq)]
+pub enum Authentication {
+    Failed,
+    Authenticated,
+    // The password is right, but the user has 2FA enabled
+    SecondFactorRequired,
+}
+
+// Verifies the credentials and records the attempt.
+// After a failure the next attempt is refused for a delay doubling at each failure; once the failures reach
+// the lockout threshold, the account is locked for the lockout duration. Unknown usernames get the same
+// treatment (and the same timing), so the responses do not reveal whether a user exists.
+// With 2FA enabled the attempt is recorded only after the second factor, so that guessing codes counts
+// towards the lockout and a right password does not reset the failure streak
+pub async fn authenticate(
+    db: &Repositories,
+    config: &Config,
+    username: &str,
+    password: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<Authentication, MyError> {
+    check_lockout(db, config, username).await?;
+
+    let success = verify_user(db, config, username, password).await?;
+    if success && two_factor::is_enabled(db, username).await? {
+        return Ok(Authentication::SecondFactorRequired);
+    }
+    let attempt = NewLoginAttempt { username, ip, user_agent, success };
+    time_db("login_attempt.insert", db.logins.insert_attempt(&attempt)).await?;
+    Ok(if success { Authentication::Authenticated } else { Authentication::Failed })
+}
+
+// Second step of the sign-in of a user with 2FA enabled: verifies the code and records the attempt
+pub async fn authenticate_second_factor(
+    db: &Repositories,
+    config: &Config,
+    username: &str,
+    code: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<bool, MyError> {
+    check_lockout(db, config, username).await?;
+
+    let success = two_factor::verify_code(db, username, code).await?;
+    let attempt = NewLoginAttempt { username, ip, user_agent, success };
+    time_db("login_attempt.insert", db.logins.insert_attempt(&attempt)).await?;
+    Ok(success)
+}
+
+// Refuses the attempt while the delay after the last failure, or the lockout, has not elapsed
+async fn check_lockout(db: &Repositories, config: &Config, username: &str) -> Result<(), MyError> {
+    let streak = time_db("login_attempt.failure_streak", db.logins.failure_streak(username, config.login_lockout_duration)).await?;
+    if let Some(last_failure) = streak.last_failure {
+        let locked = streak.failures >= i64::from(config.login_lockout_threshold);
+        let wait = if locked { config.login_lockout_duration } else { failure_delay(config.login_delay, streak.failures) };
+        let elapsed = (Utc::now() - last_failure).to_std().unwrap_or_default();
+        if let Some(remaining) = wait.checked_sub(elapsed).filter(|remaining| !remaining.is_zero()) {
+            let secs = retry_after_secs(remaining);
+            return Err(if locked { MyError::AccountLocked(secs) } else { MyError::TooManyRequests(secs) });
+        }
+    }
+    Ok(())
+}
+
+// Delay imposed after `failures` consecutive failures: `base`, doubled at each further failure
+fn failure_delay(base: Duration, failures: i64) -> Duration {
+    let doublings = failures.saturating_sub(1).clamp(0, 16) as u32;
+    base.saturating_mul(1 << doublings)
+}
+
+pub async fn user_attempts(db: &Repositories, username: &str, limit: i64) -> Result<Vec<LoginAttempt>, MyError> {
+    time_db("login_attempt.user_attempts", db.logins.user_attempts(username, limit)).await
+}
@@ -1,139 +1,163 @@
-use serde::{Serialize};
-use sqlx::{Row, SqlitePool};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use crate::db_mapper::Repositories;
 use crate::utilities::error::MyError;
+use crate::utilities::i18n::{self, Locale, Text};
+use crate::utilities::metrics::time_db;
+use crate::events::Event;
+use crate::state::EventBus;
 
-#[derive(Debug, Serialize, Clone)]
+// What a system message reports, stored as its type and parameters and put into words for each reader
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SystemEvent {
+    GroupCreated { user: String },
+    PrivateChatStarted { user: String, with: String },
+    MemberJoined { user: String },
+    MemberLeft { user: String },
+}
+
+impl SystemEvent {
+    pub fn render(&self, locale: Locale) -> String {
+        let text = match self {
+            SystemEvent::GroupCreated { user } => Text::GroupCreated { user },
+            SystemEvent::PrivateChatStarted { user, with } => Text::PrivateChatStarted { user, with },
+            SystemEvent::MemberJoined { user } => Text::MemberJoined { user },
+            SystemEvent::MemberLeft { user } => Text::MemberLeft { user },
+        };
+        i18n::translate(locale, &text)
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub struct Message {
     pub id: i64,
     pub chat_id: i64,
+    // For system messages, the event rendered in the reader's language (stored in English)
     pub msg: String,
     pub from_user: String,
     pub is_auto: bool,
-    pub send_at: String,
+    // Event reported by a system message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<SystemEvent>,
+    // Server time the message was stored at, in UTC (serialized as RFC 3339)
+    pub send_at: DateTime<Utc>,
+    // Id chosen by the sending client to deduplicate retries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
 }
 
-pub async fn insert_message(
-    pool: &SqlitePool,
-    chat_id: i64,
-    username: &str,
-    msg: &str,
-    is_auto: bool,
-) -> Result<(), MyError> {
-    // Verify that the user is a member of the chat
-    let is_member = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM USERS_JOINED WHERE chatId = ? AND username = ?"
-    )
-        .bind(chat_id)
-        .bind(username)
-        .fetch_one(pool)
-        .await
-        .map_err(MyError::from)?;
+// Message contents never end up in the logs: only their length does
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("id", &self.id)
+            .field("chat_id", &self.chat_id)
+            .field("msg_len", &self.msg.len())
+            .field("from_user", &self.from_user)
+            .field("is_auto", &self.is_auto)
+            .field("event", &self.event)
+            .field("send_at", &self.send_at)
+            .field("client_id", &self.client_id)
+            .finish()
+    }
+}
 
-    if is_member == 0 {
-        return Err(MyError::UserDoesNotBelongToGroup);
+impl Message {
+    // Renders the text of a system message in the given language. User messages are left as they are
+    pub fn localize(&mut self, locale: Locale) {
+        if let Some(event) = &self.event {
+            self.msg = event.render(locale);
+        }
     }
 
-    // Insert message
-    sqlx::query(
-        "INSERT INTO MESSAGE (chatID, msg, fromUser, isAuto) VALUES (?, ?, ?, ?)"
-    )
-        .bind(chat_id)
-        .bind(msg)
-        .bind(username)
-        .bind(is_auto)
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?;
+    // Checks that a send reusing the client id of this stored message is a retry of it:
+    // the same id with another chat or text is a client error, not a message to return
+    pub fn retried_as(self, chat_id: i64, msg: &str) -> Result<Message, MyError> {
+        if self.chat_id == chat_id && self.msg == msg {
+            Ok(self)
+        } else {
+            Err(MyError::DuplicateMessage)
+        }
+    }
+}
 
-    // Automatic broadcast of the message to all chat participants
-    // retrieve the last inserted message
-    let row = sqlx::query(
-        r#"
-        SELECT id, chatID, msg, fromUser, isAuto, datetime(sendAt, '+2 hours') as sendAt
-        FROM MESSAGE
-        WHERE chatID = ? AND fromUser = ? AND msg = ?
-        ORDER BY id DESC LIMIT 1
-        "#
-    )
-        .bind(chat_id)
-        .bind(username)
-        .bind(msg)
-        .fetch_one(pool)
-        .await
-        .map_err(MyError::from)?;
+#[async_trait]
+pub trait MessageRepository: Send + Sync {
+    // Stores a message sent by a member of the chat and returns it, together with whether it was
+    // inserted or already stored by the same sender with the same `client_id`.
+    // Reusing a `client_id` for another chat or text fails with `DuplicateMessage`.
+    // System messages are stored by the operations they report
+    async fn insert_message(
+        &self,
+        chat_id: i64,
+        username: &str,
+        msg: &str,
+        client_id: Option<&str>,
+    ) -> Result<(Message, bool), MyError>;
 
-    let message = crate::db_mapper::message::Message {
-        id: row.try_get("id").unwrap_or_default(),
-        chat_id: row.try_get("chatID").unwrap_or_default(),
-        msg: row.try_get("msg").unwrap_or_default(),
-        from_user: row.try_get("fromUser").unwrap_or_default(),
-        is_auto: row.try_get("isAuto").unwrap_or_default(),
-        send_at: row.try_get("sendAt").unwrap_or_default(),
-    };
+    // Returns all the messages of a chat the user is a member of, oldest first
+    async fn chat_messages(&self, chat_id: i64, username: &str) -> Result<Vec<Message>, MyError>;
 
-    // Serialize and send via WebSocket
-    if let Ok(json) = serde_json::to_string(&message) {
-        // Retrieve all usernames of participants in the chat
-        let rows = sqlx::query_scalar::<_, String>(
-            "SELECT GROUP_CONCAT(username, ',') FROM USERS_JOINED WHERE chatId = ?"
-        )
-            .bind(chat_id)
-            .fetch_one(pool)
-            .await
-            .unwrap_or_default();
-        let participants: Vec<String> = rows.split(',').map(|s| s.trim().to_string()).collect();
+    // Returns at most `limit` messages of the user's chats newer than the given cutoffs, oldest first
+    async fn messages_since(
+        &self,
+        username: &str,
+        last_id: i64,
+        chats: &HashMap<i64, i64>,
+        limit: i64,
+    ) -> Result<Vec<Message>, MyError>;
+}
 
-        // Send the message to all participants connected and joined the chat
-        let user_sockets = crate::route_handlers::ws_handler::USER_SOCKETS.clone();
-        let map = user_sockets.lock().await;
-        for user in participants {
-            if let Some(senders) = map.get(&user) {
-                for sender in senders {
-                    let _ = sender.send(json.clone());
-                }
-            }
-        }
+// Stores and publishes a message, returning it.
+// When `client_id` is given and the sender already stored a message with the same id (a retried send),
+// the stored message is returned and nothing is inserted nor published again. A `client_id` reused
+// for a different message fails with `DuplicateMessage` (409).
+pub async fn insert_message(
+    db: &Repositories,
+    events: &EventBus,
+    chat_id: i64,
+    username: &str,
+    msg: &str,
+    client_id: Option<&str>,
+) -> Result<Message, MyError> {
+    let (message, created) = time_db("message.insert", db.messages.insert_message(chat_id, username, msg, client_id)).await?;
+
+    // Publish only once the message is durably stored, and only the first time
+    if created {
+        events.publish(Event::MessageCreated(message.clone())).await;
     }
 
-    Ok(())
+    Ok(message)
 }
 
 pub async fn get_messages_for_chat(
-    pool: &SqlitePool,
+    db: &Repositories,
     chat_id: i64,
     username: &str,
+    locale: Locale,
 ) -> Result<Vec<Message>, MyError> {
-    // Verify that the user is a member of the chat
-    let is_member = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM USERS_JOINED WHERE chatId = ? AND username = ?"
-    )
-        .bind(chat_id)
-        .bind(username)
-        .fetch_one(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    if is_member == 0 {
-        return Err(MyError::UserDoesNotBelongToGroup);
-    }
-
-    // Retrieve messages
-    let rows = sqlx::query(
-        "SELECT id, chatID, msg, fromUser, isAuto, datetime(sendAt, '+2 hours') as sendAt FROM MESSAGE WHERE chatID = ? ORDER BY id ASC"
-    )
-        .bind(chat_id)
-        .fetch_all(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    let messages = rows.into_iter().map(|row| Message {
-        id: row.try_get("id").unwrap_or_default(),
-        chat_id: row.try_get("chatID").unwrap_or_default(),
-        msg: row.try_get("msg").unwrap_or_default(),
-        from_user: row.try_get("fromUser").unwrap_or_default(),
-        is_auto: row.try_get("isAuto").unwrap_or_default(),
-        send_at: row.try_get("sendAt").unwrap_or_default(),
-    }).collect();
+    let mut messages = time_db("message.chat_messages", db.messages.chat_messages(chat_id, username)).await?;
+    messages.iter_mut().for_each(|message| message.localize(locale));
+    Ok(messages)
+}
 
+// Retrieves the messages the user missed across all their chats, in order.
+// A message is missed when its id is greater than the last id the client saw in that chat
+// (`chats`), or than the global `last_id` for the chats not listed.
+// At most `limit` + 1 messages are returned, so the caller can tell when there are too many to replay.
+pub async fn get_messages_since(
+    db: &Repositories,
+    username: &str,
+    last_id: i64,
+    chats: &HashMap<i64, i64>,
+    limit: i64,
+    locale: Locale,
+) -> Result<Vec<Message>, MyError> {
+    let mut messages = time_db("message.messages_since", db.messages.messages_since(username, last_id, chats, limit + 1)).await?;
+    messages.iter_mut().for_each(|message| message.localize(locale));
     Ok(messages)
-}
\ No newline at end of file
+}
@@ -1,4 +1,70 @@
 pub mod user;
 pub mod message;
 pub mod chat;
-pub mod request;
\ No newline at end of file
+pub mod request;
+pub mod health;
+pub mod login_attempt;
+pub mod password_reset;
+pub mod two_factor;
+pub mod sqlite;
+pub mod postgres;
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+use std::sync::Arc;
+use crate::db_mapper::chat::ChatRepository;
+use crate::db_mapper::health::HealthRepository;
+use crate::db_mapper::login_attempt::LoginAttemptRepository;
+use crate::db_mapper::password_reset::PasswordResetRepository;
+use crate::db_mapper::two_factor::TwoFactorRepository;
+use crate::db_mapper::message::MessageRepository;
+use crate::db_mapper::request::RequestRepository;
+use crate::db_mapper::user::UserRepository;
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::utilities::config::Config;
+
+// The storage used by the application, one repository per entity
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepository>,
+    pub chats: Arc<dyn ChatRepository>,
+    pub messages: Arc<dyn MessageRepository>,
+    pub requests: Arc<dyn RequestRepository>,
+    pub logins: Arc<dyn LoginAttemptRepository>,
+    pub password_resets: Arc<dyn PasswordResetRepository>,
+    pub two_factor: Arc<dyn TwoFactorRepository>,
+    pub health: Arc<dyn HealthRepository>,
+}
+
+impl Repositories {
+    fn from_backend<R>(backend: R) -> Self
+    where
+        R: UserRepository + ChatRepository + MessageRepository + RequestRepository + LoginAttemptRepository
+            + PasswordResetRepository + TwoFactorRepository + HealthRepository + 'static,
+    {
+        let backend = Arc::new(backend);
+        Repositories {
+            users: backend.clone(),
+            chats: backend.clone(),
+            messages: backend.clone(),
+            requests: backend.clone(),
+            logins: backend.clone(),
+            password_resets: backend.clone(),
+            two_factor: backend.clone(),
+            health: backend,
+        }
+    }
+}
+
+// Connects to the configured database and runs its migrations.
+// `postgres://` (or `postgresql://`) urls select PostgreSQL, anything else SQLite.
+pub async fn connect(config: &Config) -> Result<Repositories, sqlx::Error> {
+    let url = &config.database_url;
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        Ok(Repositories::from_backend(PostgresRepository::connect(url, config).await?))
+    } else {
+        Ok(Repositories::from_backend(SqliteRepository::connect(url, config).await?))
+    }
+}
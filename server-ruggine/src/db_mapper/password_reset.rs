@@ -0,0 +1,86 @@
+use axum::async_trait;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::db_mapper::Repositories;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+use crate::utilities::metrics::time_db;
+use crate::utilities::notifier::{Notification, Notifier};
+use crate::utilities::password_policy::check_password;
+use crate::utilities::utils::hash_password;
+use crate::utilities::validation::{normalize_text, Normalize};
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct PasswordResetRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub username: String,
+}
+
+impl Normalize for PasswordResetRequest {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.username);
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PasswordResetConfirm {
+    #[validate(length(equal = 64))]
+    pub token: String,
+    pub new_password: String,
+}
+
+impl Normalize for PasswordResetConfirm {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.token);
+    }
+}
+
+#[async_trait]
+pub trait PasswordResetRepository: Send + Sync {
+    // Stores the hash of a new reset token valid for `ttl`, returning its expiry. Expired tokens are purged
+    async fn insert_token(&self, username: &str, token_hash: &str, ttl: Duration) -> Result<DateTime<Utc>, MyError>;
+
+    // Returns the user a valid (unexpired) token belongs to
+    async fn token_owner(&self, token_hash: &str) -> Result<Option<String>, MyError>;
+
+    // Consumes a valid token and sets the new password hash of its user, atomically.
+    // All the other tokens of the user are discarded. Returns the user, or None if the token is not valid
+    async fn reset_password(&self, token_hash: &str, password_hash: &str) -> Result<Option<String>, MyError>;
+}
+
+// Creates a reset token for the user and sends it through the notifier.
+// Nothing happens for unknown users, but the caller cannot tell the difference
+pub async fn request_reset(db: &Repositories, notifier: &dyn Notifier, config: &Config, username: &str) -> Result<(), MyError> {
+    if time_db("user.password_hash", db.users.password_hash(username)).await?.is_none() {
+        return Ok(());
+    }
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let expires_at = time_db(
+        "password_reset.insert",
+        db.password_resets.insert_token(username, &hash_token(&token), config.password_reset_ttl),
+    ).await?;
+    notifier.notify(username, &Notification::PasswordReset { token, expires_at }).await
+}
+
+// Sets a new password with a reset token, returning the user it belongs to
+pub async fn confirm_reset(db: &Repositories, config: &Config, payload: &PasswordResetConfirm) -> Result<String, MyError> {
+    let token_hash = hash_token(&payload.token);
+    let Some(username) = time_db("password_reset.owner", db.password_resets.token_owner(&token_hash)).await? else {
+        return Err(MyError::InvalidResetToken);
+    };
+    check_password(config, &username, &payload.new_password)?;
+
+    let password_hash = hash_password(config, &payload.new_password).await?;
+    time_db("password_reset.reset", db.password_resets.reset_password(&token_hash, &password_hash))
+        .await?
+        .ok_or(MyError::InvalidResetToken)
+}
+
+// Tokens are random and long, so a fast unsalted hash is enough to keep them unusable if the database leaks
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
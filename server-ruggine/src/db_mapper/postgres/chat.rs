@@ -0,0 +1,215 @@
+use axum::async_trait;
+use crate::db_mapper::chat::{Chat, ChatRaw, ChatRepository};
+use crate::db_mapper::message::{Message, SystemEvent};
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::db_mapper::postgres::message::store_system_message;
+use crate::db_mapper::postgres::request::store_requests;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl ChatRepository for PostgresRepository {
+    async fn user_chats(&self, username: &str) -> Result<Vec<Chat>, MyError> {
+        let raw_chats = sqlx::query_as::<_, ChatRaw>(
+            r#"
+            SELECT c.id, c.name, c.is_group AS "isGroup", c.created_at AS "createdAt",
+                   STRING_AGG(uj.username, ',') AS participants
+            FROM chats c
+            JOIN users_joined uj ON uj.chat_id = c.id
+            WHERE c.id IN (
+                SELECT chat_id FROM users_joined WHERE username = $1
+            )
+            GROUP BY c.id, c.name, c.is_group, c.created_at
+            ORDER BY COALESCE((SELECT MAX(m.send_at) FROM messages m WHERE m.chat_id = c.id), c.created_at) DESC
+            "#
+        )
+            .bind(username)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(raw_chats.into_iter().map(|raw| Chat::from_raw(raw, username)).collect())
+    }
+
+    async fn create_group(
+        &self,
+        name: Option<&str>,
+        is_group: bool,
+        creator: &str,
+        invitees: &[String],
+        creation: &SystemEvent,
+    ) -> Result<(i64, Message), MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        // Insert new chat
+        let chat_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO chats (name, is_group) VALUES ($1, $2) RETURNING id"
+        )
+            .bind(name)
+            .bind(is_group)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Insert only the creator as member
+        sqlx::query(
+            "INSERT INTO users_joined (chat_id, username) VALUES ($1, $2)"
+        )
+            .bind(chat_id)
+            .bind(creator)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Invite the other participants
+        if !invitees.is_empty() {
+            store_requests(&mut tx, chat_id, creator, invitees).await?;
+        }
+
+        // Automatic message indicating group creation
+        let message = store_system_message(&mut tx, chat_id, creator, creation).await?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok((chat_id, message))
+    }
+
+    async fn create_private_chat(
+        &self,
+        creator: &str,
+        other_username: &str,
+        creation: &SystemEvent,
+    ) -> Result<(i64, Option<Message>), MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        // Verify other user exists
+        let other_exists = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM users WHERE username = $1"
+        )
+            .bind(other_username)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)? > 0;
+
+        if !other_exists {
+            return Err(MyError::UserNotFound);
+        }
+
+        // See if a private chat between these two users already exists
+        let existing_chat_id = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT c.id
+            FROM chats c
+            JOIN users_joined uj1 ON uj1.chat_id = c.id AND uj1.username = $1
+            JOIN users_joined uj2 ON uj2.chat_id = c.id AND uj2.username = $2
+            WHERE NOT c.is_group
+            "#,
+        )
+            .bind(creator)
+            .bind(other_username)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        if let Some(chat_id) = existing_chat_id {
+            return Ok((chat_id, None));
+        }
+
+        // Create new private chat
+        let chat_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO chats (is_group) VALUES (FALSE) RETURNING id"
+        )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Insert new chat participants
+        sqlx::query(
+            "INSERT INTO users_joined (chat_id, username) VALUES ($1, $2), ($1, $3)"
+        )
+            .bind(chat_id)
+            .bind(creator)
+            .bind(other_username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Insert automatic message
+        let message = store_system_message(&mut tx, chat_id, creator, creation).await?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok((chat_id, Some(message)))
+    }
+
+    async fn leave_group(&self, chat_id: i64, username: &str, leave: &SystemEvent) -> Result<(Message, bool), MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        // Verify the chat exists and is a group, locking it against concurrent leaves
+        let is_group = sqlx::query_scalar::<_, i64>(
+            "SELECT id FROM chats WHERE id = $1 AND is_group FOR UPDATE",
+        )
+            .bind(chat_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        if is_group.is_none() {
+            return Err(MyError::ChatNotFound);
+        }
+
+        // Verify user is in the group
+        let is_member = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM users_joined WHERE username = $1 AND chat_id = $2",
+        )
+            .bind(username)
+            .bind(chat_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        if is_member == 0 {
+            return Err(MyError::UserDoesNotBelongToGroup);
+        }
+
+        // Insert leaving message
+        let message = store_system_message(&mut tx, chat_id, username, leave).await?;
+
+        // Remove user from the group
+        sqlx::query(
+            "DELETE FROM users_joined WHERE username = $1 AND chat_id = $2",
+        )
+            .bind(username)
+            .bind(chat_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        let remaining_members = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM users_joined WHERE chat_id = $1",
+        )
+            .bind(chat_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Once nobody is left, delete the group (messages and requests cascade)
+        if remaining_members <= 0 {
+            sqlx::query("DELETE FROM chats WHERE id = $1")
+                .bind(chat_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(MyError::from)?;
+        }
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok((message, remaining_members > 0))
+    }
+
+    async fn participants(&self, chat_id: i64) -> Result<Vec<String>, MyError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT username FROM users_joined WHERE chat_id = $1"
+        )
+            .bind(chat_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)
+    }
+}
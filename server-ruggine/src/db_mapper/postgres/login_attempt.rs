@@ -0,0 +1,63 @@
+use axum::async_trait;
+use std::time::Duration;
+use sqlx::Row;
+use crate::db_mapper::login_attempt::{FailureStreak, LoginAttempt, LoginAttemptRepository, NewLoginAttempt};
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl LoginAttemptRepository for PostgresRepository {
+    async fn insert_attempt(&self, attempt: &NewLoginAttempt<'_>) -> Result<(), MyError> {
+        sqlx::query("INSERT INTO login_attempts (username, ip, user_agent, success) VALUES ($1, $2, $3, $4)")
+            .bind(attempt.username)
+            .bind(attempt.ip)
+            .bind(attempt.user_agent)
+            .bind(attempt.success)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(())
+    }
+
+    async fn failure_streak(&self, username: &str, window: Duration) -> Result<FailureStreak, MyError> {
+        let row = sqlx::query(
+            r#"
+            SELECT COUNT(*) AS failures, MAX(attempted_at) AS last_failure
+            FROM login_attempts
+            WHERE username = $1 AND NOT success AND attempted_at > now() - make_interval(secs => $2)
+              AND id > COALESCE((SELECT MAX(id) FROM login_attempts WHERE username = $1 AND success), 0)
+            "#
+        )
+            .bind(username)
+            .bind(window.as_secs_f64())
+            .fetch_one(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(FailureStreak {
+            failures: row.try_get("failures")?,
+            last_failure: row.try_get("last_failure")?,
+        })
+    }
+
+    async fn user_attempts(&self, username: &str, limit: i64) -> Result<Vec<LoginAttempt>, MyError> {
+        let rows = sqlx::query(
+            "SELECT ip, user_agent, success, attempted_at FROM login_attempts WHERE username = $1 ORDER BY id DESC LIMIT $2"
+        )
+            .bind(username)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        rows.iter()
+            .map(|row| Ok(LoginAttempt {
+                ip: row.try_get("ip")?,
+                user_agent: row.try_get("user_agent")?,
+                success: row.try_get("success")?,
+                attempted_at: row.try_get("attempted_at")?,
+            }))
+            .collect()
+    }
+}
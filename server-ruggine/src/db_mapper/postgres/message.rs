@@ -0,0 +1,198 @@
+use axum::async_trait;
+use std::collections::HashMap;
+use sqlx::{Executor, PgConnection, Postgres, Row};
+use sqlx::postgres::PgRow;
+use crate::db_mapper::message::{Message, MessageRepository, SystemEvent};
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::Locale;
+
+const MESSAGE_COLUMNS: &str = "id, chat_id, msg, from_user, is_auto, event, send_at, client_id";
+
+// The sender of a message is empty once the user is deleted
+fn message_from_row(row: &PgRow) -> Result<Message, MyError> {
+    let from_user: Option<String> = row.try_get("from_user")?;
+    Ok(Message {
+        id: row.try_get("id")?,
+        chat_id: row.try_get("chat_id")?,
+        msg: row.try_get("msg")?,
+        from_user: from_user.unwrap_or_default(),
+        is_auto: row.try_get("is_auto")?,
+        event: event_from_json(row.try_get("event")?),
+        send_at: row.try_get("send_at")?,
+        client_id: row.try_get("client_id")?,
+    })
+}
+
+// Events are stored as JSON: unreadable ones are dropped, leaving the stored text
+fn event_from_json(json: Option<String>) -> Option<SystemEvent> {
+    serde_json::from_str(&json?).ok()
+}
+
+// Stores a message using the given connection (usually an open transaction) and returns it,
+// together with whether it was inserted or already stored with the same `client_id`.
+// Messages reporting an event are system messages
+async fn store_message(
+    conn: &mut PgConnection,
+    chat_id: i64,
+    username: &str,
+    msg: &str,
+    event: Option<&SystemEvent>,
+    client_id: Option<&str>,
+) -> Result<(Message, bool), MyError> {
+    // A retried send returns the message already stored
+    if let Some(client_id) = client_id
+        && let Some(existing) = find_by_client_id(&mut *conn, username, client_id).await?
+    {
+        return Ok((existing.retried_as(chat_id, msg)?, false));
+    }
+
+    // Verify that the user is a member of the chat
+    let is_member = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM users_joined WHERE chat_id = $1 AND username = $2"
+    )
+        .bind(chat_id)
+        .bind(username)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(MyError::from)?;
+
+    if is_member == 0 {
+        return Err(MyError::UserDoesNotBelongToGroup);
+    }
+
+    // Insert message, getting back the stored row (id and server timestamp included)
+    let row = sqlx::query(&format!(
+        "INSERT INTO messages (chat_id, msg, from_user, is_auto, event, client_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING {}",
+        MESSAGE_COLUMNS
+    ))
+        .bind(chat_id)
+        .bind(msg)
+        .bind(username)
+        .bind(event.is_some())
+        .bind(event.and_then(|event| serde_json::to_string(event).ok()))
+        .bind(client_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => MyError::DuplicateMessage,
+            e => MyError::from(e),
+        })?;
+
+    Ok((message_from_row(&row)?, true))
+}
+
+// Stores the system message reporting the event, with its English text for the clients that ignore events
+pub(super) async fn store_system_message(
+    conn: &mut PgConnection,
+    chat_id: i64,
+    username: &str,
+    event: &SystemEvent,
+) -> Result<Message, MyError> {
+    let (message, _) = store_message(conn, chat_id, username, &event.render(Locale::En), Some(event), None).await?;
+    Ok(message)
+}
+
+// Retrieves the message stored by a sender with the given client id, if any
+async fn find_by_client_id<'e, E>(executor: E, username: &str, client_id: &str) -> Result<Option<Message>, MyError>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let row = sqlx::query(&format!(
+        "SELECT {} FROM messages WHERE from_user = $1 AND client_id = $2",
+        MESSAGE_COLUMNS
+    ))
+        .bind(username)
+        .bind(client_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(MyError::from)?;
+
+    row.as_ref().map(message_from_row).transpose()
+}
+
+#[async_trait]
+impl MessageRepository for PostgresRepository {
+    async fn insert_message(
+        &self,
+        chat_id: i64,
+        username: &str,
+        msg: &str,
+        client_id: Option<&str>,
+    ) -> Result<(Message, bool), MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+        let stored = match store_message(&mut tx, chat_id, username, msg, None, client_id).await {
+            Ok(stored) => stored,
+            Err(MyError::DuplicateMessage) => {
+                // A concurrent retry stored the message first, or the client id was reused
+                drop(tx);
+                let existing = find_by_client_id(&self.pool, username, client_id.unwrap_or_default()).await?;
+                let existing = existing.ok_or(MyError::Unknown)?;
+                return Ok((existing.retried_as(chat_id, msg)?, false));
+            }
+            Err(e) => return Err(e),
+        };
+        tx.commit().await.map_err(MyError::from)?;
+
+        Ok(stored)
+    }
+
+    async fn chat_messages(&self, chat_id: i64, username: &str) -> Result<Vec<Message>, MyError> {
+        // Verify that the user is a member of the chat
+        let is_member = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM users_joined WHERE chat_id = $1 AND username = $2"
+        )
+            .bind(chat_id)
+            .bind(username)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        if is_member == 0 {
+            return Err(MyError::UserDoesNotBelongToGroup);
+        }
+
+        // Retrieve messages
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM messages WHERE chat_id = $1 ORDER BY id ASC",
+            MESSAGE_COLUMNS
+        ))
+            .bind(chat_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        rows.iter().map(message_from_row).collect()
+    }
+
+    async fn messages_since(
+        &self,
+        username: &str,
+        last_id: i64,
+        chats: &HashMap<i64, i64>,
+        limit: i64,
+    ) -> Result<Vec<Message>, MyError> {
+        let (chat_ids, last_ids): (Vec<i64>, Vec<i64>) = chats.iter().map(|(chat, last)| (*chat, *last)).unzip();
+        let rows = sqlx::query(
+            r#"
+            SELECT m.id, m.chat_id, m.msg, m.from_user, m.is_auto, m.event, m.send_at, m.client_id
+            FROM messages m
+            JOIN users_joined uj ON uj.chat_id = m.chat_id AND uj.username = $1
+            LEFT JOIN UNNEST($2::BIGINT[], $3::BIGINT[]) AS cutoff(chat_id, last_id) ON cutoff.chat_id = m.chat_id
+            WHERE m.id > COALESCE(cutoff.last_id, $4)
+            ORDER BY m.id ASC
+            LIMIT $5
+            "#
+        )
+            .bind(username)
+            .bind(chat_ids)
+            .bind(last_ids)
+            .bind(last_id)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        rows.iter().map(message_from_row).collect()
+    }
+}
@@ -0,0 +1,37 @@
+use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPoolOptions;
+use crate::utilities::config::Config;
+
+mod user;
+mod chat;
+mod message;
+mod request;
+mod login_attempt;
+mod password_reset;
+mod two_factor;
+mod health;
+
+// Migrations embedded in the binary, applied on connection
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+// PostgreSQL implementation of the repositories, for deployments with several concurrent writers
+pub struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    // Connects to the database and brings its schema up to date
+    pub async fn connect(url: &str, config: &Config) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .acquire_timeout(config.db_busy_timeout)
+            .connect(url)
+            .await?;
+
+        MIGRATOR.run(&pool).await?;
+        user::fold_names(&pool).await?;
+
+        Ok(PostgresRepository { pool })
+    }
+}
@@ -0,0 +1,73 @@
+use axum::async_trait;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use crate::db_mapper::password_reset::PasswordResetRepository;
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl PasswordResetRepository for PostgresRepository {
+    async fn insert_token(&self, username: &str, token_hash: &str, ttl: Duration) -> Result<DateTime<Utc>, MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM password_resets WHERE expires_at <= now()")
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        let expires_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "INSERT INTO password_resets (token_hash, username, expires_at) VALUES ($1, $2, now() + make_interval(secs => $3)) RETURNING expires_at"
+        )
+            .bind(token_hash)
+            .bind(username)
+            .bind(ttl.as_secs_f64())
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(expires_at)
+    }
+
+    async fn token_owner(&self, token_hash: &str) -> Result<Option<String>, MyError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT username FROM password_resets WHERE token_hash = $1 AND expires_at > now()"
+        )
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(MyError::from)
+    }
+
+    async fn reset_password(&self, token_hash: &str, password_hash: &str) -> Result<Option<String>, MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        let username = sqlx::query_scalar::<_, String>(
+            "DELETE FROM password_resets WHERE token_hash = $1 AND expires_at > now() RETURNING username"
+        )
+            .bind(token_hash)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        let Some(username) = username else {
+            return Ok(None);
+        };
+
+        sqlx::query("UPDATE users SET password = $1 WHERE username = $2")
+            .bind(password_hash)
+            .bind(&username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM password_resets WHERE username = $1")
+            .bind(&username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(Some(username))
+    }
+}
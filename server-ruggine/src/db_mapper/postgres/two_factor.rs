@@ -0,0 +1,107 @@
+use axum::async_trait;
+use crate::db_mapper::two_factor::{TwoFactor, TwoFactorRepository};
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl TwoFactorRepository for PostgresRepository {
+    async fn two_factor(&self, username: &str) -> Result<Option<TwoFactor>, MyError> {
+        let row = sqlx::query_as::<_, (String, bool, i64)>(
+            "SELECT secret, confirmed, last_step FROM two_factor WHERE username = $1"
+        )
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+        Ok(row.map(|(secret, confirmed, last_step)| TwoFactor { secret, confirmed, last_step }))
+    }
+
+    async fn set_pending_secret(&self, username: &str, secret: &str) -> Result<bool, MyError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO two_factor (username, secret) VALUES ($1, $2)
+            ON CONFLICT (username) DO UPDATE SET secret = excluded.secret, last_step = 0, created_at = now()
+            WHERE NOT two_factor.confirmed
+            "#
+        )
+            .bind(username)
+            .bind(secret)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn confirm(&self, username: &str, step: i64, code_hashes: &[String]) -> Result<bool, MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        let result = sqlx::query("UPDATE two_factor SET confirmed = TRUE, last_step = $1 WHERE username = $2 AND NOT confirmed")
+            .bind(step)
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("DELETE FROM recovery_codes WHERE username = $1")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        for code_hash in code_hashes {
+            sqlx::query("INSERT INTO recovery_codes (username, code_hash) VALUES ($1, $2)")
+                .bind(username)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await
+                .map_err(MyError::from)?;
+        }
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(true)
+    }
+
+    async fn use_step(&self, username: &str, step: i64) -> Result<bool, MyError> {
+        let result = sqlx::query("UPDATE two_factor SET last_step = $1 WHERE username = $2 AND confirmed AND last_step < $3")
+            .bind(step)
+            .bind(username)
+            .bind(step)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn use_recovery_code(&self, username: &str, code_hash: &str) -> Result<bool, MyError> {
+        let result = sqlx::query(
+            "UPDATE recovery_codes SET used_at = now() WHERE username = $1 AND code_hash = $2 AND used_at IS NULL"
+        )
+            .bind(username)
+            .bind(code_hash)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn disable(&self, username: &str) -> Result<(), MyError> {
+        let mut tx = self.pool.begin().await.map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE username = $1")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM two_factor WHERE username = $1")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        tx.commit().await.map_err(MyError::from)
+    }
+}
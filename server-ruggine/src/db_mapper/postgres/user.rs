@@ -0,0 +1,273 @@
+use axum::async_trait;
+use sqlx::Row;
+use crate::db_mapper::postgres::PostgresRepository;
+use sqlx::PgPool;
+use sqlx::postgres::PgRow;
+use crate::db_mapper::user::{fold_case, Avatar, NewUser, ProfileRecord, UpdateAccountRequest, UserMatch, UserRepository, UserSearch};
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::Locale;
+
+fn profile_from_row(row: &PgRow) -> Result<ProfileRecord, MyError> {
+    let locale: Option<String> = row.try_get("locale")?;
+    Ok(ProfileRecord {
+        username: row.try_get("username")?,
+        name: row.try_get("name")?,
+        surname: row.try_get("surname")?,
+        display_name: row.try_get("display_name")?,
+        bio: row.try_get("bio")?,
+        locale: locale.as_deref().and_then(Locale::from_tag),
+        discoverable: row.try_get("discoverable")?,
+        joined_at: row.try_get("created_at")?,
+        avatar_updated_at: row.try_get("avatar_updated_at")?,
+    })
+}
+
+#[async_trait]
+impl UserRepository for PostgresRepository {
+    async fn insert_user(&self, user: &NewUser) -> Result<(), MyError> {
+        sqlx::query(
+            r#"
+            INSERT INTO users (username, name, surname, folded_name, folded_surname, password)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#
+        )
+            .bind(&user.username)
+            .bind(&user.name)
+            .bind(&user.surname)
+            .bind(fold_case(&user.name))
+            .bind(fold_case(&user.surname))
+            .bind(&user.password_hash)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => MyError::UsernameExists,
+                e => MyError::from(e),
+            })?;
+
+        Ok(())
+    }
+
+    async fn password_hash(&self, username: &str) -> Result<Option<String>, MyError> {
+        sqlx::query_scalar::<_, String>("SELECT password FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(MyError::from)
+    }
+
+    async fn update_password(&self, username: &str, password_hash: &str) -> Result<bool, MyError> {
+        let result = sqlx::query("UPDATE users SET password = $1 WHERE username = $2")
+            .bind(password_hash)
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn locale(&self, username: &str) -> Result<Option<Locale>, MyError> {
+        let locale = sqlx::query_scalar::<_, Option<String>>("SELECT locale FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(locale.flatten().as_deref().and_then(Locale::from_tag))
+    }
+
+    async fn profile(&self, username: &str) -> Result<Option<ProfileRecord>, MyError> {
+        let row = sqlx::query(
+            r#"
+            SELECT u.username, u.name, u.surname, u.display_name, u.bio, u.locale, u.discoverable, u.created_at,
+                a.updated_at AS avatar_updated_at
+            FROM users u
+            LEFT JOIN avatars a ON a.username = u.username
+            WHERE u.username = $1
+            "#
+        )
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        row.as_ref().map(profile_from_row).transpose()
+    }
+
+    async fn update_profile(&self, username: &str, changes: &UpdateAccountRequest) -> Result<bool, MyError> {
+        // Optional fields are only written when present in the request, so that `null` can clear them
+        let result = sqlx::query(
+            r#"
+            UPDATE users SET
+                name = COALESCE($1, name),
+                folded_name = COALESCE($2, folded_name),
+                surname = COALESCE($3, surname),
+                folded_surname = COALESCE($4, folded_surname),
+                display_name = CASE WHEN $5 THEN $6 ELSE display_name END,
+                bio = CASE WHEN $7 THEN $8 ELSE bio END,
+                locale = CASE WHEN $9 THEN $10 ELSE locale END,
+                discoverable = COALESCE($11, discoverable)
+            WHERE username = $12
+            "#
+        )
+            .bind(&changes.name)
+            .bind(changes.name.as_deref().map(fold_case))
+            .bind(&changes.surname)
+            .bind(changes.surname.as_deref().map(fold_case))
+            .bind(changes.display_name.is_some())
+            .bind(changes.display_name.clone().flatten())
+            .bind(changes.bio.is_some())
+            .bind(changes.bio.clone().flatten())
+            .bind(changes.locale.is_some())
+            .bind(changes.locale.flatten().map(Locale::code))
+            .bind(changes.discoverable)
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn avatar(&self, username: &str) -> Result<Option<Avatar>, MyError> {
+        let row = sqlx::query("SELECT image, updated_at FROM avatars WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        match row {
+            Some(row) => Ok(Some(Avatar { image: row.try_get("image")?, updated_at: row.try_get("updated_at")? })),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_avatar(&self, username: &str, image: &[u8]) -> Result<(), MyError> {
+        sqlx::query(
+            r#"
+            INSERT INTO avatars (username, image, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (username) DO UPDATE SET image = excluded.image, updated_at = excluded.updated_at
+            "#
+        )
+            .bind(username)
+            .bind(image)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(())
+    }
+
+    async fn delete_avatar(&self, username: &str) -> Result<bool, MyError> {
+        let result = sqlx::query("DELETE FROM avatars WHERE username = $1")
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn search(&self, caller: &str, search: &UserSearch, limit: i64, offset: i64) -> Result<Vec<UserMatch>, MyError> {
+        // Rank of the match: exact username, username prefix, name or surname prefix, substring, characters in order
+        let rows = sqlx::query(
+            r#"
+            WITH shared AS (
+                SELECT other.username, COUNT(*) AS shared_chats
+                FROM users_joined mine
+                JOIN users_joined other ON other.chat_id = mine.chat_id AND other.username <> mine.username
+                WHERE mine.username = $1
+                GROUP BY other.username
+            )
+            SELECT u.username, u.name, u.surname, u.display_name, u.bio, u.locale, u.discoverable, u.created_at,
+                a.updated_at AS avatar_updated_at, COALESCE(s.shared_chats, 0) AS shared_chats
+            FROM users u
+            LEFT JOIN shared s ON s.username = u.username
+            LEFT JOIN avatars a ON a.username = u.username
+            WHERE u.username <> $1
+              AND (u.discoverable OR s.username IS NOT NULL)
+              AND NOT EXISTS (
+                  SELECT 1 FROM blocked_users b
+                  WHERE (b.blocker = $1 AND b.blocked = u.username) OR (b.blocker = u.username AND b.blocked = $1)
+              )
+              AND (lower(u.username) LIKE $5 OR u.folded_name LIKE $5 OR u.folded_surname LIKE $5
+                   OR (u.folded_name || ' ' || u.folded_surname) LIKE $5)
+            ORDER BY
+                s.username IS NULL,
+                CASE
+                    WHEN lower(u.username) = $2 THEN 0
+                    WHEN lower(u.username) LIKE $3 THEN 1
+                    WHEN u.folded_name LIKE $3 OR u.folded_surname LIKE $3 OR (u.folded_name || ' ' || u.folded_surname) LIKE $3 THEN 2
+                    WHEN lower(u.username) LIKE $4 OR u.folded_name LIKE $4 OR u.folded_surname LIKE $4 THEN 3
+                    ELSE 4
+                END,
+                shared_chats DESC,
+                lower(u.username)
+            LIMIT $6 OFFSET $7
+            "#
+        )
+            .bind(caller)
+            .bind(&search.exact)
+            .bind(&search.prefix)
+            .bind(&search.contains)
+            .bind(&search.fuzzy)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        rows.iter()
+            .map(|row| Ok(UserMatch { profile: profile_from_row(row)?, shared_chats: row.try_get("shared_chats")? }))
+            .collect()
+    }
+
+    async fn set_blocked(&self, blocker: &str, blocked: &str, is_blocked: bool) -> Result<bool, MyError> {
+        let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM users WHERE username = $1)")
+            .bind(blocked)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+        if !exists {
+            return Ok(false);
+        }
+
+        let query = if is_blocked {
+            "INSERT INTO blocked_users (blocker, blocked) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+        } else {
+            "DELETE FROM blocked_users WHERE blocker = $1 AND blocked = $2"
+        };
+        sqlx::query(query)
+            .bind(blocker)
+            .bind(blocked)
+            .execute(&self.pool)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(true)
+    }
+
+    async fn blocked_users(&self, username: &str) -> Result<Vec<String>, MyError> {
+        sqlx::query_scalar::<_, String>("SELECT blocked FROM blocked_users WHERE blocker = $1 ORDER BY blocked")
+            .bind(username)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(MyError::from)
+    }
+}
+
+// Sets the folded names of the users created before they were stored
+pub(super) async fn fold_names(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let users = sqlx::query("SELECT username, name, surname FROM users WHERE folded_name IS NULL OR folded_surname IS NULL")
+        .fetch_all(pool)
+        .await?;
+    for user in users {
+        sqlx::query("UPDATE users SET folded_name = $1, folded_surname = $2 WHERE username = $3")
+            .bind(fold_case(user.try_get("name")?))
+            .bind(fold_case(user.try_get("surname")?))
+            .bind(user.try_get::<String, _>("username")?)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use axum::async_trait;
+use crate::db_mapper::chat::{Chat, ChatRaw, ChatRepository};
+use crate::db_mapper::message::{Message, SystemEvent};
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::db_mapper::sqlite::message::store_system_message;
+use crate::db_mapper::sqlite::request::store_requests;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl ChatRepository for SqliteRepository {
+    async fn user_chats(&self, username: &str) -> Result<Vec<Chat>, MyError> {
+        let raw_chats = sqlx::query_as::<_, ChatRaw>(
+            "SELECT c.id AS id, c.name, c.isGroup, c.createdAt, GROUP_CONCAT(u.username, ',') AS participants,
+                    MAX(m.sendAt) as last_message_at
+             FROM CHAT c
+             JOIN USERS_JOINED uj ON uj.chatId = c.id
+             JOIN USER u ON u.username = uj.username
+             LEFT JOIN MESSAGE m ON m.chatID = c.id
+             WHERE c.id IN (
+                 SELECT chatId FROM USERS_JOINED WHERE username = ?
+             )
+             GROUP BY c.id, c.name, c.isGroup, c.createdAt
+             ORDER BY COALESCE(last_message_at, c.createdAt) DESC;"
+        )
+            .bind(username)
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(raw_chats.into_iter().map(|raw| Chat::from_raw(raw, username)).collect())
+    }
+
+    async fn create_group(
+        &self,
+        name: Option<&str>,
+        is_group: bool,
+        creator: &str,
+        invitees: &[String],
+        creation: &SystemEvent,
+    ) -> Result<(i64, Message), MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        // Insert new chat
+        let chat_id = sqlx::query(
+            "INSERT INTO CHAT (name, isGroup, createdAt) VALUES (?, ?, CURRENT_TIMESTAMP)"
+        )
+            .bind(name)
+            .bind(is_group)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?
+            .last_insert_rowid();
+
+        // Insert only the creator into USERS_JOINED
+        sqlx::query(
+            "INSERT INTO USERS_JOINED (chatId, username) VALUES (?, ?)"
+        )
+            .bind(chat_id)
+            .bind(creator)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Invite the other participants
+        if !invitees.is_empty() {
+            store_requests(&mut tx, chat_id, creator, invitees).await?;
+        }
+
+        // Automatic message indicating group creation
+        let message = store_system_message(&mut tx, chat_id, creator, creation).await?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok((chat_id, message))
+    }
+
+    async fn create_private_chat(
+        &self,
+        creator: &str,
+        other_username: &str,
+        creation: &SystemEvent,
+    ) -> Result<(i64, Option<Message>), MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        // Verify other user exists
+        let other_exists = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM USER WHERE username = ?"
+        )
+            .bind(other_username)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)? > 0;
+
+        if !other_exists {
+            return Err(MyError::UserNotFound);
+        }
+
+        // See if a private chat between these two users already exists
+        let existing_chat_id = sqlx::query_scalar::<_, Option<i64>>(
+            r#"
+            SELECT c.id
+            FROM CHAT c
+            JOIN USERS_JOINED uj1 ON uj1.chatId = c.id AND uj1.username = ?
+            JOIN USERS_JOINED uj2 ON uj2.chatId = c.id AND uj2.username = ?
+            WHERE c.isGroup = 0
+            "#,
+        )
+            .bind(creator)
+            .bind(other_username)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        if let Some(Some(chat_id)) = existing_chat_id {
+            return Ok((chat_id, None));
+        }
+
+        // Create new private chat
+        let chat_id = sqlx::query(
+            "INSERT INTO CHAT (isGroup, createdAt) VALUES (0, CURRENT_TIMESTAMP)"
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?
+            .last_insert_rowid();
+
+        // Insert new chat participants
+        sqlx::query(
+            "INSERT INTO USERS_JOINED (chatId, username) VALUES (?, ?), (?, ?)"
+        )
+            .bind(chat_id)
+            .bind(creator)
+            .bind(chat_id)
+            .bind(other_username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Insert automatic message
+        let message = store_system_message(&mut tx, chat_id, creator, creation).await?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok((chat_id, Some(message)))
+    }
+
+    async fn leave_group(&self, chat_id: i64, username: &str, leave: &SystemEvent) -> Result<(Message, bool), MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        // Verify the chat exists and is a group
+        let is_group = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM CHAT WHERE id = ? AND isGroup = 1",
+        )
+            .bind(chat_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        if is_group == 0 {
+            return Err(MyError::ChatNotFound);
+        }
+
+        // Verify user is in the group
+        let is_member = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM USERS_JOINED WHERE username = ? AND chatId = ?",
+        )
+            .bind(username)
+            .bind(chat_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        if is_member == 0 {
+            return Err(MyError::UserDoesNotBelongToGroup);
+        }
+
+        // Insert leaving message
+        let message = store_system_message(&mut tx, chat_id, username, leave).await?;
+
+        // Remove user from a group
+        sqlx::query(
+            "DELETE FROM USERS_JOINED WHERE username = ? AND chatId = ?",
+        )
+            .bind(username)
+            .bind(chat_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Check if only one person remains in the group
+        let remaining_members = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM USERS_JOINED WHERE chatId = ?",
+        )
+            .bind(chat_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        // Once nobody is left, delete the group (messages and pending requests cascade)
+        if remaining_members <= 0 {
+            sqlx::query("DELETE FROM CHAT WHERE id = ?")
+                .bind(chat_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(MyError::from)?;
+        }
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok((message, remaining_members > 0))
+    }
+
+    async fn participants(&self, chat_id: i64) -> Result<Vec<String>, MyError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT username FROM USERS_JOINED WHERE chatId = ?"
+        )
+            .bind(chat_id)
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)
+    }
+}
@@ -0,0 +1,30 @@
+use axum::async_trait;
+use crate::db_mapper::health::{pending_versions, HealthRepository};
+use crate::db_mapper::sqlite::{SqliteRepository, MIGRATOR};
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl HealthRepository for SqliteRepository {
+    async fn ping(&self) -> Result<(), MyError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>, MyError> {
+        let applied = sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(pending_versions(&MIGRATOR, &applied))
+    }
+
+    async fn close(&self) {
+        // Closing the writer last lets SQLite checkpoint the WAL into the main database file
+        self.reader.close().await;
+        self.writer.close().await;
+    }
+}
@@ -0,0 +1,64 @@
+use axum::async_trait;
+use std::time::Duration;
+use sqlx::Row;
+use crate::db_mapper::login_attempt::{FailureStreak, LoginAttempt, LoginAttemptRepository, NewLoginAttempt};
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl LoginAttemptRepository for SqliteRepository {
+    async fn insert_attempt(&self, attempt: &NewLoginAttempt<'_>) -> Result<(), MyError> {
+        sqlx::query("INSERT INTO LOGIN_ATTEMPT (username, ip, userAgent, success) VALUES (?, ?, ?, ?)")
+            .bind(attempt.username)
+            .bind(attempt.ip)
+            .bind(attempt.user_agent)
+            .bind(attempt.success)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(())
+    }
+
+    async fn failure_streak(&self, username: &str, window: Duration) -> Result<FailureStreak, MyError> {
+        let row = sqlx::query(
+            r#"
+            SELECT COUNT(*) AS failures, MAX(attemptedAt) AS lastFailure
+            FROM LOGIN_ATTEMPT
+            WHERE username = ? AND NOT success AND attemptedAt > datetime('now', ?)
+              AND ID > COALESCE((SELECT MAX(ID) FROM LOGIN_ATTEMPT WHERE username = ? AND success), 0)
+            "#
+        )
+            .bind(username)
+            .bind(format!("-{} seconds", window.as_secs()))
+            .bind(username)
+            .fetch_one(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(FailureStreak {
+            failures: row.try_get("failures")?,
+            last_failure: row.try_get("lastFailure")?,
+        })
+    }
+
+    async fn user_attempts(&self, username: &str, limit: i64) -> Result<Vec<LoginAttempt>, MyError> {
+        let rows = sqlx::query(
+            "SELECT ip, userAgent, success, attemptedAt FROM LOGIN_ATTEMPT WHERE username = ? ORDER BY ID DESC LIMIT ?"
+        )
+            .bind(username)
+            .bind(limit)
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        rows.iter()
+            .map(|row| Ok(LoginAttempt {
+                ip: row.try_get("ip")?,
+                user_agent: row.try_get("userAgent")?,
+                success: row.try_get("success")?,
+                attempted_at: row.try_get("attemptedAt")?,
+            }))
+            .collect()
+    }
+}
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+use sqlx::SqlitePool;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use crate::utilities::config::Config;
+
+mod user;
+mod chat;
+mod message;
+mod request;
+mod login_attempt;
+mod password_reset;
+mod two_factor;
+mod health;
+
+// Migrations embedded in the binary, applied on connection
+pub(super) static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+// SQLite implementation of the repositories, used for development.
+// SQLite allows a single writer at a time, so writes go through a one-connection pool while reads
+// use a separate pool: in WAL mode readers are not blocked by the writer (nor block it).
+pub struct SqliteRepository {
+    reader: SqlitePool,
+    writer: SqlitePool,
+}
+
+impl SqliteRepository {
+    // Opens the database and brings its schema up to date
+    pub async fn connect(url: &str, config: &Config) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .journal_mode(SqliteJournalMode::Wal)
+            // Safe in WAL mode: a crash may lose the last commits, but never corrupts the database
+            .synchronous(SqliteSynchronous::Normal)
+            // Enforce the declared foreign keys (and their ON DELETE CASCADE)
+            .foreign_keys(true)
+            // Wait for locks held by other processes instead of failing immediately with SQLITE_BUSY
+            .busy_timeout(config.db_busy_timeout);
+
+        let writer = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options.clone())
+            .await?;
+
+        // Migrate before opening the readers, which cannot change the schema
+        check_local_time_migration(&writer).await?;
+        MIGRATOR.run(&writer).await?;
+        user::fold_names(&writer).await?;
+
+        let reader = SqlitePoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .connect_with(options.read_only(true))
+            .await?;
+
+        Ok(SqliteRepository { reader, writer })
+    }
+}
+
+// Version of the migration converting the chats created in local time to UTC
+const LOCAL_TIME_MIGRATION: i64 = 3;
+
+// The conversion uses the timezone of the server process, which must then be the one the chats
+// were created in: when there are chats to convert, it only runs if `TZ` says which one it is
+async fn check_local_time_migration(writer: &SqlitePool) -> Result<(), sqlx::Error> {
+    if std::env::var_os("TZ").is_some() {
+        return Ok(());
+    }
+
+    let table_exists = |name: &'static str| {
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)")
+            .bind(name)
+            .fetch_one(writer)
+    };
+    if !table_exists("CHAT").await? {
+        return Ok(());
+    }
+    if table_exists("_sqlx_migrations").await? {
+        let applied = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM _sqlx_migrations WHERE version = ?)")
+            .bind(LOCAL_TIME_MIGRATION)
+            .fetch_one(writer)
+            .await?;
+        if applied {
+            return Ok(());
+        }
+    }
+
+    let has_chats = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM CHAT)")
+        .fetch_one(writer)
+        .await?;
+    if has_chats {
+        return Err(sqlx::Error::Configuration(
+            "the chats in this database were created in local time: set TZ to the timezone of the server that created them \
+             (e.g. TZ=Europe/Rome) to convert them to UTC"
+                .into(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use axum::async_trait;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use crate::db_mapper::password_reset::PasswordResetRepository;
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl PasswordResetRepository for SqliteRepository {
+    async fn insert_token(&self, username: &str, token_hash: &str, ttl: Duration) -> Result<DateTime<Utc>, MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM PASSWORD_RESET WHERE expiresAt <= CURRENT_TIMESTAMP")
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        let expires_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "INSERT INTO PASSWORD_RESET (tokenHash, username, expiresAt) VALUES (?, ?, datetime('now', ?)) RETURNING expiresAt"
+        )
+            .bind(token_hash)
+            .bind(username)
+            .bind(format!("+{} seconds", ttl.as_secs()))
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(expires_at)
+    }
+
+    async fn token_owner(&self, token_hash: &str) -> Result<Option<String>, MyError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT username FROM PASSWORD_RESET WHERE tokenHash = ? AND expiresAt > CURRENT_TIMESTAMP"
+        )
+            .bind(token_hash)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(MyError::from)
+    }
+
+    async fn reset_password(&self, token_hash: &str, password_hash: &str) -> Result<Option<String>, MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        let username = sqlx::query_scalar::<_, String>(
+            "DELETE FROM PASSWORD_RESET WHERE tokenHash = ? AND expiresAt > CURRENT_TIMESTAMP RETURNING username"
+        )
+            .bind(token_hash)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        let Some(username) = username else {
+            return Ok(None);
+        };
+
+        sqlx::query("UPDATE USER SET password = ? WHERE username = ?")
+            .bind(password_hash)
+            .bind(&username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM PASSWORD_RESET WHERE username = ?")
+            .bind(&username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(Some(username))
+    }
+}
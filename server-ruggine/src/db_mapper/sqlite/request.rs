@@ -0,0 +1,181 @@
+use axum::async_trait;
+use sqlx::{Row, SqliteConnection};
+use crate::db_mapper::message::{Message, SystemEvent};
+use crate::db_mapper::request::{RequestRepository, UserRequest};
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::db_mapper::sqlite::message::store_system_message;
+use crate::utilities::error::MyError;
+
+// Inserts the invite requests using the given connection (usually an open transaction).
+// Returns the list of recipients that were not found.
+pub(super) async fn store_requests(
+    conn: &mut SqliteConnection,
+    chat_id: i64,
+    from: &str,
+    to_list: &[String],
+) -> Result<Vec<String>, MyError> {
+    let mut not_found = Vec::new();
+    let mut inserted = false;
+
+    // 1. Verify that the chat exists and is a group
+    let exists = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM CHAT WHERE id = ? AND isGroup = 1",
+    )
+        .bind(chat_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(MyError::from)?;
+    if exists == 0 {
+        return Err(MyError::ChatNotFound);
+    }
+
+    // 2. Verify that the sender belongs to the group
+    let is_member = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM USERS_JOINED WHERE username = ? AND chatId = ?",
+    )
+        .bind(from)
+        .bind(chat_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(MyError::from)?;
+
+    if is_member == 0 {
+        return Err(MyError::UserDoesNotBelongToGroup);
+    }
+
+    // 3. Iterate over the recipient users
+    for to_user in to_list {
+        // Check that the user exists
+        let user_exists = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM USER WHERE username = ?",
+        )
+            .bind(to_user)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(MyError::from)?;
+
+        if user_exists == 0 {
+            not_found.push(to_user.clone());
+            continue;
+        }
+
+        // Check user is not already in the group
+        let already_in_group = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM USERS_JOINED WHERE username = ? AND chatId = ?",
+        )
+            .bind(to_user)
+            .bind(chat_id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(MyError::from)?;
+
+        if already_in_group > 0 {
+            continue;
+        }
+
+        // Insert the request
+        sqlx::query(
+            "INSERT INTO REQUEST (fromUser, toUser, chatID) VALUES (?, ?, ?)",
+        )
+            .bind(from)
+            .bind(to_user)
+            .bind(chat_id)
+            .execute(&mut *conn)
+            .await
+            .map_err(MyError::from)?;
+        inserted = true;
+    }
+
+    if inserted {
+        Ok(not_found)
+    } else if !not_found.is_empty() {
+        Err(MyError::UsersNotFound(not_found))
+    } else {
+        Err(MyError::UserAlreadyInGroup)
+    }
+}
+
+#[async_trait]
+impl RequestRepository for SqliteRepository {
+    async fn insert_requests(&self, chat_id: i64, from: &str, to_list: &[String]) -> Result<Vec<String>, MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+        let not_found = store_requests(&mut tx, chat_id, from, to_list).await?;
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(not_found)
+    }
+
+    async fn delete_request(&self, chat_id: i64, to_user: &str) -> Result<bool, MyError> {
+        let res = sqlx::query(
+            "DELETE FROM REQUEST WHERE chatID = ? AND toUser = ?",
+        )
+            .bind(chat_id)
+            .bind(to_user)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn accept_request(&self, chat_id: i64, username: &str, join: &SystemEvent) -> Result<Message, MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        // Delete the request: accepting is only possible if the user was actually invited
+        let deleted = sqlx::query(
+            "DELETE FROM REQUEST WHERE chatID = ? AND toUser = ?"
+        )
+            .bind(chat_id)
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?
+            .rows_affected();
+
+        if deleted == 0 {
+            return Err(MyError::RequestNotFound);
+        }
+
+        // Insert user into the group
+        sqlx::query(
+            "INSERT INTO USERS_JOINED (chatId, username) VALUES (?, ?)"
+        )
+            .bind(chat_id)
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => MyError::UserAlreadyInGroup,
+                e => MyError::from(e),
+            })?;
+
+        // Insert automatic message
+        let message = store_system_message(&mut tx, chat_id, username, join).await?;
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(message)
+    }
+
+    async fn user_requests(&self, username: &str) -> Result<Vec<UserRequest>, MyError> {
+        let rows = sqlx::query(
+            "
+            SELECT R.chatID as chat_id, R.fromUser as \"from\", C.name as \"name\", R.sendAt as sent_at
+            FROM REQUEST R
+            JOIN CHAT C ON R.chatID = C.id
+            WHERE R.toUser = ?
+            "
+        )
+            .bind(username)
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| UserRequest {
+                chat_id: row.try_get("chat_id").unwrap_or_default(),
+                from: row.try_get("from").unwrap_or_default(),
+                name: row.try_get("name").unwrap_or_default(),
+                sent_at: row.try_get("sent_at").unwrap_or_default(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,107 @@
+use axum::async_trait;
+use crate::db_mapper::two_factor::{TwoFactor, TwoFactorRepository};
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::utilities::error::MyError;
+
+#[async_trait]
+impl TwoFactorRepository for SqliteRepository {
+    async fn two_factor(&self, username: &str) -> Result<Option<TwoFactor>, MyError> {
+        let row = sqlx::query_as::<_, (String, bool, i64)>(
+            "SELECT secret, confirmed, lastStep FROM TWO_FACTOR WHERE username = ?"
+        )
+            .bind(username)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+        Ok(row.map(|(secret, confirmed, last_step)| TwoFactor { secret, confirmed, last_step }))
+    }
+
+    async fn set_pending_secret(&self, username: &str, secret: &str) -> Result<bool, MyError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO TWO_FACTOR (username, secret) VALUES (?, ?)
+            ON CONFLICT (username) DO UPDATE SET secret = excluded.secret, lastStep = 0, createdAt = CURRENT_TIMESTAMP
+            WHERE NOT TWO_FACTOR.confirmed
+            "#
+        )
+            .bind(username)
+            .bind(secret)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn confirm(&self, username: &str, step: i64, code_hashes: &[String]) -> Result<bool, MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        let result = sqlx::query("UPDATE TWO_FACTOR SET confirmed = 1, lastStep = ? WHERE username = ? AND NOT confirmed")
+            .bind(step)
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("DELETE FROM RECOVERY_CODE WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        for code_hash in code_hashes {
+            sqlx::query("INSERT INTO RECOVERY_CODE (username, codeHash) VALUES (?, ?)")
+                .bind(username)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await
+                .map_err(MyError::from)?;
+        }
+
+        tx.commit().await.map_err(MyError::from)?;
+        Ok(true)
+    }
+
+    async fn use_step(&self, username: &str, step: i64) -> Result<bool, MyError> {
+        let result = sqlx::query("UPDATE TWO_FACTOR SET lastStep = ? WHERE username = ? AND confirmed AND lastStep < ?")
+            .bind(step)
+            .bind(username)
+            .bind(step)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn use_recovery_code(&self, username: &str, code_hash: &str) -> Result<bool, MyError> {
+        let result = sqlx::query(
+            "UPDATE RECOVERY_CODE SET usedAt = CURRENT_TIMESTAMP WHERE username = ? AND codeHash = ? AND usedAt IS NULL"
+        )
+            .bind(username)
+            .bind(code_hash)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn disable(&self, username: &str) -> Result<(), MyError> {
+        let mut tx = self.writer.begin().await.map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM RECOVERY_CODE WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        sqlx::query("DELETE FROM TWO_FACTOR WHERE username = ?")
+            .bind(username)
+            .execute(&mut *tx)
+            .await
+            .map_err(MyError::from)?;
+
+        tx.commit().await.map_err(MyError::from)
+    }
+}
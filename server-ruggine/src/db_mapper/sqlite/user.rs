@@ -0,0 +1,281 @@
+use axum::async_trait;
+use sqlx::Row;
+use crate::db_mapper::sqlite::SqliteRepository;
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqliteRow;
+use crate::db_mapper::user::{fold_case, Avatar, NewUser, ProfileRecord, UpdateAccountRequest, UserMatch, UserRepository, UserSearch};
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::Locale;
+
+fn profile_from_row(row: &SqliteRow) -> Result<ProfileRecord, MyError> {
+    let locale: Option<String> = row.try_get("locale")?;
+    Ok(ProfileRecord {
+        username: row.try_get("username")?,
+        name: row.try_get("name")?,
+        surname: row.try_get("surname")?,
+        display_name: row.try_get("displayName")?,
+        bio: row.try_get("bio")?,
+        locale: locale.as_deref().and_then(Locale::from_tag),
+        discoverable: row.try_get("discoverable")?,
+        joined_at: row.try_get("createdAt")?,
+        avatar_updated_at: row.try_get("avatarUpdatedAt")?,
+    })
+}
+
+#[async_trait]
+impl UserRepository for SqliteRepository {
+    async fn insert_user(&self, user: &NewUser) -> Result<(), MyError> {
+        sqlx::query(
+            r#"
+            INSERT INTO USER (username, name, surname, foldedName, foldedSurname, password, createdAt)
+            VALUES (?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+            "#
+        )
+            .bind(&user.username)
+            .bind(&user.name)
+            .bind(&user.surname)
+            .bind(fold_case(&user.name))
+            .bind(fold_case(&user.surname))
+            .bind(&user.password_hash)
+            .execute(&self.writer)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => MyError::UsernameExists,
+                e => MyError::from(e),
+            })?;
+
+        Ok(())
+    }
+
+    async fn password_hash(&self, username: &str) -> Result<Option<String>, MyError> {
+        let row = sqlx::query("SELECT password FROM USER WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        match row {
+            Some(row) => Ok(Some(row.try_get("password")?)),
+            None => Ok(None), // Username not found
+        }
+    }
+
+    async fn update_password(&self, username: &str, password_hash: &str) -> Result<bool, MyError> {
+        let result = sqlx::query("UPDATE USER SET password = ? WHERE username = ?")
+            .bind(password_hash)
+            .bind(username)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn locale(&self, username: &str) -> Result<Option<Locale>, MyError> {
+        let locale = sqlx::query_scalar::<_, Option<String>>("SELECT locale FROM USER WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(locale.flatten().as_deref().and_then(Locale::from_tag))
+    }
+
+    async fn profile(&self, username: &str) -> Result<Option<ProfileRecord>, MyError> {
+        let row = sqlx::query(
+            r#"
+            SELECT u.username, u.name, u.surname, u.displayName, u.bio, u.locale, u.discoverable, u.createdAt,
+                a.updatedAt AS avatarUpdatedAt
+            FROM USER u
+            LEFT JOIN AVATAR a ON a.username = u.username
+            WHERE u.username = ?
+            "#
+        )
+            .bind(username)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        row.as_ref().map(profile_from_row).transpose()
+    }
+
+    async fn update_profile(&self, username: &str, changes: &UpdateAccountRequest) -> Result<bool, MyError> {
+        // Optional fields are only written when present in the request, so that `null` can clear them
+        let result = sqlx::query(
+            r#"
+            UPDATE USER SET
+                name = COALESCE(?, name),
+                foldedName = COALESCE(?, foldedName),
+                surname = COALESCE(?, surname),
+                foldedSurname = COALESCE(?, foldedSurname),
+                displayName = CASE WHEN ? THEN ? ELSE displayName END,
+                bio = CASE WHEN ? THEN ? ELSE bio END,
+                locale = CASE WHEN ? THEN ? ELSE locale END,
+                discoverable = COALESCE(?, discoverable)
+            WHERE username = ?
+            "#
+        )
+            .bind(&changes.name)
+            .bind(changes.name.as_deref().map(fold_case))
+            .bind(&changes.surname)
+            .bind(changes.surname.as_deref().map(fold_case))
+            .bind(changes.display_name.is_some())
+            .bind(changes.display_name.clone().flatten())
+            .bind(changes.bio.is_some())
+            .bind(changes.bio.clone().flatten())
+            .bind(changes.locale.is_some())
+            .bind(changes.locale.flatten().map(Locale::code))
+            .bind(changes.discoverable)
+            .bind(username)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn avatar(&self, username: &str) -> Result<Option<Avatar>, MyError> {
+        let row = sqlx::query("SELECT image, updatedAt FROM AVATAR WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        match row {
+            Some(row) => Ok(Some(Avatar { image: row.try_get("image")?, updated_at: row.try_get("updatedAt")? })),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_avatar(&self, username: &str, image: &[u8]) -> Result<(), MyError> {
+        sqlx::query(
+            r#"
+            INSERT INTO AVATAR (username, image, updatedAt) VALUES (?, ?, CURRENT_TIMESTAMP)
+            ON CONFLICT (username) DO UPDATE SET image = excluded.image, updatedAt = excluded.updatedAt
+            "#
+        )
+            .bind(username)
+            .bind(image)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(())
+    }
+
+    async fn delete_avatar(&self, username: &str) -> Result<bool, MyError> {
+        let result = sqlx::query("DELETE FROM AVATAR WHERE username = ?")
+            .bind(username)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn search(&self, caller: &str, search: &UserSearch, limit: i64, offset: i64) -> Result<Vec<UserMatch>, MyError> {
+        // Rank of the match: exact username, username prefix, name or surname prefix, substring, characters in order
+        let rows = sqlx::query(
+            r#"
+            WITH SHARED AS (
+                SELECT other.username, COUNT(*) AS sharedChats
+                FROM USERS_JOINED mine
+                JOIN USERS_JOINED other ON other.chatId = mine.chatId AND other.username <> mine.username
+                WHERE mine.username = ?1
+                GROUP BY other.username
+            )
+            SELECT u.username, u.name, u.surname, u.displayName, u.bio, u.locale, u.discoverable, u.createdAt,
+                a.updatedAt AS avatarUpdatedAt, COALESCE(s.sharedChats, 0) AS sharedChats
+            FROM USER u
+            LEFT JOIN SHARED s ON s.username = u.username
+            LEFT JOIN AVATAR a ON a.username = u.username
+            WHERE u.username <> ?1
+              AND (u.discoverable OR s.username IS NOT NULL)
+              AND NOT EXISTS (
+                  SELECT 1 FROM BLOCKED_USER b
+                  WHERE (b.blocker = ?1 AND b.blocked = u.username) OR (b.blocker = u.username AND b.blocked = ?1)
+              )
+              AND (lower(u.username) LIKE ?5 ESCAPE '\' OR u.foldedName LIKE ?5 ESCAPE '\' OR u.foldedSurname LIKE ?5 ESCAPE '\'
+                   OR (u.foldedName || ' ' || u.foldedSurname) LIKE ?5 ESCAPE '\')
+            ORDER BY
+                s.username IS NULL,
+                CASE
+                    WHEN lower(u.username) = ?2 THEN 0
+                    WHEN lower(u.username) LIKE ?3 ESCAPE '\' THEN 1
+                    WHEN u.foldedName LIKE ?3 ESCAPE '\' OR u.foldedSurname LIKE ?3 ESCAPE '\'
+                         OR (u.foldedName || ' ' || u.foldedSurname) LIKE ?3 ESCAPE '\' THEN 2
+                    WHEN lower(u.username) LIKE ?4 ESCAPE '\' OR u.foldedName LIKE ?4 ESCAPE '\'
+                         OR u.foldedSurname LIKE ?4 ESCAPE '\' THEN 3
+                    ELSE 4
+                END,
+                sharedChats DESC,
+                lower(u.username)
+            LIMIT ?6 OFFSET ?7
+            "#
+        )
+            .bind(caller)
+            .bind(&search.exact)
+            .bind(&search.prefix)
+            .bind(&search.contains)
+            .bind(&search.fuzzy)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)?;
+
+        rows.iter()
+            .map(|row| Ok(UserMatch { profile: profile_from_row(row)?, shared_chats: row.try_get("sharedChats")? }))
+            .collect()
+    }
+
+    async fn set_blocked(&self, blocker: &str, blocked: &str, is_blocked: bool) -> Result<bool, MyError> {
+        let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM USER WHERE username = ?")
+            .bind(blocked)
+            .fetch_one(&self.reader)
+            .await
+            .map_err(MyError::from)?
+            > 0;
+        if !exists {
+            return Ok(false);
+        }
+
+        let query = if is_blocked {
+            "INSERT INTO BLOCKED_USER (blocker, blocked) VALUES (?, ?) ON CONFLICT DO NOTHING"
+        } else {
+            "DELETE FROM BLOCKED_USER WHERE blocker = ? AND blocked = ?"
+        };
+        sqlx::query(query)
+            .bind(blocker)
+            .bind(blocked)
+            .execute(&self.writer)
+            .await
+            .map_err(MyError::from)?;
+
+        Ok(true)
+    }
+
+    async fn blocked_users(&self, username: &str) -> Result<Vec<String>, MyError> {
+        sqlx::query_scalar::<_, String>("SELECT blocked FROM BLOCKED_USER WHERE blocker = ? ORDER BY blocked")
+            .bind(username)
+            .fetch_all(&self.reader)
+            .await
+            .map_err(MyError::from)
+    }
+}
+
+// Sets the folded names of the users created before they were stored
+pub(super) async fn fold_names(writer: &SqlitePool) -> Result<(), sqlx::Error> {
+    let users = sqlx::query("SELECT username, name, surname FROM USER WHERE foldedName IS NULL OR foldedSurname IS NULL")
+        .fetch_all(writer)
+        .await?;
+    for user in users {
+        sqlx::query("UPDATE USER SET foldedName = ?, foldedSurname = ? WHERE username = ?")
+            .bind(fold_case(user.try_get("name")?))
+            .bind(fold_case(user.try_get("surname")?))
+            .bind(user.try_get::<String, _>("username")?)
+            .execute(writer)
+            .await?;
+    }
+    Ok(())
+}
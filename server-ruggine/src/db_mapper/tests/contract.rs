@@ -0,0 +1,468 @@
+// Behaviour both backends must share, checked through the repository traits only
+use std::collections::HashMap;
+use serde_json::{json, Value};
+use crate::db_mapper::message::SystemEvent;
+use crate::db_mapper::tests::TestDb;
+use crate::db_mapper::two_factor;
+use crate::db_mapper::user::{self, NewUser, UpdateAccountRequest, UserSearchPage, UserSearchQuery};
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::Locale;
+use crate::utilities::totp;
+
+backend_tests!(
+    users_are_stored_once,
+    profiles_are_updated_field_by_field,
+    private_chats_are_created_once,
+    groups_invite_existing_users,
+    invitations_are_accepted_or_declined,
+    messages_are_deduplicated_and_ordered,
+    missed_messages_follow_the_cutoffs,
+    groups_are_deleted_with_their_last_member,
+    timestamps_are_utc,
+    two_factor_codes_are_used_once,
+    pending_sign_ins_are_not_authenticated,
+    directory_ranks_the_matches,
+    directory_is_paginated,
+    directory_hides_blocked_users,
+    hidden_users_are_found_by_their_chat_partners,
+);
+
+async fn users_are_stored_once(t: &TestDb) {
+    t.add_users(&["alice"]).await;
+    let again = NewUser {
+        username: "alice".to_string(),
+        name: "Other".to_string(),
+        surname: "User".to_string(),
+        password_hash: "x".to_string(),
+    };
+    assert!(matches!(t.db.users.insert_user(&again).await, Err(MyError::UsernameExists)));
+
+    assert_eq!(t.db.users.password_hash("alice").await.unwrap().as_deref(), Some("not a real hash"));
+    assert_eq!(t.db.users.password_hash("nobody").await.unwrap(), None);
+
+    assert!(t.db.users.update_password("alice", "new hash").await.unwrap());
+    assert!(!t.db.users.update_password("nobody", "new hash").await.unwrap());
+    assert_eq!(t.db.users.password_hash("alice").await.unwrap().as_deref(), Some("new hash"));
+}
+
+async fn profiles_are_updated_field_by_field(t: &TestDb) {
+    t.add_users(&["alice"]).await;
+    let profile = t.db.users.profile("alice").await.unwrap().expect("profile");
+    assert_eq!((profile.name.as_str(), profile.display_name, profile.locale), ("Test", None, None));
+    assert!(profile.discoverable);
+    assert!(t.db.users.profile("nobody").await.unwrap().is_none());
+
+    let changes = UpdateAccountRequest {
+        name: None,
+        surname: Some("Rossi".to_string()),
+        display_name: Some(Some("Ali".to_string())),
+        bio: Some(Some("Hello".to_string())),
+        locale: Some(Some(Locale::It)),
+        discoverable: Some(false),
+    };
+    assert!(t.db.users.update_profile("alice", &changes).await.unwrap());
+    let profile = t.db.users.profile("alice").await.unwrap().expect("profile");
+    assert_eq!(profile.name, "Test");
+    assert_eq!(profile.surname, "Rossi");
+    assert_eq!(profile.display_name.as_deref(), Some("Ali"));
+    assert_eq!(profile.bio.as_deref(), Some("Hello"));
+    assert_eq!(profile.locale, Some(Locale::It));
+    assert!(!profile.discoverable);
+    assert_eq!(t.db.users.locale("alice").await.unwrap(), Some(Locale::It));
+
+    // `null` clears, missing fields are kept
+    let changes = UpdateAccountRequest {
+        name: None,
+        surname: None,
+        display_name: Some(None),
+        bio: None,
+        locale: Some(None),
+        discoverable: None,
+    };
+    assert!(t.db.users.update_profile("alice", &changes).await.unwrap());
+    let profile = t.db.users.profile("alice").await.unwrap().expect("profile");
+    assert_eq!((profile.display_name, profile.bio.as_deref(), profile.locale), (None, Some("Hello"), None));
+    assert!(!t.db.users.update_profile("nobody", &changes).await.unwrap());
+}
+
+async fn private_chats_are_created_once(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let started = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "bob".to_string() };
+
+    let (chat_id, message) = t.db.chats.create_private_chat("alice", "bob", &started).await.unwrap();
+    let message = message.expect("creation message");
+    assert_eq!((message.chat_id, message.from_user.as_str(), message.is_auto), (chat_id, "alice", true));
+    assert_eq!(message.event, Some(started.clone()));
+    assert_eq!(message.msg, "alice started a private chat with bob");
+    assert_eq!(message.client_id, None);
+
+    // Either user finds the same chat
+    let (again, message) = t.db.chats.create_private_chat("bob", "alice", &started).await.unwrap();
+    assert_eq!((again, message.is_none()), (chat_id, true));
+    assert!(matches!(t.db.chats.create_private_chat("alice", "nobody", &started).await, Err(MyError::UserNotFound)));
+
+    let chats = t.db.chats.user_chats("alice").await.unwrap();
+    assert_eq!(chats.len(), 1);
+    assert_eq!((chats[0].id, chats[0].is_group, chats[0].name.as_deref()), (chat_id, false, None));
+    assert_eq!(chats[0].participants, vec!["bob".to_string()]);
+
+    let mut participants = t.db.chats.participants(chat_id).await.unwrap();
+    participants.sort();
+    assert_eq!(participants, vec!["alice".to_string(), "bob".to_string()]);
+}
+
+async fn groups_invite_existing_users(t: &TestDb) {
+    t.add_users(&["alice", "bob", "carol"]).await;
+    let created = SystemEvent::GroupCreated { user: "alice".to_string() };
+    let invitees = vec!["bob".to_string(), "ghost".to_string()];
+
+    let (chat_id, message) = t.db.chats.create_group(Some("Team"), true, "alice", &invitees, &created).await.unwrap();
+    assert_eq!((message.chat_id, message.event), (chat_id, Some(created)));
+    assert_eq!(t.db.chats.participants(chat_id).await.unwrap(), vec!["alice".to_string()]);
+
+    let requests = t.db.requests.user_requests("bob").await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!((requests[0].chat_id, requests[0].from.as_str(), requests[0].name.as_str()), (chat_id, "alice", "Team"));
+
+    // Unknown users are reported, and fail the call when nobody else is invited
+    let not_found = t.db.requests.insert_requests(chat_id, "alice", &["carol".to_string(), "ghost".to_string()]).await.unwrap();
+    assert_eq!(not_found, vec!["ghost".to_string()]);
+    match t.db.requests.insert_requests(chat_id, "alice", &["ghost".to_string()]).await {
+        Err(MyError::UsersNotFound(users)) => assert_eq!(users, vec!["ghost".to_string()]),
+        other => panic!("expected UsersNotFound, got {:?}", other),
+    }
+
+    // Only members invite
+    assert!(t.db.requests.insert_requests(chat_id, "carol", &["bob".to_string()]).await.is_err());
+    assert!(matches!(
+        t.db.requests.insert_requests(chat_id, "alice", &["alice".to_string()]).await,
+        Err(MyError::UserAlreadyInGroup)
+    ));
+}
+
+async fn invitations_are_accepted_or_declined(t: &TestDb) {
+    t.add_users(&["alice", "bob", "carol"]).await;
+    let created = SystemEvent::GroupCreated { user: "alice".to_string() };
+    let invitees = vec!["bob".to_string(), "carol".to_string()];
+    let (chat_id, _) = t.db.chats.create_group(Some("Team"), true, "alice", &invitees, &created).await.unwrap();
+
+    let joined = SystemEvent::MemberJoined { user: "bob".to_string() };
+    let message = t.db.requests.accept_request(chat_id, "bob", &joined).await.unwrap();
+    assert_eq!((message.from_user.as_str(), message.event), ("bob", Some(joined.clone())));
+    assert!(t.db.requests.user_requests("bob").await.unwrap().is_empty());
+    assert!(matches!(t.db.requests.accept_request(chat_id, "bob", &joined).await, Err(MyError::RequestNotFound)));
+
+    assert!(t.db.requests.delete_request(chat_id, "carol").await.unwrap());
+    assert!(!t.db.requests.delete_request(chat_id, "carol").await.unwrap());
+
+    let mut participants = t.db.chats.participants(chat_id).await.unwrap();
+    participants.sort();
+    assert_eq!(participants, vec!["alice".to_string(), "bob".to_string()]);
+}
+
+async fn messages_are_deduplicated_and_ordered(t: &TestDb) {
+    t.add_users(&["alice", "bob", "carol"]).await;
+    let started = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "bob".to_string() };
+    let (chat_id, _) = t.db.chats.create_private_chat("alice", "bob", &started).await.unwrap();
+
+    let (first, created) = t.db.messages.insert_message(chat_id, "alice", "hi", Some("c-1")).await.unwrap();
+    assert!(created);
+    assert_eq!((first.msg.as_str(), first.is_auto, first.event.as_ref()), ("hi", false, None));
+    assert_eq!(first.client_id.as_deref(), Some("c-1"));
+
+    // A retry returns the stored message
+    let (retried, created) = t.db.messages.insert_message(chat_id, "alice", "hi", Some("c-1")).await.unwrap();
+    assert!(!created);
+    assert_eq!((retried.id, retried.send_at), (first.id, first.send_at));
+
+    // Reusing the client id for another text or chat is refused
+    assert!(matches!(
+        t.db.messages.insert_message(chat_id, "alice", "hi again", Some("c-1")).await,
+        Err(MyError::DuplicateMessage)
+    ));
+    let with_carol = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "carol".to_string() };
+    let (carol_chat, _) = t.db.chats.create_private_chat("alice", "carol", &with_carol).await.unwrap();
+    assert!(matches!(
+        t.db.messages.insert_message(carol_chat, "alice", "hi", Some("c-1")).await,
+        Err(MyError::DuplicateMessage)
+    ));
+    // Client ids belong to their sender
+    let (other, created) = t.db.messages.insert_message(chat_id, "bob", "hi", Some("c-1")).await.unwrap();
+    assert!(created && other.id != first.id);
+
+    let (second, _) = t.db.messages.insert_message(chat_id, "bob", "hello", None).await.unwrap();
+    assert!(second.id > other.id);
+    assert!(matches!(
+        t.db.messages.insert_message(chat_id, "carol", "intruder", None).await,
+        Err(MyError::UserDoesNotBelongToGroup)
+    ));
+
+    let messages = t.db.messages.chat_messages(chat_id, "bob").await.unwrap();
+    let texts: Vec<&str> = messages.iter().map(|m| m.msg.as_str()).collect();
+    assert_eq!(texts, vec!["alice started a private chat with bob", "hi", "hi", "hello"]);
+    assert!(messages.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert!(matches!(t.db.messages.chat_messages(chat_id, "carol").await, Err(MyError::UserDoesNotBelongToGroup)));
+}
+
+async fn missed_messages_follow_the_cutoffs(t: &TestDb) {
+    t.add_users(&["alice", "bob", "carol"]).await;
+    let with_bob = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "bob".to_string() };
+    let with_carol = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "carol".to_string() };
+    let (bob_chat, _) = t.db.chats.create_private_chat("alice", "bob", &with_bob).await.unwrap();
+    let (carol_chat, _) = t.db.chats.create_private_chat("alice", "carol", &with_carol).await.unwrap();
+
+    let mut ids = Vec::new();
+    for (chat_id, sender) in [(bob_chat, "bob"), (carol_chat, "carol"), (bob_chat, "bob"), (carol_chat, "carol")] {
+        ids.push(t.db.messages.insert_message(chat_id, sender, "m", None).await.unwrap().0.id);
+    }
+
+    // Everything after the global cutoff, across chats, oldest first
+    let since = t.db.messages.messages_since("alice", ids[0], &HashMap::new(), 10).await.unwrap();
+    assert_eq!(since.iter().map(|m| m.id).collect::<Vec<_>>(), ids[1..].to_vec());
+
+    // A per-chat cutoff overrides the global one for that chat only
+    let chats = HashMap::from([(carol_chat, ids[3])]);
+    let since = t.db.messages.messages_since("alice", 0, &chats, 10).await.unwrap();
+    assert!(since.iter().all(|m| m.chat_id == bob_chat));
+    assert_eq!(since.len(), 3);
+
+    // Only the user's chats, and at most `limit`
+    let since = t.db.messages.messages_since("bob", 0, &HashMap::new(), 2).await.unwrap();
+    assert_eq!(since.len(), 2);
+    assert!(since.iter().all(|m| m.chat_id == bob_chat));
+}
+
+async fn groups_are_deleted_with_their_last_member(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let created = SystemEvent::GroupCreated { user: "alice".to_string() };
+    let (chat_id, _) = t.db.chats.create_group(Some("Team"), true, "alice", &["bob".to_string()], &created).await.unwrap();
+    t.db.requests.accept_request(chat_id, "bob", &SystemEvent::MemberJoined { user: "bob".to_string() }).await.unwrap();
+
+    let left = SystemEvent::MemberLeft { user: "alice".to_string() };
+    let (message, still_exists) = t.db.chats.leave_group(chat_id, "alice", &left).await.unwrap();
+    assert!(still_exists);
+    assert_eq!((message.from_user.as_str(), message.event), ("alice", Some(left.clone())));
+    assert!(matches!(t.db.chats.leave_group(chat_id, "alice", &left).await, Err(MyError::UserDoesNotBelongToGroup)));
+
+    let left = SystemEvent::MemberLeft { user: "bob".to_string() };
+    let (_, still_exists) = t.db.chats.leave_group(chat_id, "bob", &left).await.unwrap();
+    assert!(!still_exists);
+    assert_eq!(t.chat_rows().await, (0, 0, 0));
+    assert!(matches!(t.db.chats.leave_group(chat_id, "bob", &left).await, Err(MyError::ChatNotFound)));
+
+    // Private chats cannot be left
+    let started = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "bob".to_string() };
+    let (private, _) = t.db.chats.create_private_chat("alice", "bob", &started).await.unwrap();
+    assert!(matches!(t.db.chats.leave_group(private, "alice", &left).await, Err(MyError::ChatNotFound)));
+}
+
+pub(super) async fn timestamps_are_utc(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let before = chrono::Utc::now() - chrono::Duration::seconds(2);
+    let started = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "bob".to_string() };
+    let (chat_id, _) = t.db.chats.create_private_chat("alice", "bob", &started).await.unwrap();
+    let (message, _) = t.db.messages.insert_message(chat_id, "alice", "hi", None).await.unwrap();
+    let after = chrono::Utc::now() + chrono::Duration::seconds(2);
+
+    let chat = &t.db.chats.user_chats("alice").await.unwrap()[0];
+    let joined = t.db.users.profile("alice").await.unwrap().expect("profile").joined_at;
+    for time in [message.send_at, chat.created_at, joined] {
+        assert!(before <= time && time <= after, "{} is not the current UTC time", time);
+    }
+    let json = serde_json::to_value(&message).unwrap();
+    assert!(json["send_at"].as_str().unwrap().ends_with('Z'));
+}
+
+async fn two_factor_codes_are_used_once(t: &TestDb) {
+    t.add_users(&["alice"]).await;
+    let enrollment = two_factor::enroll(&t.db, &Config::default(), "alice").await.unwrap();
+    assert!(!two_factor::is_enabled(&t.db, "alice").await.unwrap());
+
+    let step = totp::current_step();
+    let code = totp::code(&enrollment.secret, step);
+    let recovery = two_factor::confirm(&t.db, "alice", &code).await.unwrap().recovery_codes;
+    assert_eq!(recovery.len(), 10);
+    assert!(two_factor::is_enabled(&t.db, "alice").await.unwrap());
+    assert!(matches!(two_factor::enroll(&t.db, &Config::default(), "alice").await, Err(MyError::TwoFactorAlreadyEnabled)));
+
+    // The code that enabled 2FA, and the older ones, cannot be replayed
+    assert!(!two_factor::verify_code(&t.db, "alice", &code).await.unwrap());
+    assert!(t.db.two_factor.use_step("alice", step as i64 + 1).await.unwrap());
+    assert!(!t.db.two_factor.use_step("alice", step as i64 + 1).await.unwrap());
+    assert!(!t.db.two_factor.use_step("alice", step as i64).await.unwrap());
+
+    // Each recovery code works once, however it is typed
+    assert!(two_factor::verify_code(&t.db, "alice", &recovery[0]).await.unwrap());
+    assert!(!two_factor::verify_code(&t.db, "alice", &recovery[0]).await.unwrap());
+    assert!(two_factor::verify_code(&t.db, "alice", &recovery[1].replace('-', "").to_uppercase()).await.unwrap());
+    assert!(!two_factor::verify_code(&t.db, "alice", "0000-0000-0000-0000").await.unwrap());
+
+    two_factor::disable(&t.db, "alice", &recovery[2]).await.unwrap();
+    assert!(!two_factor::is_enabled(&t.db, "alice").await.unwrap());
+    assert!(!two_factor::verify_code(&t.db, "alice", &recovery[3]).await.unwrap());
+}
+
+async fn pending_sign_ins_are_not_authenticated(t: &TestDb) {
+    let url = t.serve().await;
+    let client = || reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let credentials = json!({"username": "alice", "password": "Passw0rd!x"});
+
+    // Alice enables 2FA
+    let alice = client();
+    let created = alice
+        .post(format!("{}/users", url))
+        .json(&json!({"username": "alice", "name": "Alice", "surname": "Rossi", "password": "Passw0rd!x"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(created.status(), 201);
+    assert_eq!(alice.post(format!("{}/login", url)).json(&credentials).send().await.unwrap().status(), 200);
+    let enrollment: Value = alice.post(format!("{}/me/2fa", url)).send().await.unwrap().json().await.unwrap();
+    let secret = enrollment["secret"].as_str().unwrap().to_string();
+    let step = totp::current_step();
+    let confirmed = alice
+        .post(format!("{}/me/2fa/confirm", url))
+        .json(&json!({"code": totp::code(&secret, step)}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(confirmed.status(), 200);
+
+    // A new sign-in waits for the second factor: its session is refused everywhere
+    let device = client();
+    assert_eq!(device.post(format!("{}/login", url)).json(&credentials).send().await.unwrap().status(), 202);
+    let me = device.get(format!("{}/me", url)).send().await.unwrap();
+    assert_eq!(me.status(), 401);
+    let error: Value = me.json().await.unwrap();
+    assert_eq!(error["error"]["code"], "unauthenticated");
+    assert_eq!(websocket_upgrade(&device, &url).await, 401);
+
+    // Until the code is given
+    let completed = device
+        .post(format!("{}/login/2fa", url))
+        .json(&json!({"code": totp::code(&secret, step + 1)}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(completed.status(), 200);
+    assert_eq!(device.get(format!("{}/me", url)).send().await.unwrap().status(), 200);
+    assert_eq!(websocket_upgrade(&device, &url).await, 101);
+}
+
+// Status of the answer to a WebSocket handshake
+async fn websocket_upgrade(client: &reqwest::Client, url: &str) -> u16 {
+    client
+        .get(format!("{}/ws", url))
+        .header("connection", "upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn add_person(t: &TestDb, username: &str, name: &str, surname: &str) {
+    let user = NewUser {
+        username: username.to_string(),
+        name: name.to_string(),
+        surname: surname.to_string(),
+        password_hash: "not a real hash".to_string(),
+    };
+    t.db.users.insert_user(&user).await.expect("create the user");
+}
+
+async fn search_page(t: &TestDb, caller: &str, q: &str, limit: i64, offset: i64) -> UserSearchPage {
+    let query = UserSearchQuery { q: q.to_string(), limit: Some(limit), offset: Some(offset) };
+    user::search_users(&t.db, caller, &query).await.unwrap()
+}
+
+// Usernames found by `caller`, in order
+async fn found(t: &TestDb, caller: &str, q: &str) -> Vec<String> {
+    search_page(t, caller, q, 50, 0).await.users.into_iter().map(|found| found.profile.username).collect()
+}
+
+async fn directory_ranks_the_matches(t: &TestDb) {
+    t.add_users(&["alice", "rossana"]).await;
+    add_person(t, "r1o2s3s", "Test", "User").await;
+    add_person(t, "marco", "Marco", "Derossi").await;
+    add_person(t, "bianchi", "Paolo", "Rossi").await;
+    add_person(t, "ross", "Test", "User").await;
+
+    // Exact username, username prefix, name or surname prefix, substring, characters in order
+    assert_eq!(found(t, "alice", "Ross").await, ["ross", "rossana", "bianchi", "marco", "r1o2s3s"]);
+    assert_eq!(found(t, "alice", "paolo ro").await, ["bianchi"]);
+    assert!(found(t, "alice", "100%").await.is_empty());
+
+    // Users sharing a chat come first
+    let started = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "r1o2s3s".to_string() };
+    t.db.chats.create_private_chat("alice", "r1o2s3s", &started).await.unwrap();
+    let page = search_page(t, "alice", "ross", 50, 0).await;
+    let ranked: Vec<_> = page.users.iter().map(|found| (found.profile.username.as_str(), found.shared_chats)).collect();
+    assert_eq!(ranked, [("r1o2s3s", 1), ("ross", 0), ("rossana", 0), ("bianchi", 0), ("marco", 0)]);
+
+    // Letters outside ASCII are folded in the same way by both backends
+    add_person(t, "ada", "Élise", "Ünal").await;
+    assert_eq!(found(t, "alice", "élise").await, ["ada"]);
+    assert_eq!(found(t, "alice", "ÉLISE ü").await, ["ada"]);
+    assert_eq!(found(t, "alice", "ünal").await, ["ada"]);
+    assert!(found(t, "alice", "elise").await.is_empty());
+}
+
+async fn directory_is_paginated(t: &TestDb) {
+    t.add_users(&["alice", "user5", "user3", "user1", "user4", "user2"]).await;
+
+    let page = search_page(t, "alice", "user", 2, 0).await;
+    let usernames: Vec<_> = page.users.iter().map(|found| found.profile.username.as_str()).collect();
+    assert_eq!((usernames, page.next_offset), (vec!["user1", "user2"], Some(2)));
+    let page = search_page(t, "alice", "user", 2, 2).await;
+    let usernames: Vec<_> = page.users.iter().map(|found| found.profile.username.as_str()).collect();
+    assert_eq!((usernames, page.next_offset), (vec!["user3", "user4"], Some(4)));
+    let page = search_page(t, "alice", "user", 2, 4).await;
+    let usernames: Vec<_> = page.users.iter().map(|found| found.profile.username.as_str()).collect();
+    assert_eq!((usernames, page.next_offset), (vec!["user5"], None));
+    assert!(search_page(t, "alice", "user", 2, 6).await.users.is_empty());
+}
+
+async fn directory_hides_blocked_users(t: &TestDb) {
+    t.add_users(&["alice", "bob", "bobby"]).await;
+
+    // Neither the blocker nor the blocked user finds the other
+    user::block_user(&t.db, "alice", "bob").await.unwrap();
+    user::block_user(&t.db, "bobby", "alice").await.unwrap();
+    assert!(found(t, "alice", "bob").await.is_empty());
+    assert!(found(t, "bob", "alice").await.is_empty());
+    assert!(found(t, "bobby", "alice").await.is_empty());
+    assert_eq!(found(t, "bob", "bobby").await, ["bobby"]);
+    assert_eq!(user::get_blocked_users(&t.db, "alice").await.unwrap(), ["bob"]);
+
+    user::unblock_user(&t.db, "alice", "bob").await.unwrap();
+    assert_eq!(found(t, "alice", "bob").await, ["bob"]);
+    assert!(user::get_blocked_users(&t.db, "alice").await.unwrap().is_empty());
+
+    assert!(!t.db.users.set_blocked("alice", "nobody", true).await.unwrap());
+    assert!(!t.db.users.set_blocked("alice", "nobody", false).await.unwrap());
+    assert!(matches!(user::block_user(&t.db, "alice", "nobody").await, Err(MyError::UserNotFound)));
+    assert!(matches!(user::block_user(&t.db, "alice", "alice").await, Err(MyError::CannotBlockYourself)));
+}
+
+async fn hidden_users_are_found_by_their_chat_partners(t: &TestDb) {
+    t.add_users(&["alice", "bob", "carol"]).await;
+    let changes = UpdateAccountRequest {
+        name: None,
+        surname: None,
+        display_name: None,
+        bio: None,
+        locale: None,
+        discoverable: Some(false),
+    };
+    assert!(t.db.users.update_profile("bob", &changes).await.unwrap());
+
+    let started = SystemEvent::PrivateChatStarted { user: "carol".to_string(), with: "bob".to_string() };
+    t.db.chats.create_private_chat("carol", "bob", &started).await.unwrap();
+    assert!(found(t, "alice", "bob").await.is_empty());
+    assert_eq!(found(t, "carol", "bob").await, ["bob"]);
+}
@@ -0,0 +1,194 @@
+// Tests of the repositories, written once against the traits and run on both backends:
+// SQLite on a temporary file, and PostgreSQL on a fresh database created on the server named by
+// `RUGGINE_TEST_DATABASE_URL` (e.g. `postgres://postgres@localhost:5432/postgres`).
+// Without that variable the PostgreSQL runs are skipped with a notice; CI always sets it.
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use sqlx::{Connection, Executor, PgConnection, PgPool, SqlitePool};
+use crate::db_mapper::Repositories;
+use crate::db_mapper::postgres::PostgresRepository;
+use crate::db_mapper::sqlite::SqliteRepository;
+use crate::db_mapper::user::NewUser;
+use crate::events::hub::Hub;
+use crate::routes::{api_cors, create_routes};
+use crate::state::AppState;
+use crate::utilities::config::Config;
+use crate::utilities::notifier::OutboxNotifier;
+use crate::utilities::rate_limit::RateLimiter;
+
+// Generates, for each listed `async fn(&TestDb)`, one test per backend
+macro_rules! backend_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    let db = $crate::db_mapper::tests::TestDb::sqlite().await;
+                    super::$name(&db).await;
+                    db.close().await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    let Some(db) = $crate::db_mapper::tests::TestDb::postgres().await else {
+                        return;
+                    };
+                    super::$name(&db).await;
+                    db.close().await;
+                }
+            )*
+        }
+    };
+}
+
+mod contract;
+mod transactions;
+mod timezones;
+
+// Direct access to the database under test, to set up and inspect what the repositories cannot
+pub enum Raw {
+    Sqlite { pool: SqlitePool, path: PathBuf },
+    Postgres { pool: PgPool, admin_url: String, name: String },
+}
+
+pub struct TestDb {
+    pub db: Repositories,
+    pub raw: Raw,
+}
+
+impl TestDb {
+    pub async fn sqlite() -> Self {
+        let path = std::env::temp_dir().join(format!("ruggine-test-{}.db", unique_suffix()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let backend = SqliteRepository::connect(&url, &Config::default()).await.expect("open the SQLite database");
+        let pool = SqlitePool::connect(&url).await.expect("open the SQLite database");
+        TestDb { db: Repositories::from_backend(backend), raw: Raw::Sqlite { pool, path } }
+    }
+
+    // None when no PostgreSQL server is configured
+    pub async fn postgres() -> Option<Self> {
+        let Ok(admin_url) = std::env::var("RUGGINE_TEST_DATABASE_URL") else {
+            eprintln!("RUGGINE_TEST_DATABASE_URL is not set: skipping the PostgreSQL run");
+            return None;
+        };
+        let name = format!("ruggine_test_{}", unique_suffix());
+        let mut admin = PgConnection::connect(&admin_url).await.expect("connect to the PostgreSQL server");
+        admin.execute(format!("CREATE DATABASE {}", name).as_str()).await.expect("create the test database");
+
+        let url = database_url(&admin_url, &name);
+        let backend = PostgresRepository::connect(&url, &Config::default()).await.expect("open the PostgreSQL database");
+        let pool = PgPool::connect(&url).await.expect("open the PostgreSQL database");
+        Some(TestDb { db: Repositories::from_backend(backend), raw: Raw::Postgres { pool, admin_url, name } })
+    }
+
+    // Runs a query returning a single number, written for each backend
+    pub async fn scalar(&self, sqlite: &str, postgres: &str) -> i64 {
+        match &self.raw {
+            Raw::Sqlite { pool, .. } => sqlx::query_scalar(sqlite).fetch_one(pool).await.expect("run the SQLite query"),
+            Raw::Postgres { pool, .. } => sqlx::query_scalar(postgres).fetch_one(pool).await.expect("run the PostgreSQL query"),
+        }
+    }
+
+    // Runs a statement (or several) written for each backend
+    pub async fn execute(&self, sqlite: &str, postgres: &str) {
+        match &self.raw {
+            Raw::Sqlite { pool, .. } => pool.execute(sqlite).await.map(drop).expect("run the SQLite statement"),
+            Raw::Postgres { pool, .. } => pool.execute(postgres).await.map(drop).expect("run the PostgreSQL statement"),
+        }
+    }
+
+    // Makes every following `operation` (INSERT, UPDATE or DELETE) on the table fail
+    pub async fn fail_on(&self, operation: &str, sqlite_table: &str, postgres_table: &str) {
+        let sqlite = format!(
+            "CREATE TRIGGER injected_failure BEFORE {} ON {} BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+            operation, sqlite_table
+        );
+        let postgres = format!(
+            "CREATE FUNCTION injected_failure() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN RAISE EXCEPTION 'injected failure'; END $$;
+             CREATE TRIGGER injected_failure BEFORE {} ON {} FOR EACH ROW EXECUTE FUNCTION injected_failure()",
+            operation, postgres_table
+        );
+        self.execute(&sqlite, &postgres).await;
+    }
+
+    // Rows of the chats, their members and their messages
+    pub async fn chat_rows(&self) -> (i64, i64, i64) {
+        (
+            self.scalar("SELECT COUNT(*) FROM CHAT", "SELECT COUNT(*) FROM chats").await,
+            self.scalar("SELECT COUNT(*) FROM USERS_JOINED", "SELECT COUNT(*) FROM users_joined").await,
+            self.scalar("SELECT COUNT(*) FROM MESSAGE", "SELECT COUNT(*) FROM messages").await,
+        )
+    }
+
+    pub async fn add_users(&self, usernames: &[&str]) {
+        for username in usernames {
+            let user = NewUser {
+                username: username.to_string(),
+                name: "Test".to_string(),
+                surname: "User".to_string(),
+                password_hash: "not a real hash".to_string(),
+            };
+            self.db.users.insert_user(&user).await.expect("create the user");
+        }
+    }
+
+    // Serves the API backed by this database on a free local port, returning its base url.
+    // Passwords are hashed with cheap Argon2 parameters, to keep the tests fast
+    pub async fn serve(&self) -> String {
+        let config = Config { argon2_memory_kib: 1024, argon2_iterations: 1, ..Config::default() };
+        let outbox = std::env::temp_dir().join(format!("ruggine-test-{}-outbox.jsonl", unique_suffix()));
+        let state = AppState {
+            db: self.db.clone(),
+            events: Arc::new(Hub::new(self.db.chats.clone(), config.ws_queue_capacity, config.ws_overflow_policy)),
+            limiter: Arc::new(RateLimiter::new(&config)),
+            notifier: Arc::new(OutboxNotifier::new(outbox)),
+            config: Arc::new(config),
+        };
+        let app = create_routes(state, api_cors()).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind a local port");
+        let addr = listener.local_addr().expect("local address");
+        tokio::spawn(axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).into_future());
+        format!("http://{}", addr)
+    }
+
+    // Removes the database. Left behind when a test fails, to be inspected
+    pub async fn close(self) {
+        drop(self.db);
+        match self.raw {
+            Raw::Sqlite { pool, path } => {
+                pool.close().await;
+                for suffix in ["", "-wal", "-shm"] {
+                    let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+                }
+            }
+            Raw::Postgres { pool, admin_url, name } => {
+                pool.close().await;
+                let mut admin = PgConnection::connect(&admin_url).await.expect("connect to the PostgreSQL server");
+                admin.execute(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name).as_str()).await.expect("drop the test database");
+            }
+        }
+    }
+}
+
+fn unique_suffix() -> String {
+    format!("{}_{:016x}", std::process::id(), rand::random::<u64>())
+}
+
+// The url of another database on the same server
+fn database_url(server_url: &str, name: &str) -> String {
+    let (base, params) = server_url.split_once('?').map_or((server_url, None), |(base, params)| (base, Some(params)));
+    let authority_end = base.find("://").map_or(0, |i| i + 3);
+    let base = match base[authority_end..].find('/') {
+        Some(slash) => &base[..authority_end + slash],
+        None => base,
+    };
+    match params {
+        Some(params) => format!("{}/{}?{}", base, name, params),
+        None => format!("{}/{}", base, name),
+    }
+}
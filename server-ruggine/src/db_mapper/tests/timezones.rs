@@ -0,0 +1,139 @@
+// Stored and returned timestamps must not depend on the timezone of the server. The timezone of a
+// process is fixed when it starts, so each check runs in a child process of the test binary, once per
+// `TZ` value. Europe/Rome and America/New_York both move to summer time in March 2024.
+use std::borrow::Cow;
+use std::process::Command;
+use sqlx::SqlitePool;
+use sqlx::migrate::Migrator;
+use crate::db_mapper::chat::ChatRepository;
+use crate::db_mapper::sqlite::{MIGRATOR, SqliteRepository};
+use crate::db_mapper::tests::{TestDb, contract, unique_suffix};
+use crate::utilities::config::Config;
+
+const TIMEZONES: [&str; 3] = ["UTC", "Europe/Rome", "America/New_York"];
+
+// Set in the child processes, so that the checks do nothing when run directly
+const CHILD: &str = "RUGGINE_TEST_TZ_CHILD";
+
+#[test]
+fn timestamps_are_utc_in_every_timezone() {
+    for tz in TIMEZONES {
+        run_child("new_timestamps_are_utc", Some(tz));
+        run_child("local_chat_times_are_converted_to_utc", Some(tz));
+    }
+}
+
+#[test]
+fn local_chat_times_need_an_explicit_timezone() {
+    run_child("local_chat_times_are_not_converted_without_tz", None);
+}
+
+fn run_child(test: &str, tz: Option<&str>) {
+    let (_, module) = module_path!().split_once("::").expect("module path");
+    let name = format!("{}::{}", module, test);
+    let mut command = Command::new(std::env::current_exe().expect("test binary"));
+    command.args([name.as_str(), "--exact", "--ignored", "--test-threads=1"]).env(CHILD, "1");
+    match tz {
+        Some(tz) => command.env("TZ", tz),
+        None => command.env_remove("TZ"),
+    };
+
+    let output = command.output().expect("run the test binary");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{} failed with TZ={:?}:\n{}{}", test, tz, stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("1 passed"), "{} did not run:\n{}", test, stdout);
+}
+
+#[tokio::test]
+#[ignore = "run by timestamps_are_utc_in_every_timezone"]
+async fn new_timestamps_are_utc() {
+    if std::env::var_os(CHILD).is_none() {
+        return;
+    }
+    let db = TestDb::sqlite().await;
+    contract::timestamps_are_utc(&db).await;
+    db.close().await;
+    if let Some(db) = TestDb::postgres().await {
+        contract::timestamps_are_utc(&db).await;
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+#[ignore = "run by timestamps_are_utc_in_every_timezone"]
+async fn local_chat_times_are_converted_to_utc() {
+    let Some(tz) = std::env::var("TZ").ok().filter(|_| std::env::var_os(CHILD).is_some()) else {
+        return;
+    };
+    // Chats created half an hour before and after the clocks went forward in Rome
+    let expected = match tz.as_str() {
+        "UTC" => ["2024-03-31T01:30:00Z", "2024-03-31T03:30:00Z"],
+        "Europe/Rome" => ["2024-03-31T00:30:00Z", "2024-03-31T01:30:00Z"],
+        "America/New_York" => ["2024-03-31T05:30:00Z", "2024-03-31T07:30:00Z"],
+        other => panic!("no expected times for TZ={}", other),
+    };
+
+    let (url, path) = local_time_database().await;
+    let db = SqliteRepository::connect(&url, &Config::default()).await.expect("migrate the database");
+    let chats = db.user_chats("alice").await.unwrap();
+    for (name, expected) in [("Before", expected[0]), ("After", expected[1])] {
+        let chat = chats.iter().find(|chat| chat.name.as_deref() == Some(name)).expect("chat");
+        let json = serde_json::to_value(chat).unwrap();
+        assert_eq!(json["created_at"], expected, "chat created {} the change with TZ={}", name, tz);
+    }
+    remove_database(&path);
+}
+
+#[tokio::test]
+#[ignore = "run by local_chat_times_need_an_explicit_timezone"]
+async fn local_chat_times_are_not_converted_without_tz() {
+    if std::env::var_os(CHILD).is_none() || std::env::var_os("TZ").is_some() {
+        return;
+    }
+    let (url, path) = local_time_database().await;
+    let error = SqliteRepository::connect(&url, &Config::default()).await.err().expect("the migration must not run");
+    assert!(error.to_string().contains("TZ"), "unexpected error: {}", error);
+
+    // Nothing was converted
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let times: Vec<String> = sqlx::query_scalar("SELECT createdAt FROM CHAT ORDER BY ID").fetch_all(&pool).await.unwrap();
+    assert_eq!(times, vec!["2024-03-31 01:30:00", "2024-03-31 03:30:00"]);
+    pool.close().await;
+    remove_database(&path);
+}
+
+// A database from before the conversion, with two chats created in local time
+async fn local_time_database() -> (String, String) {
+    let path = std::env::temp_dir().join(format!("ruggine-test-{}.db", unique_suffix())).display().to_string();
+    let url = format!("sqlite://{}?mode=rwc", path);
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let before_conversion = Migrator { migrations: Cow::Owned(MIGRATOR.migrations[..2].to_vec()), ..Migrator::DEFAULT };
+    before_conversion.run(&pool).await.unwrap();
+
+    sqlx::query("INSERT INTO USER (username, name, surname, password) VALUES ('alice', 'Alice', 'User', 'x')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    for (id, name, created_at) in [(1, "Before", "2024-03-31 01:30:00"), (2, "After", "2024-03-31 03:30:00")] {
+        sqlx::query("INSERT INTO CHAT (ID, name, isGroup, createdAt) VALUES (?, ?, 1, ?)")
+            .bind(id)
+            .bind(name)
+            .bind(created_at)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO USERS_JOINED (username, chatId) VALUES ('alice', ?)")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    pool.close().await;
+    (url, path)
+}
+
+fn remove_database(path: &str) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+}
@@ -0,0 +1,121 @@
+// Operations writing several rows must leave nothing behind, and publish nothing, when a statement fails
+use axum::async_trait;
+use std::sync::{Arc, Mutex};
+use crate::db_mapper::{chat, request};
+use crate::db_mapper::tests::TestDb;
+use crate::events::queue::ConnectionQueue;
+use crate::events::{BroadcastStats, Broadcaster, Event, Subscription};
+use crate::route_handlers::user_handler::AuthUser;
+use crate::state::EventBus;
+use crate::utilities::config::OverflowPolicy;
+use crate::utilities::i18n::Locale;
+
+backend_tests!(
+    failed_group_creation_is_rolled_back,
+    failed_private_chat_creation_is_rolled_back,
+    failed_leave_is_rolled_back,
+    failed_acceptance_is_rolled_back,
+);
+
+// Broadcaster keeping the published events
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<Event>>,
+}
+
+impl Recorder {
+    fn published(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl Broadcaster for Recorder {
+    async fn publish(&self, event: Event) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    async fn subscribe(&self, _username: &str, _locale: Locale) -> Subscription {
+        Subscription { id: 0, queue: Arc::new(ConnectionQueue::new(1, OverflowPolicy::DropOldest)) }
+    }
+
+    async fn unsubscribe(&self, _username: &str, _id: u64) {}
+
+    fn close_all(&self, _code: u16, _reason: &'static str) {}
+
+    fn stats(&self) -> BroadcastStats {
+        BroadcastStats::default()
+    }
+}
+
+fn recorder() -> (Arc<Recorder>, EventBus) {
+    let recorder = Arc::new(Recorder::default());
+    (recorder.clone(), recorder)
+}
+
+fn user(username: &str) -> AuthUser {
+    AuthUser(username.to_string())
+}
+
+async fn pending_requests(t: &TestDb) -> i64 {
+    t.scalar("SELECT COUNT(*) FROM REQUEST", "SELECT COUNT(*) FROM requests").await
+}
+
+async fn failed_group_creation_is_rolled_back(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let (recorder, events) = recorder();
+
+    // The creation message is the last statement: the chat, its creator and the invitations are rolled back
+    t.fail_on("INSERT", "MESSAGE", "messages").await;
+    let created = chat::create_group(&t.db, &events, Some("Team".to_string()), true, user("alice"), vec!["bob".to_string()]).await;
+
+    assert!(created.is_err());
+    assert_eq!(t.chat_rows().await, (0, 0, 0));
+    assert_eq!(pending_requests(t).await, 0);
+    assert_eq!(recorder.published(), 0);
+}
+
+async fn failed_private_chat_creation_is_rolled_back(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let (recorder, events) = recorder();
+
+    // After the chat and both members
+    t.fail_on("INSERT", "MESSAGE", "messages").await;
+    let created = chat::create_private_chat(&t.db, &events, user("alice"), "bob".to_string()).await;
+
+    assert!(created.is_err());
+    assert_eq!(t.chat_rows().await, (0, 0, 0));
+    assert_eq!(recorder.published(), 0);
+}
+
+async fn failed_leave_is_rolled_back(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let (recorder, events) = recorder();
+    let chat_id = chat::create_group(&t.db, &events, Some("Team".to_string()), true, user("alice"), vec!["bob".to_string()]).await.unwrap();
+    request::accept_request(&t.db, &events, chat_id, user("bob")).await.unwrap();
+    assert_eq!(recorder.published(), 2);
+    let before = t.chat_rows().await;
+
+    // The leave message is stored before the member is removed
+    t.fail_on("DELETE", "USERS_JOINED", "users_joined").await;
+    assert!(chat::leave_group(&t.db, &events, chat_id, user("alice")).await.is_err());
+
+    assert_eq!(t.chat_rows().await, before);
+    assert_eq!(recorder.published(), 2);
+}
+
+async fn failed_acceptance_is_rolled_back(t: &TestDb) {
+    t.add_users(&["alice", "bob"]).await;
+    let (recorder, events) = recorder();
+    let chat_id = chat::create_group(&t.db, &events, Some("Team".to_string()), true, user("alice"), vec!["bob".to_string()]).await.unwrap();
+    assert_eq!(recorder.published(), 1);
+    let before = t.chat_rows().await;
+
+    // The invitation is deleted before the user joins
+    t.fail_on("INSERT", "USERS_JOINED", "users_joined").await;
+    assert!(request::accept_request(&t.db, &events, chat_id, user("bob")).await.is_err());
+
+    assert_eq!(t.chat_rows().await, before);
+    assert_eq!(pending_requests(t).await, 1);
+    assert_eq!(recorder.published(), 1);
+}
@@ -0,0 +1,139 @@
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::db_mapper::Repositories;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+use crate::utilities::metrics::time_db;
+use crate::utilities::totp;
+use crate::utilities::validation::{normalize_text, Normalize};
+use validator::Validate;
+
+// Recovery codes given to the user when 2FA is enabled
+const RECOVERY_CODES: usize = 10;
+
+// The TOTP settings of a user
+pub struct TwoFactor {
+    pub secret: String,
+    // False while the enrolment waits for its first code
+    pub confirmed: bool,
+    // Time step of the last code accepted
+    pub last_step: i64,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct TwoFactorCode {
+    // A TOTP code, or one of the recovery codes
+    #[validate(length(min = 1, max = 32))]
+    pub code: String,
+}
+
+impl Normalize for TwoFactorCode {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.code);
+    }
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[async_trait]
+pub trait TwoFactorRepository: Send + Sync {
+    async fn two_factor(&self, username: &str) -> Result<Option<TwoFactor>, MyError>;
+
+    // Stores the secret of a pending enrolment, replacing the previous one. Returns false if 2FA is already enabled
+    async fn set_pending_secret(&self, username: &str, secret: &str) -> Result<bool, MyError>;
+
+    // Enables the pending enrolment, recording the step of its first code and replacing the recovery codes,
+    // atomically. Returns false if there is no pending enrolment
+    async fn confirm(&self, username: &str, step: i64, code_hashes: &[String]) -> Result<bool, MyError>;
+
+    // Records the use of the code of `step`. Returns false if a code of the same or a later step was already used
+    async fn use_step(&self, username: &str, step: i64) -> Result<bool, MyError>;
+
+    // Consumes an unused recovery code, returning false if there is none with this hash
+    async fn use_recovery_code(&self, username: &str, code_hash: &str) -> Result<bool, MyError>;
+
+    // Removes the secret and the recovery codes of the user
+    async fn disable(&self, username: &str) -> Result<(), MyError>;
+}
+
+pub async fn is_enabled(db: &Repositories, username: &str) -> Result<bool, MyError> {
+    let settings = time_db("two_factor.get", db.two_factor.two_factor(username)).await?;
+    Ok(settings.is_some_and(|settings| settings.confirmed))
+}
+
+// Starts (or restarts) the enrolment of the user with a new secret, to be confirmed with a first code
+pub async fn enroll(db: &Repositories, config: &Config, username: &str) -> Result<TwoFactorEnrollment, MyError> {
+    let secret = totp::generate_secret();
+    if !time_db("two_factor.set_pending", db.two_factor.set_pending_secret(username, &secret)).await? {
+        return Err(MyError::TwoFactorAlreadyEnabled);
+    }
+    Ok(TwoFactorEnrollment {
+        provisioning_uri: totp::provisioning_uri(&config.totp_issuer, username, &secret),
+        secret,
+    })
+}
+
+// Enables 2FA once the user proves their app generates the right codes.
+// Returns the recovery codes, which are shown only this once
+pub async fn confirm(db: &Repositories, username: &str, code: &str) -> Result<RecoveryCodes, MyError> {
+    let pending = match time_db("two_factor.get", db.two_factor.two_factor(username)).await? {
+        Some(settings) if settings.confirmed => return Err(MyError::TwoFactorAlreadyEnabled),
+        Some(settings) => settings,
+        None => return Err(MyError::NoTwoFactorEnrollment),
+    };
+    let step = totp::matching_step(&pending.secret, code.trim(), totp::current_step()).ok_or(MyError::InvalidTwoFactorCode)?;
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| new_recovery_code()).collect();
+    let code_hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    if !time_db("two_factor.confirm", db.two_factor.confirm(username, step as i64, &code_hashes)).await? {
+        return Err(MyError::NoTwoFactorEnrollment);
+    }
+    Ok(RecoveryCodes { recovery_codes })
+}
+
+// Checks a TOTP code, or consumes a recovery code. Each TOTP code is accepted only once
+pub async fn verify_code(db: &Repositories, username: &str, code: &str) -> Result<bool, MyError> {
+    let Some(settings) = time_db("two_factor.get", db.two_factor.two_factor(username)).await?.filter(|s| s.confirmed) else {
+        return Ok(false);
+    };
+
+    let code = code.trim();
+    if let Some(step) = totp::matching_step(&settings.secret, code, totp::current_step()) {
+        return time_db("two_factor.use_step", db.two_factor.use_step(username, step as i64)).await;
+    }
+    time_db("two_factor.use_recovery_code", db.two_factor.use_recovery_code(username, &hash_recovery_code(code))).await
+}
+
+// Disables 2FA, which takes a valid code as well
+pub async fn disable(db: &Repositories, username: &str, code: &str) -> Result<(), MyError> {
+    if !is_enabled(db, username).await? {
+        return Err(MyError::TwoFactorNotEnabled);
+    }
+    if !verify_code(db, username, code).await? {
+        return Err(MyError::InvalidTwoFactorCode);
+    }
+    time_db("two_factor.disable", db.two_factor.disable(username)).await
+}
+
+// 16 random hex digits in groups of 4, e.g. `3f2a-9c1d-0b7e-51a4`
+fn new_recovery_code() -> String {
+    let digits = hex::encode(rand::random::<[u8; 8]>());
+    digits.as_bytes().chunks(4).map(|group| String::from_utf8_lossy(group)).collect::<Vec<_>>().join("-")
+}
+
+// Like the reset tokens, recovery codes are random enough for a fast unsalted hash.
+// Case, dashes and spaces are ignored, however the user types the code
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
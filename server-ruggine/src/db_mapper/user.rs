@@ -1,79 +1,457 @@
+use axum::async_trait;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fmt;
+use std::sync::OnceLock;
+use tracing::{info, warn};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool, Row};
+use crate::db_mapper::Repositories;
+use crate::utilities::avatar::process_avatar;
 use crate::utilities::utils::{hash_password, verify_password};
 use crate::utilities::error::MyError;
+use crate::utilities::i18n::Locale;
+use crate::utilities::config::Config;
+use crate::utilities::metrics::time_db;
+use crate::utilities::password_policy::check_password;
+use crate::utilities::validation::{double_option, normalize_text, validate_username, Normalize};
+use validator::Validate;
 
-#[derive(Debug, Serialize, FromRow, Clone)]
-pub struct User {
+// The password is checked against the password policy instead
+#[derive(Deserialize, Validate)]
+pub struct CreateUserRequest {
+    #[validate(custom(function = "validate_username"))]
     pub username: String,
+    #[validate(length(min = 1, max = 64))]
     pub name: String,
+    #[validate(length(min = 1, max = 64))]
     pub surname: String,
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct CreateUserRequest {
+impl Normalize for CreateUserRequest {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.username);
+        normalize_text(&mut self.name);
+        normalize_text(&mut self.surname);
+    }
+}
+
+// Passwords never end up in the logs
+impl fmt::Debug for CreateUserRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreateUserRequest")
+            .field("username", &self.username)
+            .field("name", &self.name)
+            .field("surname", &self.surname)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct LoginRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub username: String,
+    #[validate(length(min = 1, max = 1024))]
+    pub password: String,
+}
+
+impl Normalize for LoginRequest {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.username);
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl Normalize for ChangePasswordRequest {
+    fn normalize(&mut self) {}
+}
+
+// Changes to the profile and settings of the signed-in user: fields left out are kept, `null` clears them
+#[derive(Deserialize, Validate)]
+pub struct UpdateAccountRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub name: Option<String>,
+    #[validate(length(min = 1, max = 64))]
+    pub surname: Option<String>,
+    // Without a display name, name and surname are shown
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(min = 1, max = 64))]
+    pub display_name: Option<Option<String>>,
+    // A blank bio clears it
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(max = 500))]
+    pub bio: Option<Option<String>>,
+    // Language of the messages for the user, overriding the `Accept-Language` header
+    #[serde(default, deserialize_with = "double_option")]
+    pub locale: Option<Option<Locale>>,
+    // Whether the user can be found in the directory by users they share no chat with
+    pub discoverable: Option<bool>,
+}
+
+impl Normalize for UpdateAccountRequest {
+    fn normalize(&mut self) {
+        for text in [&mut self.name, &mut self.surname].into_iter().flatten() {
+            normalize_text(text);
+        }
+        if let Some(Some(display_name)) = &mut self.display_name {
+            normalize_text(display_name);
+        }
+        if let Some(bio) = &mut self.bio {
+            if let Some(text) = bio {
+                normalize_text(text);
+            }
+            if bio.as_deref() == Some("") {
+                *bio = None;
+            }
+        }
+    }
+}
+
+// Profile data of a user, as stored
+pub struct ProfileRecord {
     pub username: String,
     pub name: String,
     pub surname: String,
-    pub password: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub locale: Option<Locale>,
+    pub discoverable: bool,
+    pub joined_at: DateTime<Utc>,
+    // Last change of the avatar, None without one
+    pub avatar_updated_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Deserialize)]
-pub struct LoginRequest {
+// Profile of a user as every signed-in user sees it
+#[derive(Debug, Serialize)]
+pub struct PublicProfile {
     pub username: String,
-    pub password: String,
+    // The chosen display name, else name and surname
+    pub display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    // Changes with the avatar, so that it can be cached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    // In UTC (serialized as RFC 3339)
+    pub joined_at: DateTime<Utc>,
+}
+
+// Profile of the signed-in user: the public one, plus the account details only they see
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    #[serde(flatten)]
+    pub public: PublicProfile,
+    pub name: String,
+    pub surname: String,
+    pub locale: Option<Locale>,
+    pub discoverable: bool,
+}
+
+// Characters left as they are in the usernames put in URLs
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_');
+
+impl ProfileRecord {
+    fn public(&self) -> PublicProfile {
+        PublicProfile {
+            username: self.username.clone(),
+            display_name: self.display_name.clone().unwrap_or_else(|| format!("{} {}", self.name, self.surname)),
+            bio: self.bio.clone(),
+            avatar_url: self.avatar_updated_at.map(|updated_at| {
+                format!("/users/{}/avatar?v={}", utf8_percent_encode(&self.username, PATH_SEGMENT), updated_at.timestamp())
+            }),
+            joined_at: self.joined_at,
+        }
+    }
+}
+
+impl From<ProfileRecord> for Profile {
+    fn from(record: ProfileRecord) -> Self {
+        Profile {
+            public: record.public(),
+            name: record.name,
+            surname: record.surname,
+            locale: record.locale,
+            discoverable: record.discoverable,
+        }
+    }
+}
+
+// Parameters of `GET /users`: the text to look for, and the page
+#[derive(Deserialize, Validate)]
+pub struct UserSearchQuery {
+    #[validate(length(min = 1, max = 64))]
+    pub q: String,
+    #[validate(range(min = 1, max = 50))]
+    pub limit: Option<i64>,
+    #[validate(range(min = 0))]
+    pub offset: Option<i64>,
+}
+
+impl Normalize for UserSearchQuery {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.q);
+    }
+}
+
+// Lowercase form of the names matched by the search, computed here so that both backends fold
+// non-ASCII letters in the same way
+pub fn fold_case(text: &str) -> String {
+    text.to_lowercase()
+}
+
+// Patterns matched against the folded username, name and surname (with `\` escaping `%` and `_`)
+pub struct UserSearch {
+    // The text as typed, folded
+    pub exact: String,
+    // Values starting with the text
+    pub prefix: String,
+    // Values containing the text
+    pub contains: String,
+    // Values containing the characters of the text in order, with anything in between
+    pub fuzzy: String,
+}
+
+impl UserSearch {
+    pub fn new(text: &str) -> Self {
+        let text = fold_case(text);
+        let escape = |c: char| match c {
+            '%' | '_' | '\\' => format!("\\{}", c),
+            c => c.to_string(),
+        };
+        let escaped: String = text.chars().map(escape).collect();
+        let fuzzy: String = text.chars().map(|c| format!("%{}", escape(c))).collect();
+        UserSearch {
+            exact: text.clone(),
+            prefix: format!("{}%", escaped),
+            contains: format!("%{}%", escaped),
+            fuzzy: format!("{}%", fuzzy),
+        }
+    }
+}
+
+// A user found by a search, with the number of chats they share with the searching user
+pub struct UserMatch {
+    pub profile: ProfileRecord,
+    pub shared_chats: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSearchResult {
+    #[serde(flatten)]
+    pub profile: PublicProfile,
+    pub shared_chats: i64,
+}
+
+// A page of search results. `next_offset` is set when there are more
+#[derive(Debug, Serialize)]
+pub struct UserSearchPage {
+    pub users: Vec<UserSearchResult>,
+    pub next_offset: Option<i64>,
+}
+
+// An avatar, as a PNG image
+pub struct Avatar {
+    pub image: Vec<u8>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// A user ready to be stored, with the password already hashed
+pub struct NewUser {
+    pub username: String,
+    pub name: String,
+    pub surname: String,
+    pub password_hash: String,
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    // Stores a new user, failing with `UsernameExists` if the username is taken
+    async fn insert_user(&self, user: &NewUser) -> Result<(), MyError>;
+
+    // Returns the password hash of the user, or None if the user does not exist
+    async fn password_hash(&self, username: &str) -> Result<Option<String>, MyError>;
+
+    // Replaces the password hash of the user, returning whether the user exists
+    async fn update_password(&self, username: &str, password_hash: &str) -> Result<bool, MyError>;
+
+    // Returns the language chosen by the user, or None if the user has not chosen one (or does not exist)
+    async fn locale(&self, username: &str) -> Result<Option<Locale>, MyError>;
+
+    // Returns the profile of the user, or None if the user does not exist
+    async fn profile(&self, username: &str) -> Result<Option<ProfileRecord>, MyError>;
+
+    // Applies the changes to the profile and settings of the user, returning whether the user exists
+    async fn update_profile(&self, username: &str, changes: &UpdateAccountRequest) -> Result<bool, MyError>;
+
+    // Returns the avatar of the user, if any
+    async fn avatar(&self, username: &str) -> Result<Option<Avatar>, MyError>;
+
+    // Stores (or replaces) the avatar of the user
+    async fn set_avatar(&self, username: &str, image: &[u8]) -> Result<(), MyError>;
+
+    // Removes the avatar of the user, returning whether there was one
+    async fn delete_avatar(&self, username: &str) -> Result<bool, MyError>;
+
+    // Returns at most `limit` users matching the search, skipping `offset`, for the searching user `caller`.
+    // Left out are the caller, the users blocked by or blocking them, and the users not discoverable
+    // who share no chat with them. Users sharing chats with the caller come first, then the closest matches
+    async fn search(&self, caller: &str, search: &UserSearch, limit: i64, offset: i64) -> Result<Vec<UserMatch>, MyError>;
+
+    // Blocks or unblocks a user, returning whether that user exists
+    async fn set_blocked(&self, blocker: &str, blocked: &str, is_blocked: bool) -> Result<bool, MyError>;
+
+    // Returns the users blocked by the user, by username
+    async fn blocked_users(&self, username: &str) -> Result<Vec<String>, MyError>;
 }
 
 pub async fn create_user(
-    pool: &SqlitePool,
-    mut payload: CreateUserRequest,
+    db: &Repositories,
+    config: &Config,
+    payload: CreateUserRequest,
 ) -> Result<(), MyError> {
-    // 1. Verify if username exists
-    let exists = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM USER WHERE username = ?"
-    )
-        .bind(&payload.username)
-        .fetch_one(pool)
-        .await
-        .map_err(MyError::from)?;
-
-    if exists > 0 {
-        return Err(MyError::UsernameExists);
+    // 1. Check the strength of the password, then hash it
+    check_password(config, &payload.username, &payload.password)?;
+    let password_hash = hash_password(config, &payload.password).await?;
+
+    // 2. Insert (the repository reports an existing username)
+    let user = NewUser {
+        username: payload.username,
+        name: payload.name,
+        surname: payload.surname,
+        password_hash,
+    };
+    time_db("user.insert", db.users.insert_user(&user)).await
+}
+
+// Hash checked against when the username does not exist, so that unknown users take as long as wrong passwords
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+// Checks the credentials. A legacy or outdated hash is replaced after a successful check,
+// a failure to store the new one is logged but does not fail the sign-in
+pub async fn verify_user(db: &Repositories, config: &Config, username: &str, password: &str) -> Result<bool, MyError> {
+    let Some(hashed_password) = time_db("user.password_hash", db.users.password_hash(username)).await? else {
+        // Username not found
+        let dummy = match DUMMY_HASH.get() {
+            Some(dummy) => dummy.clone(),
+            None => hash_password(config, "ruggine-dummy-password").await?,
+        };
+        let _ = verify_password(config, &dummy, password).await?;
+        return Ok(false);
+    };
+
+    let verification = verify_password(config, &hashed_password, password).await?;
+    if verification.valid && verification.needs_rehash {
+        let rehashed = hash_password(config, password).await?;
+        match time_db("user.update_password", db.users.update_password(username, &rehashed)).await {
+            Ok(_) => info!(username, "Password hash upgraded"),
+            Err(e) => warn!(username, error = %e, "Password hash upgrade failed"),
+        }
     }
+    Ok(verification.valid)
+}
 
-    // 2. Hash of password
-    payload.password = hash_password(&payload.password).map_err(|_| MyError::PasswordHashingFailed)?;
-
-    // 3. Insert
-    sqlx::query(
-        "INSERT INTO USER (username, name, surname, password) VALUES (?, ?, ?, ?)"
-    )
-        .bind(&payload.username)
-        .bind(&payload.name)
-        .bind(&payload.surname)
-        .bind(&payload.password)
-        .execute(pool)
-        .await
-        .map_err(MyError::from)?;
+// Computes the dummy hash ahead of the first sign-in attempt on an unknown username
+pub async fn init_dummy_hash(config: &Config) -> Result<(), MyError> {
+    let dummy = hash_password(config, "ruggine-dummy-password").await?;
+    let _ = DUMMY_HASH.set(dummy);
+    Ok(())
+}
+
+// Changes the password of the user, who must prove to know the current one
+pub async fn change_password(db: &Repositories, config: &Config, username: &str, payload: &ChangePasswordRequest) -> Result<(), MyError> {
+    if !verify_user(db, config, username, &payload.current_password).await? {
+        return Err(MyError::InvalidCredentials);
+    }
+    check_password(config, username, &payload.new_password)?;
 
+    let password_hash = hash_password(config, &payload.new_password).await?;
+    if !time_db("user.update_password", db.users.update_password(username, &password_hash)).await? {
+        return Err(MyError::UserNotFound);
+    }
     Ok(())
 }
 
-pub async fn verify_user(pool: &SqlitePool, username: &str, password: &str) -> Result<bool, MyError> {
-    let row = sqlx::query("SELECT password FROM USER WHERE username = ?")
-        .bind(username)
-        .fetch_optional(pool)
-        .await
-        .map_err(MyError::from)?;
+pub async fn get_locale(db: &Repositories, username: &str) -> Result<Option<Locale>, MyError> {
+    time_db("user.locale", db.users.locale(username)).await
+}
 
-    match row {
-        Some(row) => {
-            let hashed_password: String = row.try_get("password")?;
-            let valid = verify_password(&hashed_password, password).unwrap_or(false);
-            Ok(valid)
-        }
-        None => Ok(false), // Username not found
+// Returns the full profile of the user, for the user themselves
+pub async fn get_profile(db: &Repositories, username: &str) -> Result<Profile, MyError> {
+    let record = time_db("user.profile", db.users.profile(username)).await?.ok_or(MyError::UserNotFound)?;
+    Ok(record.into())
+}
+
+pub async fn get_public_profile(db: &Repositories, username: &str) -> Result<PublicProfile, MyError> {
+    let record = time_db("user.profile", db.users.profile(username)).await?.ok_or(MyError::UserNotFound)?;
+    Ok(record.public())
+}
+
+// Applies the changes to the profile and settings of the user, returning the updated profile
+pub async fn update_account(db: &Repositories, username: &str, payload: &UpdateAccountRequest) -> Result<Profile, MyError> {
+    if !time_db("user.update_profile", db.users.update_profile(username, payload)).await? {
+        return Err(MyError::UserNotFound);
+    }
+    get_profile(db, username).await
+}
+
+// Resizes and stores the uploaded image as the avatar of the user, returning the updated profile
+pub async fn set_avatar(db: &Repositories, config: &Config, username: &str, upload: Bytes) -> Result<Profile, MyError> {
+    let image = process_avatar(config, upload).await?;
+    time_db("user.set_avatar", db.users.set_avatar(username, &image)).await?;
+    get_profile(db, username).await
+}
+
+// Removes the avatar of the user, if any, returning the updated profile
+pub async fn delete_avatar(db: &Repositories, username: &str) -> Result<Profile, MyError> {
+    time_db("user.delete_avatar", db.users.delete_avatar(username)).await?;
+    get_profile(db, username).await
+}
+
+pub async fn get_avatar(db: &Repositories, username: &str) -> Result<Avatar, MyError> {
+    time_db("user.avatar", db.users.avatar(username)).await?.ok_or(MyError::AvatarNotFound)
+}
+
+// Looks for users by username, name or surname, 20 per page by default
+pub async fn search_users(db: &Repositories, caller: &str, query: &UserSearchQuery) -> Result<UserSearchPage, MyError> {
+    let limit = query.limit.unwrap_or(20);
+    let offset = query.offset.unwrap_or(0);
+    let search = UserSearch::new(&query.q);
+
+    // One more than the page, to tell whether there is a next one
+    let mut matches = time_db("user.search", db.users.search(caller, &search, limit + 1, offset)).await?;
+    let next_offset = (matches.len() as i64 > limit).then_some(offset + limit);
+    matches.truncate(limit as usize);
+
+    let users = matches
+        .into_iter()
+        .map(|found| UserSearchResult { profile: found.profile.public(), shared_chats: found.shared_chats })
+        .collect();
+    Ok(UserSearchPage { users, next_offset })
+}
+
+pub async fn block_user(db: &Repositories, blocker: &str, blocked: &str) -> Result<(), MyError> {
+    if blocker == blocked {
+        return Err(MyError::CannotBlockYourself);
+    }
+    if !time_db("user.set_blocked", db.users.set_blocked(blocker, blocked, true)).await? {
+        return Err(MyError::UserNotFound);
     }
+    Ok(())
 }
 
+pub async fn unblock_user(db: &Repositories, blocker: &str, blocked: &str) -> Result<(), MyError> {
+    time_db("user.set_blocked", db.users.set_blocked(blocker, blocked, false)).await?;
+    Ok(())
+}
+
+pub async fn get_blocked_users(db: &Repositories, username: &str) -> Result<Vec<String>, MyError> {
+    time_db("user.blocked_users", db.users.blocked_users(username)).await
+}
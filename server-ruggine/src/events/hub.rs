@@ -0,0 +1,189 @@
+use axum::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::events::{Broadcaster, BroadcastStats, Event, Subscription};
+use crate::db_mapper::chat::ChatRepository;
+use crate::events::queue::{ConnectionQueue, PushOutcome};
+use crate::utilities::config::OverflowPolicy;
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::Locale;
+use crate::utilities::metrics::time_db;
+use metrics::counter;
+use tracing::warn;
+
+// Outgoing queue of a registered connection, with its subscription id and language
+type Connection = (u64, Locale, Arc<ConnectionQueue>);
+
+// Default broadcaster: fans events out to the WebSocket connections of the chat participants
+pub struct Hub {
+    chats: Arc<dyn ChatRepository>,
+    // Outgoing queues of each user's connections, identified by their subscription id.
+    // Queues are bounded: a slow client cannot make the server buffer messages without limit
+    connections: Mutex<HashMap<String, Vec<Connection>>>,
+    next_id: AtomicU64,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    dropped_messages: AtomicU64,
+    evictions: AtomicU64,
+    // Set by `close_all`, with the close code and reason: new connections are closed right away
+    closing: Mutex<Option<(u16, &'static str)>>,
+}
+
+impl Hub {
+    pub fn new(chats: Arc<dyn ChatRepository>, queue_capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        Hub {
+            chats,
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            queue_capacity,
+            overflow_policy,
+            dropped_messages: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            closing: Mutex::new(None),
+        }
+    }
+
+    // Resolves the users subscribed to a chat, i.e. its current participants
+    async fn subscribers(&self, chat_id: i64) -> Result<Vec<String>, MyError> {
+        self.chats.participants(chat_id).await
+    }
+}
+
+#[async_trait]
+impl Broadcaster for Hub {
+    async fn publish(&self, event: Event) {
+        // One payload per language, shared by the connections reading in it
+        let Ok(payloads) = Locale::ALL
+            .into_iter()
+            .map(|locale| event.to_json(locale).map(|json| (locale, json)))
+            .collect::<Result<HashMap<_, _>, _>>()
+        else {
+            return;
+        };
+
+        let message = event.message();
+        if message.is_some() {
+            counter!("chat_messages_sent_total").increment(1);
+        }
+        let participants = match time_db("chat.participants", self.subscribers(event.chat_id())).await {
+            Ok(participants) => participants,
+            Err(e) => {
+                warn!(chat_id = event.chat_id(), error = %e, "Unable to resolve subscribers");
+                return;
+            }
+        };
+
+        // Collect the queues of the connected participants, then release the lock before pushing
+        let queues: Vec<(String, Locale, Arc<ConnectionQueue>)> = {
+            let map = self.connections.lock().unwrap();
+            participants
+                .iter()
+                .filter_map(|user| map.get(user).map(|queues| (user, queues)))
+                .flat_map(|(user, queues)| queues.iter().map(move |(_, locale, queue)| (user.clone(), *locale, queue.clone())))
+                .collect()
+        };
+
+        for (user, locale, queue) in queues {
+            match queue.push(message, payloads[&locale].clone()) {
+                PushOutcome::Queued | PushOutcome::Closed => {}
+                PushOutcome::DroppedOldest => {
+                    self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+                }
+                PushOutcome::Evicted => {
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                    warn!(username = %user, "Evicted slow WebSocket connection");
+                }
+            }
+        }
+    }
+
+    async fn subscribe(&self, username: &str, locale: Locale) -> Subscription {
+        let queue = Arc::new(ConnectionQueue::new(self.queue_capacity, self.overflow_policy));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        if let Some((code, reason)) = *self.closing.lock().unwrap() {
+            queue.close_with(code, reason);
+        }
+
+        let mut map = self.connections.lock().unwrap();
+        map.entry(username.to_string()).or_default().push((id, locale, queue.clone()));
+
+        Subscription { id, queue }
+    }
+
+    async fn unsubscribe(&self, username: &str, id: u64) {
+        let mut map = self.connections.lock().unwrap();
+        if let Some(vec) = map.get_mut(username) {
+            vec.retain(|(connection_id, _, queue)| {
+                if *connection_id == id {
+                    queue.close();
+                }
+                *connection_id != id
+            });
+            if vec.is_empty() {
+                map.remove(username);
+            }
+        }
+    }
+
+    fn close_all(&self, code: u16, reason: &'static str) {
+        *self.closing.lock().unwrap() = Some((code, reason));
+        let map = self.connections.lock().unwrap();
+        for (_, _, queue) in map.values().flatten() {
+            queue.close_with(code, reason);
+        }
+    }
+
+    fn stats(&self) -> BroadcastStats {
+        let map = self.connections.lock().unwrap();
+        let depths: Vec<usize> = map.values().flatten().map(|(_, _, queue)| queue.len()).collect();
+        BroadcastStats {
+            connections: depths.len(),
+            queued_messages: depths.iter().sum(),
+            max_queue_depth: depths.iter().copied().max().unwrap_or(0),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_mapper::message::SystemEvent;
+    use crate::db_mapper::tests::TestDb;
+    use crate::events::queue::{Outbound, CLOSE_RESYNC_REQUIRED};
+
+    // Publishes `count` messages of a private chat between alice and bob, with alice connected
+    async fn overflow(policy: OverflowPolicy, count: usize) -> (BroadcastStats, Subscription) {
+        let db = TestDb::sqlite().await;
+        db.add_users(&["alice", "bob"]).await;
+        let started = SystemEvent::PrivateChatStarted { user: "alice".to_string(), with: "bob".to_string() };
+        let (_, message) = db.db.chats.create_private_chat("alice", "bob", &started).await.unwrap();
+        let message = message.expect("creation message");
+
+        let hub = Hub::new(db.db.chats.clone(), 1, policy);
+        let subscription = hub.subscribe("alice", Locale::En).await;
+        for _ in 0..count {
+            hub.publish(Event::MessageCreated(message.clone())).await;
+        }
+        let stats = hub.stats();
+        db.close().await;
+        (stats, subscription)
+    }
+
+    #[tokio::test]
+    async fn dropped_messages_are_counted() {
+        let (stats, _subscription) = overflow(OverflowPolicy::DropOldest, 3).await;
+        assert_eq!((stats.connections, stats.queued_messages, stats.dropped_messages, stats.evictions), (1, 1, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn evictions_are_counted() {
+        let (stats, subscription) = overflow(OverflowPolicy::Disconnect, 3).await;
+        // The connection stays registered until its writer sees the close frame
+        assert_eq!((stats.connections, stats.dropped_messages, stats.evictions), (1, 0, 1));
+        assert!(matches!(subscription.recv().await, Some(Outbound::Close(CLOSE_RESYNC_REQUIRED, _))));
+    }
+}
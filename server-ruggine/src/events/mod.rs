@@ -0,0 +1,90 @@
+use axum::async_trait;
+use std::sync::Arc;
+use crate::db_mapper::message::Message;
+use crate::events::queue::{ConnectionQueue, MessageRef, Outbound};
+use crate::utilities::i18n::Locale;
+
+pub mod hub;
+pub mod queue;
+
+// Domain events published by the database layer once a change has been committed.
+// New kinds of notifications are added here and serialized in `Event::to_json`,
+// without touching the SQL code that produces them.
+#[derive(Debug, Clone)]
+pub enum Event {
+    MessageCreated(Message),
+}
+
+impl Event {
+    // Chat whose participants must receive the event
+    pub fn chat_id(&self) -> i64 {
+        match self {
+            Event::MessageCreated(message) => message.chat_id,
+        }
+    }
+
+    // Message carried by the event, used to skip duplicates when replaying missed messages
+    pub fn message(&self) -> Option<MessageRef> {
+        match self {
+            Event::MessageCreated(message) => Some(MessageRef { chat_id: message.chat_id, id: message.id }),
+        }
+    }
+
+    // JSON payload sent to the clients reading in the given language
+    pub fn to_json(&self, locale: Locale) -> Result<String, serde_json::Error> {
+        match self {
+            Event::MessageCreated(message) => {
+                let mut message = message.clone();
+                message.localize(locale);
+                serde_json::to_string(&message)
+            }
+        }
+    }
+}
+
+// A live connection registered on the broadcaster: `queue` holds the serialized events
+// addressed to the user, `id` identifies the connection when unsubscribing
+pub struct Subscription {
+    pub id: u64,
+    pub queue: Arc<ConnectionQueue>,
+}
+
+impl Subscription {
+    // Waits for the next item to send to the client
+    pub async fn recv(&self) -> Option<Outbound> {
+        self.queue.pop().await
+    }
+}
+
+// Counters describing the state of the fan-out
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BroadcastStats {
+    pub connections: usize,
+    // Messages waiting in the connection queues
+    pub queued_messages: usize,
+    // Length of the longest connection queue
+    pub max_queue_depth: usize,
+    // Messages discarded because a queue was full
+    pub dropped_messages: u64,
+    // Connections closed because their queue was full
+    pub evictions: u64,
+}
+
+// In-process event bus: db operations publish events, connections subscribe to them.
+// It is held in the application state as a trait object so it can be replaced (e.g. in tests).
+#[async_trait]
+pub trait Broadcaster: Send + Sync {
+    // Delivers the event to every connection of the users subscribed to its chat
+    async fn publish(&self, event: Event);
+
+    // Registers a new connection for the given user, receiving the events in the given language
+    async fn subscribe(&self, username: &str, locale: Locale) -> Subscription;
+
+    // Removes a connection previously returned by `subscribe`
+    async fn unsubscribe(&self, username: &str, id: u64);
+
+    // Closes every connection, present and future, with the given code once its pending events are sent
+    fn close_all(&self, code: u16, reason: &'static str);
+
+    fn stats(&self) -> BroadcastStats;
+}
@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use crate::utilities::config::OverflowPolicy;
+
+// Close code sent to a client evicted because it could not keep up: it must resync its chats
+pub const CLOSE_RESYNC_REQUIRED: u16 = 4000;
+
+// Close code sent to every client when the server shuts down ("going away")
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+
+// Stored message carried by an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRef {
+    pub chat_id: i64,
+    pub id: i64,
+}
+
+// Item delivered to a connection's writer
+#[derive(Clone, PartialEq, Eq)]
+pub enum Outbound {
+    // Serialized event, with the message it carries (if any)
+    Event { message: Option<MessageRef>, json: String },
+    // Serialized answer to a frame sent by the client (ack or error)
+    Reply(String),
+    // Events were discarded to make room: the client must reload its chats
+    ResyncRequired,
+    // Close the connection with the given code and reason
+    Close(u16, &'static str),
+}
+
+// The serialized events carry message contents, which are not logged
+impl fmt::Debug for Outbound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outbound::Event { message, json } => f
+                .debug_struct("Event")
+                .field("message", message)
+                .field("json_len", &json.len())
+                .finish(),
+            Outbound::Reply(json) => f.debug_struct("Reply").field("json_len", &json.len()).finish(),
+            Outbound::ResyncRequired => f.write_str("ResyncRequired"),
+            Outbound::Close(code, reason) => f.debug_tuple("Close").field(code).field(reason).finish(),
+        }
+    }
+}
+
+// Result of pushing a message on a queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    Queued,
+    // The message was queued after discarding the oldest event
+    DroppedOldest,
+    // The queue was full and the connection has been closed
+    Evicted,
+    // The connection was already closed, the message was discarded
+    Closed,
+}
+
+struct QueueState {
+    items: VecDeque<Outbound>,
+    // Set when events were dropped, until the client has been told to resync
+    resync_required: bool,
+    closed: bool,
+}
+
+// Bounded outgoing queue of a single WebSocket connection.
+// Producers never wait: when the queue is full the overflow policy decides what to give up.
+// Only events are given up: the replies to the client's own frames are always delivered, and when
+// nothing but replies is waiting the connection is closed as if the policy were `Disconnect`.
+pub struct ConnectionQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl ConnectionQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        ConnectionQueue {
+            state: Mutex::new(QueueState { items: VecDeque::with_capacity(capacity), resync_required: false, closed: false }),
+            notify: Notify::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    pub fn push(&self, message: Option<MessageRef>, json: String) -> PushOutcome {
+        self.enqueue(Outbound::Event { message, json })
+    }
+
+    // Queues the answer to a frame sent by the client, which is never discarded to make room
+    pub fn reply(&self, json: String) -> PushOutcome {
+        self.enqueue(Outbound::Reply(json))
+    }
+
+    fn enqueue(&self, item: Outbound) -> PushOutcome {
+        let outcome = {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return PushOutcome::Closed;
+            }
+
+            let oldest_event = state.items.iter().position(|item| matches!(item, Outbound::Event { .. }));
+            match (state.items.len() < self.capacity, self.policy, oldest_event) {
+                (true, _, _) => {
+                    state.items.push_back(item);
+                    PushOutcome::Queued
+                }
+                (false, OverflowPolicy::DropOldest, Some(oldest)) => {
+                    state.items.remove(oldest);
+                    state.items.push_back(item);
+                    state.resync_required = true;
+                    PushOutcome::DroppedOldest
+                }
+                _ => {
+                    // Pending events are useless: the client has to resync anyway
+                    state.items.retain(|item| matches!(item, Outbound::Reply(_)));
+                    if matches!(item, Outbound::Reply(_)) {
+                        state.items.push_back(item);
+                    }
+                    state.items.push_back(Outbound::Close(CLOSE_RESYNC_REQUIRED, "Too slow, resync required"));
+                    state.closed = true;
+                    PushOutcome::Evicted
+                }
+            }
+        };
+        self.notify.notify_one();
+        outcome
+    }
+
+    // Closes the queue: the writer receives the pending items and then `None`
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    // Closes the queue after the pending items: the writer delivers them, then closes the connection
+    // with the given code. Unlike an eviction, nothing already queued is lost
+    pub fn close_with(&self, code: u16, reason: &'static str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            state.items.push_back(Outbound::Close(code, reason));
+            state.closed = true;
+        }
+        self.notify.notify_one();
+    }
+
+    // Number of messages waiting to be sent
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    // Waits for the next item to send, returns `None` once the queue is closed and drained.
+    // After events were dropped the client is first told to resync
+    pub async fn pop(&self) -> Option<Outbound> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.resync_required {
+                    state.resync_required = false;
+                    return Some(Outbound::ResyncRequired);
+                }
+                if let Some(item) = state.items.pop_front() {
+                    return Some(item);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: i64) -> (Option<MessageRef>, String) {
+        (Some(MessageRef { chat_id: 1, id }), format!("event {}", id))
+    }
+
+    fn queued(id: i64) -> Outbound {
+        let (message, json) = event(id);
+        Outbound::Event { message, json }
+    }
+
+    // Items waiting in a closed queue, in order
+    async fn drain(queue: &ConnectionQueue) -> Vec<Outbound> {
+        let mut items = Vec::new();
+        while let Some(item) = queue.pop().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn full_queues_drop_the_oldest_event_and_ask_for_a_resync() {
+        let queue = ConnectionQueue::new(2, OverflowPolicy::DropOldest);
+        let (message, json) = event(1);
+        assert_eq!(queue.push(message, json), PushOutcome::Queued);
+        let (message, json) = event(2);
+        assert_eq!(queue.push(message, json), PushOutcome::Queued);
+        let (message, json) = event(3);
+        assert_eq!(queue.push(message, json), PushOutcome::DroppedOldest);
+        assert_eq!(queue.len(), 2);
+
+        queue.close();
+        assert_eq!(drain(&queue).await, [Outbound::ResyncRequired, queued(2), queued(3)]);
+        assert_eq!(queue.pop().await, None);
+    }
+
+    #[tokio::test]
+    async fn replies_are_never_dropped() {
+        let queue = ConnectionQueue::new(2, OverflowPolicy::DropOldest);
+        assert_eq!(queue.reply("ack".to_string()), PushOutcome::Queued);
+        let (message, json) = event(1);
+        queue.push(message, json);
+        assert_eq!(queue.reply("error".to_string()), PushOutcome::DroppedOldest);
+
+        // With only replies waiting there is no event to give up: the connection is closed after them
+        let (message, json) = event(2);
+        assert_eq!(queue.push(message, json), PushOutcome::Evicted);
+        assert_eq!(queue.reply("late".to_string()), PushOutcome::Closed);
+        assert_eq!(
+            drain(&queue).await,
+            [
+                Outbound::ResyncRequired,
+                Outbound::Reply("ack".to_string()),
+                Outbound::Reply("error".to_string()),
+                Outbound::Close(CLOSE_RESYNC_REQUIRED, "Too slow, resync required"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn full_queues_disconnect_keeping_the_replies() {
+        let queue = ConnectionQueue::new(2, OverflowPolicy::Disconnect);
+        let (message, json) = event(1);
+        queue.push(message, json);
+        queue.reply("ack".to_string());
+        assert_eq!(queue.reply("error".to_string()), PushOutcome::Evicted);
+        let (message, json) = event(2);
+        assert_eq!(queue.push(message, json), PushOutcome::Closed);
+
+        assert_eq!(
+            drain(&queue).await,
+            [
+                Outbound::Reply("ack".to_string()),
+                Outbound::Reply("error".to_string()),
+                Outbound::Close(CLOSE_RESYNC_REQUIRED, "Too slow, resync required"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn close_with_delivers_the_pending_items_first() {
+        let queue = ConnectionQueue::new(2, OverflowPolicy::Disconnect);
+        let (message, json) = event(1);
+        queue.push(message, json);
+        queue.close_with(CLOSE_GOING_AWAY, "Server shutting down");
+        // Only the first close counts
+        queue.close_with(CLOSE_RESYNC_REQUIRED, "Too slow, resync required");
+        let (message, json) = event(2);
+        assert_eq!(queue.push(message, json), PushOutcome::Closed);
+
+        assert_eq!(drain(&queue).await, [queued(1), Outbound::Close(CLOSE_GOING_AWAY, "Server shutting down")]);
+    }
+
+    #[tokio::test]
+    async fn pop_waits_for_the_next_item() {
+        let queue = std::sync::Arc::new(ConnectionQueue::new(2, OverflowPolicy::DropOldest));
+        let reader = tokio::spawn({
+            let queue = queue.clone();
+            async move { drain(&queue).await }
+        });
+        tokio::task::yield_now().await;
+        let (message, json) = event(1);
+        queue.push(message, json);
+        queue.close();
+        assert_eq!(reader.await.unwrap(), [queued(1)]);
+    }
+}
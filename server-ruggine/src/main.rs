@@ -1,44 +1,114 @@
 use std::net::SocketAddr;
-use sqlx::sqlite::SqlitePoolOptions;
-use tower_http::cors::{CorsLayer};
-use axum::http::{header, Method};
+use std::sync::Arc;
+use axum_server::Handle;
+use crate::events::hub::Hub;
+use crate::events::queue::CLOSE_GOING_AWAY;
+use crate::state::{AppState, EventBus};
+use crate::utilities::config::Config;
+use crate::utilities::notifier::OutboxNotifier;
+use crate::utilities::rate_limit::RateLimiter;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tracing::{info, warn};
 
 mod routes;
 mod db_mapper;
 mod utilities;
 mod route_handlers;
+mod events;
+mod state;
 
 
 #[tokio::main]
 async fn main() -> Result<(), sqlx::Error> {
+    let config = Config::from_env();
+    utilities::logging::init(&config);
+    let metrics = utilities::metrics::install();
+
+    // Connect to the configured database (SQLite or PostgreSQL), bringing its schema up to date
+    let db = db_mapper::connect(&config).await?;
+
+    info!("Connected to the db!");
+    // Also rejects invalid Argon2 parameters before serving any request
+    db_mapper::user::init_dummy_hash(&config)
+        .await
+        .map_err(|_| sqlx::Error::Configuration("invalid Argon2 parameters".into()))?;
+
+    // Create the event bus delivering db events to the WebSocket connections
+    let events: EventBus = Arc::new(Hub::new(db.chats.clone(), config.ws_queue_capacity, config.ws_overflow_policy));
+    let config = Arc::new(config);
+    let limiter = Arc::new(RateLimiter::new(&config));
+    // Notifications (e.g. password reset tokens) are written to a local outbox
+    let notifier = Arc::new(OutboxNotifier::new(config.outbox_path.clone()));
+    let state = AppState { db: db.clone(), events: events.clone(), config: config.clone(), limiter, notifier };
 
-    // Configure the CORS (Cross-Origin Resource Sharing) middleware using CorsLayer.
-    // This middleware defines the rules for allowing cross-origin requests to the server.
-    let cors = CorsLayer::new()
-        .allow_origin("http://localhost:5173".parse::<axum::http::HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::DELETE])
-        .allow_headers([header::CONTENT_TYPE])
-        .allow_credentials(true);
-
-    // Create a new SQLite connection pool using SqlitePoolOptions.
-    let pool = SqlitePoolOptions::new()
-        .max_connections(1)
-        .connect("sqlite://Ruggine.db")
-        .await?;
-    
-    println!("Connected to the db!");
-    
     // Create the router
-    let app = routes::create_routes(pool.clone()).await.layer(cors);
+    let app = routes::create_routes(state, routes::api_cors()).await;
+
+    // Start the monitor thread sampling the process and fan-out metrics
+    let monitor = utilities::monitor::start_monitoring(events.clone(), metrics.clone(), &config);
 
-    // Define the address to run the server
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    // Load the TLS certificate, if configured
+    let tls = utilities::tls::load(&config).await?;
+    let handle = Handle::new();
+    let redirect_handle = Handle::new();
+    let metrics_handle = Handle::new();
 
-    // Start the monitor thread for the CPU usage
-    utilities::monitor::start_monitoring();
+    // On SIGINT/SIGTERM stop accepting connections and ask every WebSocket client to go away:
+    // each connection sends its pending events, then the close frame.
+    // The in-flight requests have until the drain deadline to complete
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let shutdown_events = events.clone();
+    let (shutdown_handle, shutdown_redirect, shutdown_metrics) = (handle.clone(), redirect_handle.clone(), metrics_handle.clone());
+    let drain_timeout = config.shutdown_drain_timeout;
+    tokio::spawn(async move {
+        utilities::shutdown::signal().await;
+        info!("Shutting down");
+        shutdown_events.close_all(CLOSE_GOING_AWAY, "Server shutting down");
+        let _ = shutdown_tx.send(Instant::now() + drain_timeout);
+        shutdown_handle.graceful_shutdown(Some(drain_timeout));
+        shutdown_redirect.shutdown();
+        shutdown_metrics.shutdown();
+    });
+
+    // Serve the metrics on their own listener, never exposed with the API
+    let metrics_listener = std::net::TcpListener::bind(config.metrics_addr)?;
+    metrics_listener.set_nonblocking(true)?;
+    tokio::spawn(utilities::metrics::serve(metrics_listener, metrics, metrics_handle));
 
     // Start the Axum server to handle incoming HTTP requests.
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
-    println!("Server running at http://{}", addr);
+    let addr = config.bind_addr;
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    // The peer address identifies the client for the rate limits
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls) => {
+            if let Some(redirect_addr) = config.http_redirect_addr {
+                let redirect_listener = std::net::TcpListener::bind(redirect_addr)?;
+                redirect_listener.set_nonblocking(true)?;
+                tokio::spawn(utilities::tls::serve_redirect(redirect_listener, addr.port(), redirect_handle));
+            }
+            info!("Server running at https://{}", addr);
+            axum_server::from_tcp_rustls(listener, tls).handle(handle).serve(app).await?;
+        }
+        None => {
+            if config.http_redirect_addr.is_some() {
+                warn!("RUGGINE_HTTP_REDIRECT_ADDR is ignored without a TLS certificate");
+            }
+            info!("Server running at http://{}", addr);
+            axum_server::from_tcp(listener).handle(handle).serve(app).await?;
+        }
+    }
+
+    // The upgraded WebSocket connections are not tracked by the server: wait for them separately
+    if let Ok(deadline) = shutdown_rx.await {
+        utilities::shutdown::drain_connections(&events, deadline).await;
+    }
+
+    // Release the database connections and stop the monitor thread
+    db.health.close().await;
+    let _ = tokio::task::spawn_blocking(move || monitor.stop()).await;
+    info!("Server stopped");
     Ok(())
 }
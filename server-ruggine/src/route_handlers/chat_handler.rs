@@ -1,30 +1,49 @@
-use axum::{extract::{State, Path}, Json};
+use axum::{extract::State, Json};
+use crate::utilities::extract::Path;
 use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
+use crate::db_mapper::Repositories;
 
 use crate::db_mapper::chat::{get_user_chats, create_group, Chat, create_private_chat, leave_group};
 use crate::utilities::error::MyError;
 use crate::route_handlers::user_handler::AuthUser;
 use crate::routes::ApiResponse;
+use crate::utilities::i18n::Text;
+use crate::state::EventBus;
+use crate::utilities::validation::{normalize_text, validate_username, validate_usernames, Normalize, ValidJson};
+use validator::Validate;
 
 // Handler to get all chats for the authenticated user
 // Returns a JSON array of Chat objects
 pub async fn user_chats_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
     user: AuthUser,
 ) -> Result<Json<Vec<Chat>>, MyError> {
-    let chats = get_user_chats(&pool, user).await?;
+    let chats = get_user_chats(&db, user).await?;
     Ok(Json(chats))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct CreateGroupPayload {
+    #[validate(length(min = 1, max = 64))]
     pub name: Option<String>,
     pub is_group: bool,
+    #[validate(
+        length(max = 100, code = "count"),
+        custom(function = "validate_usernames"),
+    )]
     pub participants: Vec<String>,
 }
 
+impl Normalize for CreateGroupPayload {
+    fn normalize(&mut self) {
+        if let Some(name) = &mut self.name {
+            normalize_text(name);
+        }
+        self.participants.iter_mut().for_each(normalize_text);
+    }
+}
+
 #[derive(Serialize)]
 pub struct GroupChatResponse {
     pub chat_id: i64
@@ -33,12 +52,14 @@ pub struct GroupChatResponse {
 // Handler to create a new group chat
 // Returns the ID of the newly created chat
 pub async fn create_group_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
+    State(events): State<EventBus>,
     creator: AuthUser,
-    Json(payload): Json<CreateGroupPayload>,
+    ValidJson(payload): ValidJson<CreateGroupPayload>,
 ) -> Result<Json<GroupChatResponse>, MyError> {
     let chat_id = create_group(
-        &pool,
+        &db,
+        &events,
         payload.name,
         payload.is_group,
         creator,
@@ -47,11 +68,18 @@ pub async fn create_group_handler(
     Ok(Json(GroupChatResponse{chat_id}))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct CreatePrivateChatPayload {
+    #[validate(custom(function = "validate_username"))]
     pub other_username: String,
 }
 
+impl Normalize for CreatePrivateChatPayload {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.other_username);
+    }
+}
+
 #[derive(Serialize)]
 pub struct PrivateChatResponse {
     pub chat_id: i64,
@@ -61,12 +89,13 @@ pub struct PrivateChatResponse {
 // Handler to create a new private chat
 // Returns the ID of the chat and whether it already existed
 pub async fn create_private_chat_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
+    State(events): State<EventBus>,
     creator: AuthUser,
-    Json(payload): Json<CreatePrivateChatPayload>,
+    ValidJson(payload): ValidJson<CreatePrivateChatPayload>,
 ) -> Result<Json<PrivateChatResponse>, MyError> {
-    // Modifica la funzione create_private_chat per restituire anche se già esiste
-    let (chat_id, already_exists) = create_private_chat(&pool, creator, payload.other_username).await?;
+    // The chat is reused if the two users already have one
+    let (chat_id, already_exists) = create_private_chat(&db, &events, creator, payload.other_username).await?;
     Ok(Json(PrivateChatResponse {
         chat_id,
         already_exists,
@@ -76,14 +105,13 @@ pub async fn create_private_chat_handler(
 // Handler to leave a group chat
 // Returns a success message upon leaving the group
 pub async fn leave_group_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
+    State(events): State<EventBus>,
     Path(chat_id): Path<i64>,
     user: AuthUser,
 ) -> Result<(StatusCode, Json<ApiResponse>), MyError> {
-    leave_group(&pool, chat_id, user).await?;
+    leave_group(&db, &events, chat_id, user).await?;
     Ok((
         StatusCode::OK,
-        Json(ApiResponse {
-            message: "Successfully left the group".to_string(),
-        }),
+        Json(ApiResponse::new(Text::GroupLeft)),
     ))}
\ No newline at end of file
@@ -0,0 +1,103 @@
+use axum::{extract::{State, Extension}, http::StatusCode, Json};
+use async_session::{MemoryStore, Session, SessionStore};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::warn;
+use crate::db_mapper::Repositories;
+use crate::utilities::config::Config;
+
+// Version of the running build
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Serialize)]
+pub struct ComponentStatus {
+    pub status: &'static str,
+    // Short description of the failure. Details are only logged, the probes are unauthenticated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+}
+
+// Handler for `GET /healthz`: the process is up and serving requests
+pub async fn healthz_handler() -> Json<HealthResponse> {
+    Json(HealthResponse { status: "ok", version: VERSION, components: BTreeMap::new() })
+}
+
+// Handler for `GET /readyz`: the server can handle traffic, i.e. the database is reachable,
+// its schema is up to date and the session store works. Answers 503 when any of them fails
+pub async fn readyz_handler(
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
+    Extension(store): Extension<MemoryStore>,
+) -> (StatusCode, Json<HealthResponse>) {
+    let deadline = config.db_busy_timeout;
+    let mut components = BTreeMap::new();
+
+    components.insert("database", check(deadline, "database", async {
+        db.health.ping().await.map_err(|e| ("unreachable".to_string(), e.to_string()))
+    }).await);
+
+    components.insert("migrations", check(deadline, "migrations", async {
+        match db.health.pending_migrations().await {
+            Ok(pending) if pending.is_empty() => Ok(()),
+            Ok(pending) => Err((format!("{} pending", pending.len()), format!("pending versions {:?}", pending))),
+            Err(e) => Err(("unknown".to_string(), e.to_string())),
+        }
+    }).await);
+
+    components.insert("session_store", check(deadline, "session_store", async {
+        probe_session_store(&store).await.map_err(|e| ("unusable".to_string(), e))
+    }).await);
+
+    let ready = components.values().all(|component| component.status == "ok");
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(HealthResponse { status: if ready { "ok" } else { "unavailable" }, version: VERSION, components }))
+}
+
+// Runs a readiness check within the deadline. Failures are (public message, logged detail)
+async fn check(
+    deadline: Duration,
+    component: &'static str,
+    probe: impl Future<Output = Result<(), (String, String)>>,
+) -> ComponentStatus {
+    let error = match timeout(deadline, probe).await {
+        Ok(Ok(())) => return ComponentStatus { status: "ok", error: None },
+        Ok(Err((error, detail))) => {
+            warn!(component, detail, "Readiness check failed");
+            error
+        }
+        Err(_) => {
+            warn!(component, "Readiness check timed out");
+            "timeout".to_string()
+        }
+    };
+    ComponentStatus { status: "error", error: Some(error) }
+}
+
+// Stores, loads and destroys a throwaway session
+async fn probe_session_store(store: &MemoryStore) -> Result<(), String> {
+    let mut session = Session::new();
+    session.insert("probe", true).map_err(|e| e.to_string())?;
+    let cookie = store
+        .store_session(session)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("no cookie value returned")?;
+    let session = store
+        .load_session(cookie)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("stored session not found")?;
+    store.destroy_session(session).await.map_err(|e| e.to_string())
+}
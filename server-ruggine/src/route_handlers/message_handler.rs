@@ -1,36 +1,51 @@
-use axum::{extract::{State, Path}, Json};
+use axum::{extract::State, Json};
+use crate::utilities::extract::Path;
 use axum::http::StatusCode;
-use sqlx::SqlitePool;
+use crate::db_mapper::Repositories;
 use crate::route_handlers::user_handler::AuthUser;
 use crate::utilities::error::MyError;
+use crate::utilities::i18n;
 use crate::db_mapper::message::{get_messages_for_chat, Message};
 use serde::Deserialize;
-use crate::routes::ApiResponse;
+use crate::state::EventBus;
+use crate::utilities::validation::{normalize_text, Normalize, ValidJson};
+use validator::Validate;
 
 // Handler to get all messages for a specific chat
-// Returns a JSON array of Message objects
+// Returns a JSON array of Message objects, system messages rendered in the reader's language
 pub async fn get_chat_messages_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
     AuthUser(username): AuthUser,
     Path(chat_id): Path<i64>,
 ) -> Result<Json<Vec<Message>>, MyError> {
-    let messages = get_messages_for_chat(&pool, chat_id, &username).await?;
+    let messages = get_messages_for_chat(&db, chat_id, &username, i18n::current()).await?;
     Ok(Json(messages))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct SendMessagePayload {
+    #[validate(length(min = 1, max = 4000))]
     pub msg: String,
+    // Optional id generated by the client: retrying a send with the same id does not store the message twice
+    #[validate(length(min = 1, max = 64))]
+    pub client_id: Option<String>,
+}
+
+impl Normalize for SendMessagePayload {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.msg);
+    }
 }
 
 // Handler to send a message in a specific chat
-// Returns a success message upon successful sending
+// Returns the stored message, with its id and server timestamp
 pub async fn send_message_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
+    State(events): State<EventBus>,
     AuthUser(username): AuthUser,
     Path(chat_id): Path<i64>,
-    Json(payload): Json<SendMessagePayload>,
-) -> Result<(StatusCode, Json<ApiResponse>), MyError> {
-    crate::db_mapper::message::insert_message(&pool, chat_id, &username, &payload.msg, false).await?;
-    Ok((StatusCode::OK, Json(ApiResponse { message: "Message successfully sent.".to_string() })))
+    ValidJson(payload): ValidJson<SendMessagePayload>,
+) -> Result<(StatusCode, Json<Message>), MyError> {
+    let message = crate::db_mapper::message::insert_message(&db, &events, chat_id, &username, &payload.msg, payload.client_id.as_deref()).await?;
+    Ok((StatusCode::OK, Json(message)))
 }
\ No newline at end of file
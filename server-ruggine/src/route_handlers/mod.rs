@@ -2,4 +2,7 @@ pub mod user_handler;
 pub mod chat_handler;
 pub mod request_handler;
 pub mod message_handler;
+pub mod health_handler;
+pub mod two_factor_handler;
+pub mod profile_handler;
 pub(crate) mod ws_handler;
\ No newline at end of file
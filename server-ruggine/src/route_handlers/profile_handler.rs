@@ -0,0 +1,112 @@
+use axum::{
+    body::Bytes,
+    extract::{rejection::BytesRejection, State},
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use crate::db_mapper::Repositories;
+use crate::db_mapper::user::{self, Profile, PublicProfile, UpdateAccountRequest, UserSearchPage, UserSearchQuery};
+use crate::route_handlers::user_handler::AuthUser;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+use crate::utilities::extract::Path;
+use crate::utilities::validation::{ValidJson, ValidQuery};
+
+// Handler for `GET /me`: the profile and settings of the signed-in user
+pub async fn get_me_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+) -> Result<Json<Profile>, MyError> {
+    Ok(Json(user::get_profile(&db, &username).await?))
+}
+
+// Handler for `PATCH /me`: changes the profile and settings of the user, returning the updated profile
+pub async fn update_me_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    ValidJson(payload): ValidJson<UpdateAccountRequest>,
+) -> Result<Json<Profile>, MyError> {
+    Ok(Json(user::update_account(&db, &username, &payload).await?))
+}
+
+// Handler for `GET /users/:username`: the public profile of any user
+pub async fn get_user_profile_handler(
+    State(db): State<Repositories>,
+    AuthUser(_): AuthUser,
+    Path(username): Path<String>,
+) -> Result<Json<PublicProfile>, MyError> {
+    Ok(Json(user::get_public_profile(&db, &username).await?))
+}
+
+// Handler for `GET /users?q=`: the users matching the text, to start chats with or invite.
+// Paginated with `limit` (20 by default, at most 50) and `offset`
+pub async fn search_users_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    ValidQuery(query): ValidQuery<UserSearchQuery>,
+) -> Result<Json<UserSearchPage>, MyError> {
+    Ok(Json(user::search_users(&db, &username, &query).await?))
+}
+
+// Handler for `GET /me/blocks`: the users blocked by the user
+pub async fn blocked_users_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+) -> Result<Json<Vec<String>>, MyError> {
+    Ok(Json(user::get_blocked_users(&db, &username).await?))
+}
+
+// Handler for `PUT /me/blocks/:username`, returning the updated list
+pub async fn block_user_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    Path(blocked): Path<String>,
+) -> Result<Json<Vec<String>>, MyError> {
+    user::block_user(&db, &username, &blocked).await?;
+    Ok(Json(user::get_blocked_users(&db, &username).await?))
+}
+
+// Handler for `DELETE /me/blocks/:username`, returning the updated list
+pub async fn unblock_user_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    Path(blocked): Path<String>,
+) -> Result<Json<Vec<String>>, MyError> {
+    user::unblock_user(&db, &username, &blocked).await?;
+    Ok(Json(user::get_blocked_users(&db, &username).await?))
+}
+
+// Handler for `PUT /me/avatar`: the body is the image itself (PNG, JPEG or WebP), stored cropped and resized
+pub async fn upload_avatar_handler(
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
+    AuthUser(username): AuthUser,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<Json<Profile>, MyError> {
+    Ok(Json(user::set_avatar(&db, &config, &username, body?).await?))
+}
+
+// Handler for `DELETE /me/avatar`
+pub async fn delete_avatar_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+) -> Result<Json<Profile>, MyError> {
+    Ok(Json(user::delete_avatar(&db, &username).await?))
+}
+
+// Handler for `GET /users/:username/avatar`: the avatar as PNG.
+// Its URL in the profiles changes with the image, so clients may keep it for a while
+pub async fn get_avatar_handler(
+    State(db): State<Repositories>,
+    AuthUser(_): AuthUser,
+    Path(username): Path<String>,
+) -> Result<Response, MyError> {
+    let avatar = user::get_avatar(&db, &username).await?;
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static("image/png")),
+        (header::CACHE_CONTROL, HeaderValue::from_static("private, max-age=86400")),
+    ];
+    Ok((headers, avatar.image).into_response())
+}
@@ -1,103 +1,75 @@
-use axum::{extract::{State, Path}, Json, http::StatusCode};
-use sqlx::SqlitePool;
+use axum::{extract::State, Json, http::StatusCode};
+use crate::utilities::extract::Path;
+use crate::db_mapper::Repositories;
 use crate::db_mapper::request::{InviteRequest, insert_requests_per_user, delete_request, get_requests_for_user, accept_request, UserRequest};
 use crate::utilities::error::MyError;
 use crate::routes::ApiResponse;
+use crate::utilities::i18n::Text;
 use crate::route_handlers::user_handler::AuthUser;
+use crate::state::EventBus;
+use crate::utilities::validation::ValidJson;
 
 // Handler to insert multiple invite requests
-// Returns a message indicating how many users were invited and which were not found
+// Returns a message indicating how many users were invited and which were not found.
+// When none of the users exists, the request fails with `users_not_found`, listing them in the details
 pub async fn request_handler_insert(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
     AuthUser(from_user): AuthUser,
     Path(chat_id): Path<i64>,
-    Json(payload): Json<InviteRequest>,
+    ValidJson(payload): ValidJson<InviteRequest>,
 ) -> Result<(StatusCode, Json<ApiResponse>), MyError> {
-    let not_found_users = insert_requests_per_user(&pool, chat_id, AuthUser(from_user), &payload.to).await?;
+    let not_found_users = insert_requests_per_user(&db, chat_id, AuthUser(from_user), &payload.to).await?;
+    let found_users = payload.to.len() - not_found_users.len();
 
-    let total_users = payload.to.len();
-    let found_users = total_users - not_found_users.len();
-    
-    let message = match (found_users, not_found_users.len()) {
-        // All users found and invited
-        (n, 0) if n > 0 => "All users invited to the group successfully.".to_string(),
-
-        // No users found
-        (0, n) if n > 0 => format!(
-            "No users could be invited. These users were not found: {}",
-            not_found_users.join(", ")
-        ),
-
-        // Some users found, some not found
-        (found, not_found_count) if found > 0 && not_found_count > 0 => format!(
-            "Invitations sent successfully to {} user(s). However, these users were not found: {}",
-            found,
-            not_found_users.join(", ")
-        ),
-
-        // Fallback case (should not occur)
-        _ => "Unexpected error occurred.".to_string(),
-    };
-
-    let status_code = if found_users > 0 {
-        StatusCode::CREATED
+    let message = if not_found_users.is_empty() {
+        Text::AllUsersInvited
     } else {
-        StatusCode::NOT_FOUND
+        Text::UsersInvited { count: found_users, not_found: &not_found_users }
     };
 
     Ok((
-        status_code,
-        Json(ApiResponse { message }),
+        StatusCode::CREATED,
+        Json(ApiResponse::new(message)),
     ))
 }
 
 // Handler to decline an invite request
 // Uses AuthUser for the recipient
 pub async fn request_handler_decline(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
     AuthUser(username): AuthUser,
     Path(chat_id): Path<i64>,
 ) -> Result<(StatusCode, Json<ApiResponse>), MyError> {
-    let deleted = delete_request(&pool, chat_id, &username).await?;
-    if deleted {
-        Ok((
-            StatusCode::OK,
-            Json(ApiResponse {
-                message: "Richiesta di invito rifiutata.".to_string(),
-            }),
-        ))
-    } else {
-        Ok((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse {
-                message: "Nessuna richiesta trovata da rifiutare.".to_string(),
-            }),
-        ))
+    if !delete_request(&db, chat_id, &username).await? {
+        return Err(MyError::RequestNotFound);
     }
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::new(Text::InvitationDeclined)),
+    ))
 }
 
 // Handler to accept an invite request
 // Uses AuthUser for the recipient
 pub async fn request_handler_accept(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
+    State(events): State<EventBus>,
     AuthUser(username): AuthUser,
     Path(chat_id): Path<i64>,
 ) -> Result<(StatusCode, Json<ApiResponse>), MyError> {
-    accept_request(&pool, chat_id, AuthUser(username)).await?;
+    accept_request(&db, &events, chat_id, AuthUser(username)).await?;
     Ok((
         StatusCode::OK,
-        Json(ApiResponse {
-            message: "Richiesta accettata e utente aggiunto al gruppo.".to_string(),
-        }),
+        Json(ApiResponse::new(Text::InvitationAccepted)),
     ))
 }
 
 // Handler to get all invite requests for the authenticated user
 // Returns a JSON array of UserRequest objects
 pub async fn get_user_requests_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
     AuthUser(username): AuthUser,
 ) -> Result<Json<Vec<UserRequest>>, MyError> {
-    let requests = get_requests_for_user(&pool, AuthUser(username)).await?;
+    let requests = get_requests_for_user(&db, AuthUser(username)).await?;
     Ok(Json(requests))
 }
\ No newline at end of file
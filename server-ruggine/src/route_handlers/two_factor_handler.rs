@@ -0,0 +1,39 @@
+use axum::{extract::State, http::StatusCode, Json};
+use std::sync::Arc;
+use crate::db_mapper::Repositories;
+use crate::db_mapper::two_factor::{self, RecoveryCodes, TwoFactorCode, TwoFactorEnrollment};
+use crate::route_handlers::user_handler::AuthUser;
+use crate::routes::ApiResponse;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::Text;
+use crate::utilities::validation::ValidJson;
+
+// Handler for `POST /me/2fa`: starts the enrolment, returning the secret and its `otpauth://` URI.
+// Calling it again before the confirmation replaces the secret
+pub async fn enroll_two_factor_handler(
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
+    AuthUser(username): AuthUser,
+) -> Result<(StatusCode, Json<TwoFactorEnrollment>), MyError> {
+    Ok((StatusCode::CREATED, Json(two_factor::enroll(&db, &config, &username).await?)))
+}
+
+// Handler for `POST /me/2fa/confirm`: enables 2FA with a first code from the app, returning the recovery codes
+pub async fn confirm_two_factor_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    ValidJson(payload): ValidJson<TwoFactorCode>,
+) -> Result<Json<RecoveryCodes>, MyError> {
+    Ok(Json(two_factor::confirm(&db, &username, &payload.code).await?))
+}
+
+// Handler for `DELETE /me/2fa`: disables 2FA, given a TOTP or recovery code
+pub async fn disable_two_factor_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    ValidJson(payload): ValidJson<TwoFactorCode>,
+) -> Result<Json<ApiResponse>, MyError> {
+    two_factor::disable(&db, &username, &payload.code).await?;
+    Ok(Json(ApiResponse::new(Text::TwoFactorDisabled)))
+}
@@ -1,15 +1,34 @@
 use axum::{
     async_trait,
-    extract::{FromRequestParts, Extension, State},
-    http::{request::Parts, StatusCode, HeaderMap, HeaderValue},
+    extract::{ConnectInfo, FromRequestParts, Extension, State},
+    http::{header, request::Parts, StatusCode, HeaderMap, HeaderValue},
     Json,
 };
-use sqlx::SqlitePool;
-use crate::db_mapper::user::{self, CreateUserRequest, LoginRequest};
+use crate::db_mapper::Repositories;
+use crate::db_mapper::login_attempt::{self, Authentication, LoginAttempt};
+use crate::db_mapper::password_reset::{self, PasswordResetConfirm, PasswordResetRequest};
+use crate::db_mapper::two_factor::TwoFactorCode;
+use crate::db_mapper::user::{self, ChangePasswordRequest, CreateUserRequest, LoginRequest};
 use crate::routes::ApiResponse;
+use crate::state::AppState;
+use crate::utilities::config::Config;
 use crate::utilities::error::MyError;
+use crate::utilities::extract::Query;
+use crate::utilities::i18n::{self, Text};
+use crate::utilities::logging::record_username;
+use crate::utilities::notifier::Notifier;
+use crate::utilities::rate_limit::{retry_after_secs, Rule};
+use crate::utilities::sessions::SessionRevocations;
+use crate::utilities::validation::ValidJson;
 use async_session::{Session, MemoryStore, SessionStore};
 use headers::{Cookie, HeaderMapExt};
+use tracing::error;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// Session key of a sign-in waiting for its second factor. Authenticated sessions hold the user under "user"
+const PENDING_USER: &str = "pending_user";
 
 // Extractor for authentication
 pub struct AuthUser(pub String);
@@ -20,98 +39,261 @@ where
     MemoryStore: Send + Sync,
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = MyError;
 
     /// Extracts an authenticated user from the request parts.
     /// This implementation retrieves the session cookie, validates the session,
     /// and extracts the username from the session data.
-    /// Returns a rejection with an appropriate HTTP status code and error message
+    /// Missing, unknown, expired and pending (second factor) sessions are all rejected as `Unauthenticated`
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let Extension(store) = Extension::<MemoryStore>::from_request_parts(parts, state)
             .await
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Session store missing".to_string()))?;
+            .map_err(|_| MyError::Unknown)?;
+        let Extension(revocations) = Extension::<SessionRevocations>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| MyError::Unknown)?;
 
-        let cookies = parts.headers.typed_get::<Cookie>();
-        let Some(cookie) = cookies.and_then(|c| c.get("axum_session").map(|v| v.to_string())) else {
-            return Err((StatusCode::UNAUTHORIZED, "No session cookie".to_string()));
+        let Some(cookie) = session_cookie(&parts.headers) else {
+            return Err(MyError::Unauthenticated);
         };
 
-        let Some(session) = store.load_session(cookie.to_string()).await.unwrap() else {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid session".to_string()));
+        let Some(session) = store.load_session(cookie).await.map_err(|_| MyError::Unknown)? else {
+            return Err(MyError::Unauthenticated);
         };
 
         let Some(user) = session.get::<String>("user") else {
-            return Err((StatusCode::UNAUTHORIZED, "User not logged in".to_string()));
+            return Err(MyError::Unauthenticated);
         };
 
+        // Sessions revoked by a password change or reset are discarded
+        if revocations.is_revoked(&user, &session) {
+            let _ = store.destroy_session(session).await;
+            return Err(MyError::SessionRevoked);
+        }
+
+        record_username(&user);
         Ok(AuthUser(user))
     }
 }
 
 // Handler for creating a new user
 pub async fn create_user_handler(
-    State(pool): State<SqlitePool>,
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
     Extension(_store): Extension<MemoryStore>,
-    Json(payload): Json<CreateUserRequest>,
-) -> (StatusCode, Json<ApiResponse>) {
-    match user::create_user(&pool, payload).await {
-        Ok(_) => (StatusCode::CREATED, Json(ApiResponse { message: "User created successfully.".to_string() })),
-        Err(MyError::UsernameExists) => (StatusCode::CONFLICT, Json(ApiResponse { message: "Username already exists.".to_string() })),
-        Err(MyError::PasswordHashingFailed) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse { message: "password hashing failed.".to_string() })),
-        Err(MyError::SqlxError(e)) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse { message: format!("Database error: {}", e) })),
-        _ => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse { message: "Internal Error.".to_string() })),
-    }
+    ValidJson(payload): ValidJson<CreateUserRequest>,
+) -> Result<(StatusCode, Json<ApiResponse>), MyError> {
+    user::create_user(&db, &config, payload).await?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::new(Text::UserCreated))))
+}
+
+// Answer of the sign-in endpoints
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    pub message: String,
+    // Set when the password was right and the code of the second factor must follow on `POST /login/2fa`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub two_factor_required: bool,
 }
 
 // Handler for user login
+// Every attempt is recorded with the client IP and user agent; repeated failures delay and then lock the username.
+// For users with 2FA enabled the session only holds a pending sign-in, which `AuthUser` and `ws_handler` do not accept
 pub async fn login_handler(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
     Extension(store): Extension<MemoryStore>,
-    Json(payload): Json<LoginRequest>,
-) -> (StatusCode, HeaderMap, Json<ApiResponse>) {
-    match user::verify_user(&pool, &payload.username, &payload.password).await {
-        Ok(true) => {
+    Extension(revocations): Extension<SessionRevocations>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+    ValidJson(payload): ValidJson<LoginRequest>,
+) -> Result<(StatusCode, HeaderMap, Json<LoginResponse>), MyError> {
+    let AppState { db, config, limiter, .. } = state;
+    // The route is limited per client IP, and attempts on the same username per username
+    limiter
+        .check_username(Rule::Login, &payload.username)
+        .map_err(|wait| MyError::TooManyRequests(retry_after_secs(wait)))?;
+
+    let ip = limiter.client_ip(&request_headers, Some(peer)).map(|ip| ip.to_string());
+    let user_agent = request_headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+
+    match login_attempt::authenticate(&db, &config, &payload.username, &payload.password, ip.as_deref(), user_agent).await? {
+        Authentication::Authenticated => {
+            record_username(&payload.username);
+            let headers = start_session(&store, &revocations, &config, &payload.username).await;
+            Ok((
+                StatusCode::OK,
+                headers,
+                Json(LoginResponse { message: i18n::localize(&Text::LoginSuccessful), two_factor_required: false }),
+            ))
+        }
+        Authentication::SecondFactorRequired => {
             let mut session = Session::new();
-            session.insert("user", &payload.username).unwrap();
+            session.insert(PENDING_USER, &payload.username).unwrap();
+            session.expire_in(config.two_factor_challenge_ttl);
             let cookie = store.store_session(session).await.unwrap().unwrap();
             let mut headers = HeaderMap::new();
-            headers.insert("Set-Cookie", HeaderValue::from_str(&format!("axum_session={}; Path=/; HttpOnly", cookie)).unwrap());
-            (
-                StatusCode::OK,
+            headers.insert("Set-Cookie", set_cookie(&format!("axum_session={}; Path=/; HttpOnly", cookie), &config));
+            Ok((
+                StatusCode::ACCEPTED,
                 headers,
-                Json(ApiResponse { message: "Login successful".to_string() }),
-            )
+                Json(LoginResponse { message: i18n::localize(&Text::TwoFactorRequired), two_factor_required: true }),
+            ))
         }
-        Ok(false) => (
-            StatusCode::UNAUTHORIZED,
-            HeaderMap::new(),
-            Json(ApiResponse { message: "Invalid username or password".to_string() }),
-        ),
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            HeaderMap::new(),
-            Json(ApiResponse { message: format!("Error during login: {}", err) }),
-        ),
+        Authentication::Failed => Err(MyError::InvalidCredentials),
+    }
+}
+
+// Handler for `POST /login/2fa`: completes a pending sign-in with a TOTP or recovery code.
+// The pending session is replaced by a new one, authenticated
+pub async fn login_second_factor_handler(
+    State(state): State<AppState>,
+    Extension(store): Extension<MemoryStore>,
+    Extension(revocations): Extension<SessionRevocations>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+    ValidJson(payload): ValidJson<TwoFactorCode>,
+) -> Result<(StatusCode, HeaderMap, Json<LoginResponse>), MyError> {
+    let AppState { db, config, limiter, .. } = state;
+    let Some(cookie) = session_cookie(&request_headers) else {
+        return Err(MyError::NoPendingSignIn);
+    };
+    let Some(pending) = store.load_session(cookie).await.unwrap() else {
+        return Err(MyError::NoPendingSignIn);
+    };
+    let Some(username) = pending.get::<String>(PENDING_USER) else {
+        return Err(MyError::NoPendingSignIn);
+    };
+    // Codes tried for the same account from many addresses share a bucket
+    limiter
+        .check_username(Rule::TwoFactor, &username)
+        .map_err(|wait| MyError::TooManyRequests(retry_after_secs(wait)))?;
+
+    let ip = limiter.client_ip(&request_headers, Some(peer)).map(|ip| ip.to_string());
+    let user_agent = request_headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+    if !login_attempt::authenticate_second_factor(&db, &config, &username, &payload.code, ip.as_deref(), user_agent).await? {
+        return Err(MyError::InvalidTwoFactorCode);
     }
+
+    record_username(&username);
+    let _ = store.destroy_session(pending).await;
+    let headers = start_session(&store, &revocations, &config, &username).await;
+    Ok((
+        StatusCode::OK,
+        headers,
+        Json(LoginResponse { message: i18n::localize(&Text::LoginSuccessful), two_factor_required: false }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginHistoryQuery {
+    pub limit: Option<i64>,
+}
+
+// Handler for `GET /me/logins`: the most recent sign-in attempts on the user's account (20 by default, at most 100)
+pub async fn login_history_handler(
+    State(db): State<Repositories>,
+    AuthUser(username): AuthUser,
+    Query(query): Query<LoginHistoryQuery>,
+) -> Result<Json<Vec<LoginAttempt>>, MyError> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    Ok(Json(login_attempt::user_attempts(&db, &username, limit).await?))
 }
 
 // Handler for user logout
 pub async fn logout_handler(
+    State(config): State<Arc<Config>>,
     Extension(store): Extension<MemoryStore>,
     headers: HeaderMap,
 ) -> (StatusCode, HeaderMap, Json<ApiResponse>) {
     let mut response_headers = HeaderMap::new();
-    if let Some(cookie) = headers.typed_get::<Cookie>() {
-        if let Some(session_cookie) = cookie.get("axum_session") {
-            if let Some(session) = store.load_session(session_cookie.to_string()).await.unwrap() {
-                let _ = store.destroy_session(session).await;
-            }
-            response_headers.insert("Set-Cookie", HeaderValue::from_static("axum_session=deleted; Path=/; Max-Age=0"));
+    if let Some(session_cookie) = session_cookie(&headers) {
+        if let Some(session) = store.load_session(session_cookie).await.unwrap() {
+            let _ = store.destroy_session(session).await;
         }
+        response_headers.insert("Set-Cookie", set_cookie("axum_session=deleted; Path=/; Max-Age=0", &config));
     }
     (
         StatusCode::OK,
         response_headers,
-        Json(ApiResponse { message: "Logout successful.".to_string() }),
+        Json(ApiResponse::new(Text::LoggedOut)),
+    )
+}
+
+// Handler for `POST /me/password`: changes the password of the user, signing out all their other sessions
+pub async fn change_password_handler(
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
+    Extension(store): Extension<MemoryStore>,
+    Extension(revocations): Extension<SessionRevocations>,
+    AuthUser(username): AuthUser,
+    headers: HeaderMap,
+    ValidJson(payload): ValidJson<ChangePasswordRequest>,
+) -> Result<Json<ApiResponse>, MyError> {
+    user::change_password(&db, &config, &username, &payload).await?;
+
+    // The session making the request stays valid
+    if let Some(cookie) = session_cookie(&headers)
+        && let Ok(Some(mut session)) = store.load_session(cookie).await
+    {
+        revocations.revoke_others(&username, &mut session);
+        let _ = store.store_session(session).await;
+    } else {
+        revocations.revoke_all(&username);
+    }
+
+    Ok(Json(ApiResponse::new(Text::PasswordChanged)))
+}
+
+// Handler for `POST /password-reset`: sends a reset token to the user through the notifier.
+// The answer is the same whether or not the user exists, and is given before the token is even created
+pub async fn request_password_reset_handler(
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
+    State(notifier): State<Arc<dyn Notifier>>,
+    ValidJson(payload): ValidJson<PasswordResetRequest>,
+) -> (StatusCode, Json<ApiResponse>) {
+    tokio::spawn(async move {
+        if let Err(e) = password_reset::request_reset(&db, notifier.as_ref(), &config, &payload.username).await {
+            error!(error = %e, "Unable to send the password reset token");
+        }
+    });
+
+    (
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::new(Text::ResetTokenSent)),
     )
-}
\ No newline at end of file
+}
+
+// Handler for `POST /password-reset/confirm`: sets a new password with a reset token, signing out every session of the user
+pub async fn confirm_password_reset_handler(
+    State(db): State<Repositories>,
+    State(config): State<Arc<Config>>,
+    Extension(revocations): Extension<SessionRevocations>,
+    ValidJson(payload): ValidJson<PasswordResetConfirm>,
+) -> Result<Json<ApiResponse>, MyError> {
+    let username = password_reset::confirm_reset(&db, &config, &payload).await?;
+    revocations.revoke_all(&username);
+    Ok(Json(ApiResponse::new(Text::PasswordReset)))
+}
+
+// Creates the authenticated session of the user, returning the header setting its cookie
+async fn start_session(store: &MemoryStore, revocations: &SessionRevocations, config: &Config, username: &str) -> HeaderMap {
+    let mut session = Session::new();
+    session.insert("user", username).unwrap();
+    revocations.stamp(username, &mut session);
+    let cookie = store.store_session(session).await.unwrap().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("Set-Cookie", set_cookie(&format!("axum_session={}; Path=/; HttpOnly", cookie), config));
+    headers
+}
+
+// Value of the session cookie of the request
+pub(crate) fn session_cookie(headers: &HeaderMap) -> Option<String> {
+    headers.typed_get::<Cookie>().and_then(|c| c.get("axum_session").map(|v| v.to_string()))
+}
+
+// Builds the session Set-Cookie header, marking the cookie `Secure` when the server is served over TLS
+fn set_cookie(cookie: &str, config: &Config) -> HeaderValue {
+    let cookie = if config.tls_enabled() { format!("{}; Secure", cookie) } else { cookie.to_string() };
+    HeaderValue::from_str(&cookie).unwrap()
+}
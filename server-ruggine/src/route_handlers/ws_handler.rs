@@ -1,128 +1,510 @@
 use axum::{
-    extract::{ws::{WebSocketUpgrade, WebSocket, Message}, State, Extension},
+    extract::{ws::{rejection::WebSocketUpgradeRejection, WebSocketUpgrade, WebSocket, Message, CloseFrame}, ConnectInfo, State, Extension},
     response::IntoResponse,
-    http::{StatusCode, HeaderMap},
-    Json,
+    http::{HeaderMap, StatusCode},
 };
-use sqlx::SqlitePool;
-use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
-use once_cell::sync::Lazy;
+use crate::db_mapper::Repositories;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use async_session::MemoryStore;
 use headers::{Cookie, HeaderMapExt};
 use async_session::SessionStore;
-use futures_util::stream::StreamExt;
+use futures_util::stream::{SplitSink, StreamExt};
 use futures_util::SinkExt;
+use crate::state::AppState;
+use crate::events::queue::{ConnectionQueue, MessageRef, Outbound};
+use crate::utilities::error::{ErrorBody, MyError};
+use crate::utilities::extract::Query;
+use crate::utilities::i18n::{self, Locale};
+use crate::utilities::logging::record_username;
+use crate::utilities::rate_limit::{retry_after_secs, ClientLimiter, Rule};
+use crate::utilities::validation::{normalize_text, Normalize};
+use validator::Validate;
+use crate::utilities::sessions::SessionRevocations;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use metrics::{counter, gauge};
+use tracing::{debug, error, info, warn, Instrument};
 
-#[derive(Serialize, Deserialize, Debug)]
+// Checked like the body of `POST /chats/:chatId/messages`
+#[derive(Serialize, Deserialize, Validate)]
 struct ChatMessage {
     chat_id: i64,
     from_user: String,
+    #[validate(length(min = 1, max = 4000))]
     msg: String,
-    send_at: Option<String>
+    // Ignored: the server timestamp is returned in the `ack` frame
+    send_at: Option<String>,
+    // Optional id generated by the client to deduplicate retried sends
+    #[validate(length(min = 1, max = 64))]
+    client_id: Option<String>,
+}
+
+impl Normalize for ChatMessage {
+    fn normalize(&mut self) {
+        normalize_text(&mut self.msg);
+    }
+}
+
+// Message contents never end up in the logs: only their length does
+impl fmt::Debug for ChatMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChatMessage")
+            .field("chat_id", &self.chat_id)
+            .field("msg_len", &self.msg.len())
+            .field("client_id", &self.client_id)
+            .finish()
+    }
+}
+
+// Last messages seen by a reconnecting client: everything newer is replayed before live delivery.
+// `last_id` applies to every chat not listed in `chats` (chat id -> last message id seen)
+#[derive(Deserialize, Debug, Default)]
+pub struct SyncRequest {
+    last_id: Option<i64>,
+    #[serde(default)]
+    chats: HashMap<i64, i64>,
+}
+
+// The same request in the query string of the WebSocket URL, where `chats` lists `chat_id:last_id` pairs
+// separated by commas, e.g. `?last_id=500&chats=12:340,15:400`
+#[derive(Deserialize, Debug, Default)]
+pub struct SyncQuery {
+    last_id: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_chat_cutoffs")]
+    chats: HashMap<i64, i64>,
+}
+
+impl From<SyncQuery> for SyncRequest {
+    fn from(query: SyncQuery) -> Self {
+        SyncRequest { last_id: query.last_id, chats: query.chats }
+    }
+}
+
+fn deserialize_chat_cutoffs<'de, D>(deserializer: D) -> Result<HashMap<i64, i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            pair.split_once(':')
+                .and_then(|(chat_id, last_id)| Some((chat_id.parse().ok()?, last_id.parse().ok()?)))
+                .ok_or_else(|| de::Error::custom(format!("invalid chat cutoff `{}`, expected chat_id:last_id", pair)))
+        })
+        .collect()
+}
+
+// Messages already sent to the client, per chat: live events carrying them are skipped.
+// Chats the client did not list are covered by its `last_id`
+#[derive(Debug, Default)]
+struct Replayed {
+    default: i64,
+    chats: HashMap<i64, i64>,
+}
+
+impl Replayed {
+    // Starts from what the client reports having seen
+    fn from_request(request: &SyncRequest) -> Self {
+        Replayed { default: request.last_id.unwrap_or(0), chats: request.chats.clone() }
+    }
+
+    fn get(&self, chat_id: i64) -> i64 {
+        self.chats.get(&chat_id).copied().unwrap_or(self.default)
+    }
+
+    fn record(&mut self, message: MessageRef) {
+        let last = self.get(message.chat_id).max(message.id);
+        self.chats.insert(message.chat_id, last);
+    }
+
+    fn contains(&self, message: MessageRef) -> bool {
+        message.id <= self.get(message.chat_id)
+    }
+
+    // Adds what a later sync replayed
+    fn merge(&mut self, other: Replayed) {
+        let chat_ids: Vec<i64> = self.chats.keys().chain(other.chats.keys()).copied().collect();
+        let chats = chat_ids.into_iter().map(|chat_id| (chat_id, self.get(chat_id).max(other.get(chat_id)))).collect();
+        self.chats = chats;
+        self.default = self.default.max(other.default);
+    }
+
+    // Highest message id sent or seen, reported to the client as its new `last_id`
+    fn last_id(&self) -> i64 {
+        self.chats.values().copied().fold(self.default, i64::max)
+    }
+}
+
+// Frames received from the client: control frames are tagged by `type`, chat messages are untagged
+#[derive(Debug)]
+enum ClientFrame {
+    Sync(SyncRequest),
+    Chat(ChatMessage),
+}
+
+impl ClientFrame {
+    // The frames are dispatched by hand on `type`: serde's tagged enums cannot deserialize the integer keys of `chats`
+    fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("sync") => serde_json::from_value(value).map(ClientFrame::Sync),
+            Some(other) => Err(serde::de::Error::custom(format!("unknown frame type: {}", other))),
+            None => serde_json::from_value(value).map(ClientFrame::Chat),
+        }
+    }
+
+    // `client_id` of a frame that could not be parsed, when it is JSON carrying one
+    fn client_id(text: &str) -> Option<String> {
+        let value: serde_json::Value = serde_json::from_str(text).ok()?;
+        value.get("client_id")?.as_str().map(str::to_string)
+    }
+}
+
+// Frames sent to the client besides the chat messages, tagged by `type`
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerFrame {
+    // Missed messages have been replayed, live delivery follows
+    SyncComplete { last_id: i64 },
+    // Too many messages were missed, or some were dropped: the client must reload its chats
+    ResyncRequired,
+    // A message sent by the client has been stored
+    Ack { client_id: Option<String>, message: crate::db_mapper::message::Message },
+    // A frame sent by the client could not be handled, described as in the REST error responses
+    Error { client_id: Option<String>, error: ErrorBody },
 }
 
-// Global map to track user WebSocket connections
-// `Arc<Mutex<...>>` is used to allow concurrent and safe access to the global map of WebSocket connections from multiple async tasks
-// `mpsc::UnboundedSender<String>` is used to send messages to WebSocket clients asynchronously and without buffer limits, so each connection
-// can receive messages from multiple parts of the server without blocking or risking deadlocks
-pub static USER_SOCKETS: Lazy<Arc<Mutex<HashMap<String, Vec<mpsc::UnboundedSender<String>>>>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(HashMap::new()))
-});
+impl ServerFrame {
+    fn to_json(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+}
 
 pub async fn ws_handler(
-    ws: WebSocketUpgrade,
-    State(pool): State<SqlitePool>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(sync): Query<SyncQuery>,
     headers: HeaderMap,
     Extension(store): Extension<MemoryStore>,
+    Extension(revocations): Extension<SessionRevocations>,
 ) -> impl IntoResponse {
     // Get session cookie from headers
     let cookies = headers.typed_get::<Cookie>();
     let Some(cookie) = cookies.and_then(|c| c.get("axum_session").map(|v| v.to_string())) else {
-        println!("WebSocket: no session cookie");
-        return (StatusCode::UNAUTHORIZED, Json("No session cookie")).into_response();
+        warn!("WebSocket: no session cookie");
+        return MyError::Unauthenticated.into_response();
+    };
+    // Plain HTTP requests (no upgrade headers) are answered after the authentication, like the other routes
+    let ws = match ws {
+        Ok(ws) => ws,
+        Err(rejection) => return MyError::InvalidRequest(rejection.status(), rejection.body_text()).into_response(),
     };
 
     // Validate session
     match store.load_session(cookie).await {
         Ok(Some(session)) => {
             match session.get::<String>("user") {
+                Some(user) if revocations.is_revoked(&user, &session) => {
+                    warn!("WebSocket: revoked session");
+                    let _ = store.destroy_session(session).await;
+                    MyError::SessionRevoked.into_response()
+                }
                 Some(user) => {
-                    println!("Authenticated on WebSocket for user: {}", user);
+                    record_username(&user);
+                    info!("WebSocket authenticated");
                     // `ws.on_upgrade` closes the HTTP connection and hands control over to the specified async task
-                    // A task is created via the `handle_socket` function, which receives the socket, the database pool and the authenticated username
-                    return ws.on_upgrade(move |socket| handle_socket(socket, pool, user));
+                    // A task is created via the `handle_socket` function, which receives the socket, the application state,
+                    // the rate limiter of the client, the authenticated username, the language chosen for the request
+                    // and the optional `last_id` and `chats` to resync from
+                    let initial_sync = (sync.last_id.is_some() || !sync.chats.is_empty()).then(|| SyncRequest::from(sync));
+                    // The connection gets its own span, outliving the HTTP request that opened it
+                    let span = tracing::info_span!("ws_connection", username = %user, connection_id = tracing::field::Empty);
+                    let limiter = state.limiter.for_client(state.limiter.client_ip(&headers, Some(peer)), &user);
+                    let locale = i18n::current();
+                    ws.on_upgrade(move |socket| handle_socket(socket, state, limiter, user, locale, initial_sync).instrument(span))
                 }
                 None => {
-                    println!("WebSocket: user not logged in");
-                    return (StatusCode::UNAUTHORIZED, Json("User not logged in")).into_response();
+                    warn!("WebSocket: user not logged in");
+                    MyError::Unauthenticated.into_response()
                 }
             }
         }
         Ok(None) => {
-            println!("WebSocket: invalid session");
-            return (StatusCode::UNAUTHORIZED, Json("Invalid session")).into_response();
+            warn!("WebSocket: invalid session");
+            MyError::Unauthenticated.into_response()
         }
         Err(e) => {
-            println!("Session store error: {:?}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json("Session store error")).into_response();
+            error!(error = %e, "Session store error");
+            MyError::Unknown.into_response()
         }
     }
 }
 
-async fn handle_socket(socket: WebSocket, pool: SqlitePool, username: String) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    limiter: ClientLimiter,
+    username: String,
+    locale: Locale,
+    initial_sync: Option<SyncRequest>,
+) {
+    let AppState { db, events, config, .. } = state;
+
     // We split the WebSocket into sender and receiver to handle sending and receiving messages independently
-    // We create an unbounded channel to asynchronously send messages to the client via the sender
     let (mut ws_sender, mut ws_receiver) = socket.split();
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
 
-    // Registration: add the connection to the global map and track each user transmitter
-    {
-        let mut map = USER_SOCKETS.lock().await;
-        map.entry(username.clone()).or_default().push(tx);
-    }
+    // Registration: subscribe the connection to the event bus, which delivers the events addressed to the user in their language
+    let subscription = events.subscribe(&username, locale).await;
+    let connection_id = subscription.id;
+    tracing::Span::current().record("connection_id", connection_id);
+    gauge!("ws_connections_active").increment(1.0);
+    info!("WebSocket connected");
 
+    // Signal used to stop a task when its sibling ends
+    let (stop_tx, mut recv_stop) = watch::channel(false);
+    let mut send_stop = stop_tx.subscribe();
 
-    // Task: receive messages from the client via WebSocket
+    // Sync requests received by the receiving task, served by the sending task
+    let (sync_tx, mut sync_rx) = mpsc::channel::<SyncRequest>(4);
+
+    // Task: receive frames from the client via WebSocket
     // Each received message is parsed and saved to the database
-    let recv_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = ws_receiver.next().await {
-            println!("Message received via WebSocket: {}", text);
-            if let Ok(mut chat_message) = serde_json::from_str::<ChatMessage>(&text) {
-                chat_message.send_at = Some(chrono::Utc::now().to_rfc3339());
-                // Save the message to the database
-                if let Err(e) = crate::db_mapper::message::insert_message(&pool, chat_message.chat_id, &chat_message.from_user, &chat_message.msg, false).await {
-                    println!("Store message error: {:?}", e);
-                    continue;
+    let recv_db = db.clone();
+    let reply_queue = subscription.queue.clone();
+    let recv_events = events.clone();
+    let recv_user = username.clone();
+    let idle_timeout = config.ws_idle_timeout;
+    let mut recv_task = tokio::spawn(async move {
+        loop {
+            // Any frame (including the pongs answering our pings) proves that the client is still alive
+            let frame = tokio::select! {
+                _ = recv_stop.changed() => break,
+                frame = timeout(idle_timeout, ws_receiver.next()) => frame,
+            };
+            let frame = match frame {
+                Ok(Some(Ok(frame))) => frame,
+                Ok(Some(Err(e))) => {
+                    debug!(error = %e, "WebSocket error");
+                    break;
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    info!("WebSocket idle timeout");
+                    break;
                 }
-            } else {
-                println!("Invalid message: {}", text);
+            };
+
+            match frame {
+                Message::Text(text) => {
+                    match ClientFrame::parse(&text) {
+                        Ok(ClientFrame::Chat(mut chat_message)) => {
+                            if let Err(wait) = limiter.check(Rule::Message) {
+                                push_rate_limited(&reply_queue, chat_message.client_id, wait, locale);
+                                continue;
+                            }
+                            chat_message.normalize();
+                            if let Err(errors) = chat_message.validate() {
+                                push_error(&reply_queue, chat_message.client_id, MyError::Validation(errors), locale);
+                                continue;
+                            }
+                            // Save the message to the database, on behalf of the authenticated user,
+                            // and reply with the stored message or the error
+                            debug!(chat_id = chat_message.chat_id, msg_len = chat_message.msg.len(), "Message received via WebSocket");
+                            let client_id = chat_message.client_id;
+                            let reply = match crate::db_mapper::message::insert_message(&recv_db, &recv_events, chat_message.chat_id, &recv_user, &chat_message.msg, client_id.as_deref()).await {
+                                Ok(message) => ServerFrame::Ack { client_id, message },
+                                Err(e) => {
+                                    warn!(chat_id = chat_message.chat_id, error = %e, "Unable to store message");
+                                    ServerFrame::Error { client_id, error: e.body(locale) }
+                                }
+                            };
+                            if let Some(json) = reply.to_json() {
+                                reply_queue.reply(json);
+                            }
+                        }
+                        Ok(ClientFrame::Sync(request)) => {
+                            if let Err(wait) = limiter.check(Rule::WsSync) {
+                                push_rate_limited(&reply_queue, None, wait, locale);
+                                continue;
+                            }
+                            debug!(last_id = request.last_id, chats = request.chats.len(), "Sync requested");
+                            if sync_tx.send(request).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            warn!(error = %e, len = text.len(), "Invalid WebSocket frame");
+                            let error = MyError::InvalidRequest(StatusCode::BAD_REQUEST, e.to_string());
+                            push_error(&reply_queue, ClientFrame::client_id(&text), error, locale);
+                        }
+                    }
+                }
+                Message::Binary(_) => debug!("Binary frames are not supported, ignored"),
+                // Pings are answered automatically, pongs only refresh the idle timeout
+                Message::Ping(_) | Message::Pong(_) => {}
+                Message::Close(_) => break,
             }
         }
-    });
+    }.in_current_span());
 
-    // Task: receive messages from the channel and send them to the client via WebSocket
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if ws_sender.send(Message::Text(msg)).await.is_err() {
-                break;
+    // Task: receive events from the connection queue and send them to the client via WebSocket,
+    // pinging the client at regular intervals
+    let mut heartbeat = interval(config.ws_ping_interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let send_user = username.clone();
+    let resync_limit = config.ws_resync_limit;
+    let mut send_task = tokio::spawn(async move {
+        // Messages already replayed: live events carrying them have already been sent
+        let mut replayed = Replayed::default();
+        if let Some(request) = initial_sync {
+            match replay_missed(&mut ws_sender, &db, &send_user, request, resync_limit, locale).await {
+                Ok(sent) => replayed = sent,
+                Err(_) => return,
             }
         }
-    });
-
-    // Wait for both tasks to complete
-    let _ = tokio::try_join!(send_task, recv_task);
-
-    // Disconnection: remove the connection from the global map
-    {
-        let mut map = USER_SOCKETS.lock().await;
-        if let Some(vec) = map.get_mut(&username) {
-            vec.retain(|sender| !sender.is_closed());
-            if vec.is_empty() {
-                map.remove(&username);
+
+        // The first tick completes immediately
+        heartbeat.tick().await;
+        loop {
+            tokio::select! {
+                _ = send_stop.changed() => break,
+                Some(request) = sync_rx.recv() => {
+                    match replay_missed(&mut ws_sender, &db, &send_user, request, resync_limit, locale).await {
+                        Ok(sent) => replayed.merge(sent),
+                        Err(_) => break,
+                    }
+                }
+                item = subscription.recv() => match item {
+                    Some(Outbound::Event { message, json }) => {
+                        if message.is_some_and(|message| replayed.contains(message)) {
+                            continue;
+                        }
+                        if ws_sender.send(Message::Text(json)).await.is_err() {
+                            break;
+                        }
+                        if message.is_some() {
+                            counter!("ws_messages_delivered_total").increment(1);
+                        }
+                    }
+                    Some(Outbound::Reply(json)) => {
+                        if ws_sender.send(Message::Text(json)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Outbound::ResyncRequired) => {
+                        if send_frame(&mut ws_sender, &ServerFrame::ResyncRequired).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Outbound::Close(code, reason)) => {
+                        let _ = ws_sender.send(Message::Close(Some(CloseFrame { code, reason: reason.into() }))).await;
+                        break;
+                    }
+                    None => break,
+                },
+                _ = heartbeat.tick() => {
+                    if ws_sender.send(Message::Ping(Vec::new())).await.is_err() {
+                        break;
+                    }
+                }
             }
         }
+    }.in_current_span());
+
+    // As soon as one of the tasks ends, stop the other one and wait for it
+    tokio::select! {
+        _ = &mut send_task => {
+            let _ = stop_tx.send(true);
+            let _ = recv_task.await;
+        }
+        _ = &mut recv_task => {
+            let _ = stop_tx.send(true);
+            let _ = send_task.await;
+        }
+    }
+
+    // Disconnection: remove the connection from the event bus
+    events.unsubscribe(&username, connection_id).await;
+    gauge!("ws_connections_active").decrement(1.0);
+    info!("WebSocket disconnected");
+}
+
+// Sends the messages the client missed, followed by a `sync_complete` frame, or a `resync_required` frame
+// when there are more than `limit`. Returns the messages replayed, or seen by the client, in each chat.
+// The connection is already subscribed to the event bus, so nothing committed in the meantime is lost.
+async fn replay_missed(
+    ws_sender: &mut SplitSink<WebSocket, Message>,
+    db: &Repositories,
+    username: &str,
+    request: SyncRequest,
+    limit: i64,
+    locale: Locale,
+) -> Result<Replayed, axum::Error> {
+    let last_id = request.last_id.unwrap_or(0);
+    let messages = match crate::db_mapper::message::get_messages_since(db, username, last_id, &request.chats, limit, locale).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!(error = %e, "Unable to retrieve missed messages");
+            return send_frame(ws_sender, &ServerFrame::ResyncRequired).await.map(|_| Replayed::default());
+        }
+    };
+
+    if messages.len() as i64 > limit {
+        info!(limit, "Too many missed messages, resync required");
+        return send_frame(ws_sender, &ServerFrame::ResyncRequired).await.map(|_| Replayed::default());
+    }
+
+    let mut replayed = Replayed::from_request(&request);
+    for message in messages {
+        replayed.record(MessageRef { chat_id: message.chat_id, id: message.id });
+        if let Ok(json) = serde_json::to_string(&message) {
+            ws_sender.send(Message::Text(json)).await?;
+        }
     }
-}
\ No newline at end of file
+
+    send_frame(ws_sender, &ServerFrame::SyncComplete { last_id: replayed.last_id() }).await?;
+    Ok(replayed)
+}
+
+async fn send_frame(ws_sender: &mut SplitSink<WebSocket, Message>, frame: &ServerFrame) -> Result<(), axum::Error> {
+    match frame.to_json() {
+        Some(json) => ws_sender.send(Message::Text(json)).await,
+        None => Ok(()),
+    }
+}
+
+// Tells the client that a frame was rejected by the rate limiter, and when it can retry
+fn push_rate_limited(queue: &ConnectionQueue, client_id: Option<String>, wait: std::time::Duration, locale: Locale) {
+    let retry_after = retry_after_secs(wait);
+    debug!(retry_after, "WebSocket frame rate limited");
+    push_error(queue, client_id, MyError::TooManyRequests(retry_after), locale);
+}
+
+// Replies to a frame that could not be handled
+fn push_error(queue: &ConnectionQueue, client_id: Option<String>, error: MyError, locale: Locale) {
+    let frame = ServerFrame::Error { client_id, error: error.body(locale) };
+    if let Some(json) = frame.to_json() {
+        queue.reply(json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_frames_keep_their_client_id() {
+        for text in [r#"{"type":"typing","client_id":"c1"}"#, r#"{"chat_id":"one","client_id":"c1"}"#] {
+            assert!(ClientFrame::parse(text).is_err());
+            assert_eq!(ClientFrame::client_id(text).as_deref(), Some("c1"));
+        }
+        assert!(ClientFrame::parse("not json").is_err());
+        assert_eq!(ClientFrame::client_id("not json"), None);
+        assert_eq!(ClientFrame::client_id(r#"{"client_id":7}"#), None);
+    }
+}
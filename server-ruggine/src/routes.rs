@@ -1,16 +1,38 @@
 use axum::{
-    routing::{get, post, delete},
+    middleware,
+    routing::{get, post, put, delete},
+    extract::DefaultBodyLimit,
+    http::{header, HeaderName, HeaderValue, Method},
     Router,
     Extension
 };
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
 use serde::{Serialize};
-use sqlx::SqlitePool;
-use crate::route_handlers::user_handler::{create_user_handler, login_handler, logout_handler};
+use crate::route_handlers::user_handler::{
+    change_password_handler, confirm_password_reset_handler, create_user_handler, login_handler, login_history_handler,
+    login_second_factor_handler, logout_handler, request_password_reset_handler,
+};
+use crate::route_handlers::profile_handler::{
+    block_user_handler, blocked_users_handler, delete_avatar_handler, get_avatar_handler, get_me_handler,
+    get_user_profile_handler, search_users_handler, unblock_user_handler, update_me_handler, upload_avatar_handler,
+};
+use crate::route_handlers::two_factor_handler::{confirm_two_factor_handler, disable_two_factor_handler, enroll_two_factor_handler};
 use crate::route_handlers::chat_handler::{user_chats_handler, create_group_handler, create_private_chat_handler, leave_group_handler};
 use crate::route_handlers::request_handler::{request_handler_insert, request_handler_decline, get_user_requests_handler, request_handler_accept};
 use crate::route_handlers::message_handler::{get_chat_messages_handler, send_message_handler};
 use async_session::{MemoryStore};
 use crate::route_handlers::ws_handler::ws_handler;
+use crate::route_handlers::health_handler::{healthz_handler, readyz_handler};
+use crate::state::AppState;
+use crate::utilities::logging::{make_request_span, REQUEST_ID_HEADER};
+use crate::utilities::metrics::track_http;
+use crate::utilities::rate_limit::{rate_limit, Rule};
+use crate::utilities::error::MyError;
+use crate::utilities::i18n::{self, negotiate_locale, Text};
+use crate::utilities::sessions::SessionRevocations;
 
 // Standard API response structure for those APIs that don't return specific data, but just a success/failure message
 #[derive(Debug, Serialize)]
@@ -18,28 +40,143 @@ pub struct ApiResponse {
     pub message: String,
 }
 
+impl ApiResponse {
+    // Response with the text in the language of the request
+    pub fn new(text: Text) -> Self {
+        ApiResponse { message: i18n::localize(&text) }
+    }
+}
+
+// Configure the CORS (Cross-Origin Resource Sharing) middleware using CorsLayer.
+// This middleware defines the rules for allowing cross-origin requests to the server:
+// the web client may use every method of the routes below, sending its session cookie
+pub fn api_cors() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(HeaderValue::from_static("http://localhost:5173"))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE])
+        .allow_credentials(true)
+}
+
 // Function to create the routes for the Axum application
-// Each route is associated with a specific handler function
-pub async fn create_routes(pool: SqlitePool) -> Router {
+// Each route is associated with a specific handler function.
+// The API routes are restricted by `cors`, the health probes are not
+pub async fn create_routes(state: AppState, cors: CorsLayer) -> Router {
     // Create an in-memory session store for managing user sessions
     let store = MemoryStore::new();
-    Router::new()
+    let revocations = SessionRevocations::default();
+
+    // Probes for load balancers and supervisors: no authentication, readable from any origin,
+    // and kept out of the request traces and metrics
+    let probes = Router::new()
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .layer(Extension(store.clone()))
+        .layer(CorsLayer::new().allow_origin(Any).allow_methods([Method::GET]))
+        .with_state(state.clone());
+
+    // Rate limits of the routes open to abuse (brute force, mass registration, spam).
+    // The messages sent over the WebSocket are limited frame by frame in `ws_handler`
+    let limited = |rule| middleware::from_fn_with_state((state.limiter.clone(), rule), rate_limit);
+
+    let api = Router::new()
         .route("/ws", get(ws_handler))
-        .route("/users", post(create_user_handler))
-        .route("/login", post(login_handler))
+        .route("/users", post(create_user_handler).layer(limited(Rule::Register)))
+        .route("/login", post(login_handler).layer(limited(Rule::Login)))
+        .route("/login/2fa", post(login_second_factor_handler).layer(limited(Rule::TwoFactor)))
         .route("/chats", get(user_chats_handler))
-        .route("/chats/:chatId/requests", post(request_handler_insert))
+        .route("/chats/:chatId/requests", post(request_handler_insert).layer(limited(Rule::Invite)))
         .route("/requests/:chatId/delete", delete(request_handler_decline))
         .route("/requests/:chatId/accept", post(request_handler_accept))
-        .route("/groups", post(create_group_handler))
+        .route("/groups", post(create_group_handler).layer(limited(Rule::Invite)))
         .route("/requests", get(get_user_requests_handler))
         .route("/chats", post(create_private_chat_handler))
         .route("/chats/:chatId/messages", get(get_chat_messages_handler))
-        .route("/chats/:chatId/messages", post(send_message_handler))
+        .route("/chats/:chatId/messages", post(send_message_handler).layer(limited(Rule::Message)))
         .route("/chats/:chatId", delete(leave_group_handler))
         .route("/logout", post(logout_handler))
+        .route("/users", get(search_users_handler))
+        .route("/users/:username", get(get_user_profile_handler))
+        .route("/users/:username/avatar", get(get_avatar_handler))
+        .route("/me", get(get_me_handler).patch(update_me_handler))
+        .route(
+            "/me/avatar",
+            put(upload_avatar_handler).delete(delete_avatar_handler)
+                .layer(DefaultBodyLimit::max(state.config.avatar_max_bytes)),
+        )
+        .route("/me/logins", get(login_history_handler))
+        .route("/me/blocks", get(blocked_users_handler))
+        .route("/me/blocks/:username", put(block_user_handler).delete(unblock_user_handler))
+        .route("/me/password", post(change_password_handler).layer(limited(Rule::PasswordChange)))
+        .route("/me/2fa", post(enroll_two_factor_handler).delete(disable_two_factor_handler).layer(limited(Rule::TwoFactor)))
+        .route("/me/2fa/confirm", post(confirm_two_factor_handler).layer(limited(Rule::TwoFactor)))
+        .route("/password-reset", post(request_password_reset_handler).layer(limited(Rule::PasswordReset)))
+        .route("/password-reset/confirm", post(confirm_password_reset_handler).layer(limited(Rule::PasswordReset)))
+        .fallback(route_not_found_handler)
+        // Choose the language of the responses, which needs the session store below
+        .layer(middleware::from_fn_with_state(state.clone(), negotiate_locale))
         .layer(Extension(store))
-        // Add the database connection pool as an extension to the router:
-        // all handlers can use the same pool without having to pass it manually to each one
-        .with_state(pool)
+        .layer(Extension(revocations))
+        // Count the requests and measure their latency, per route
+        .layer(middleware::from_fn(track_http))
+        // Trace every request in its own span, identified by the `x-request-id` header:
+        // taken from the client or generated, and echoed in the response.
+        // The last layer added runs first, so the id is set before the span is created
+        .layer(PropagateRequestIdLayer::new(HeaderName::from_static(REQUEST_ID_HEADER)))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::new(HeaderName::from_static(REQUEST_ID_HEADER), MakeRequestUuid))
+        // Add the application state (repositories and event bus) to the router:
+        // all handlers can use the same repositories without having to pass them manually to each one
+        .with_state(state)
+        .layer(cors);
+
+    api.merge(probes)
+}
+
+// Unknown routes get the usual error body
+async fn route_not_found_handler() -> MyError {
+    MyError::RouteNotFound
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_mapper::tests::TestDb;
+
+    // The browser asks before sending anything but GET and POST: each such route must be allowed
+    #[tokio::test]
+    async fn web_client_preflights_are_allowed() {
+        let db = TestDb::sqlite().await;
+        let url = db.serve().await;
+
+        let client = reqwest::Client::new();
+        for (method, path) in [
+            ("PATCH", "/me"),
+            ("PUT", "/me/avatar"),
+            ("DELETE", "/me/avatar"),
+            ("PUT", "/me/blocks/someone"),
+            ("DELETE", "/me/blocks/someone"),
+            ("DELETE", "/chats/1"),
+            ("DELETE", "/requests/1/delete"),
+        ] {
+            let response = client
+                .request(reqwest::Method::OPTIONS, format!("{}{}", url, path))
+                .header("origin", "http://localhost:5173")
+                .header("access-control-request-method", method)
+                .header("access-control-request-headers", "content-type")
+                .send()
+                .await
+                .unwrap();
+            let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+            assert!(response.status().is_success(), "{} {}: {}", method, path, response.status());
+            assert_eq!(header("access-control-allow-origin"), "http://localhost:5173", "{} {}", method, path);
+            assert!(header("access-control-allow-methods").split(',').any(|allowed| allowed.trim() == method), "{} {}", method, path);
+            assert_eq!(header("access-control-allow-credentials"), "true", "{} {}", method, path);
+        }
+
+        db.close().await;
+    }
 }
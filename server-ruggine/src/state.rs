@@ -0,0 +1,49 @@
+use axum::extract::FromRef;
+use std::sync::Arc;
+use crate::db_mapper::Repositories;
+use crate::events::Broadcaster;
+use crate::utilities::config::Config;
+use crate::utilities::notifier::Notifier;
+use crate::utilities::rate_limit::RateLimiter;
+
+pub type EventBus = Arc<dyn Broadcaster>;
+
+// Shared application state, available to every handler through `State`
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Repositories,
+    pub events: EventBus,
+    pub config: Arc<Config>,
+    pub limiter: Arc<RateLimiter>,
+    pub notifier: Arc<dyn Notifier>,
+}
+
+impl FromRef<AppState> for Repositories {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for EventBus {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RateLimiter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.limiter.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Notifier> {
+    fn from_ref(state: &AppState) -> Self {
+        state.notifier.clone()
+    }
+}
@@ -0,0 +1,42 @@
+use std::io::Cursor;
+use axum::body::Bytes;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, Limits};
+use tokio::task::spawn_blocking;
+use tracing::debug;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+
+// Largest side accepted for an uploaded image: bigger ones (or ones claiming to be) are refused before decoding
+const MAX_SIDE: u32 = 8192;
+
+// Turns an uploaded image (PNG, JPEG or WebP) into the stored avatar: cropped to a centred square,
+// resized to `avatar_size` pixels and encoded as PNG. Decoding is CPU bound, so it runs on the blocking thread pool
+pub async fn process_avatar(config: &Config, upload: Bytes) -> Result<Vec<u8>, MyError> {
+    let size = config.avatar_size;
+    spawn_blocking(move || {
+        let mut reader = ImageReader::new(Cursor::new(upload)).with_guessed_format().map_err(|_| MyError::InvalidImage)?;
+        if !matches!(reader.format(), Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP)) {
+            return Err(MyError::InvalidImage);
+        }
+
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_SIDE);
+        limits.max_image_height = Some(MAX_SIDE);
+        reader.limits(limits);
+
+        let image = reader.decode().map_err(|e| {
+            debug!(error = %e, "Unable to decode the avatar");
+            MyError::InvalidImage
+        })?;
+
+        let mut png = Vec::new();
+        image
+            .resize_to_fill(size, size, FilterType::Lanczos3)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map_err(|_| MyError::Unknown)?;
+        Ok(png)
+    })
+        .await
+        .map_err(|_| MyError::Unknown)?
+}
@@ -0,0 +1,286 @@
+use std::env;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+// What to do when a connection's outgoing queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Discard the oldest queued event to make room for the new one, telling the client to resync
+    DropOldest,
+    // Close the connection, telling the client to resync
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            other => Err(format!("unknown overflow policy: {}", other)),
+        }
+    }
+}
+
+// Output format of the logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // Human-readable, multi-line output for development
+    Pretty,
+    // One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format: {}", other)),
+        }
+    }
+}
+
+// Token bucket: up to `burst` requests at once, refilled at `burst` tokens per `period`.
+// Written as `burst/seconds` (e.g. `10/60`), or `off` to disable the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, period_secs: u64) -> Self {
+        RateLimit { burst, period: Duration::from_secs(period_secs) }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.burst == 0
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "off" {
+            return Ok(RateLimit::new(0, 1));
+        }
+        let (burst, secs) = s.split_once('/').ok_or_else(|| format!("expected burst/seconds: {}", s))?;
+        let burst = burst.trim().parse().map_err(|_| format!("invalid burst: {}", burst))?;
+        let secs: u64 = secs.trim().parse().map_err(|_| format!("invalid period: {}", secs))?;
+        Ok(RateLimit::new(burst, secs.max(1)))
+    }
+}
+
+// Server configuration, read from `RUGGINE_*` environment variables with sensible defaults
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Address the API listens on (HTTPS when TLS is configured)
+    pub bind_addr: SocketAddr,
+    // PEM certificate chain and private key: when both are set the server speaks TLS only
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    // Interval between two checks of the certificate files, reloaded when they change
+    pub tls_reload_interval: Duration,
+    // When set (and TLS is on), plain HTTP requests on this address are redirected to HTTPS
+    pub http_redirect_addr: Option<SocketAddr>,
+    // Address of the plain HTTP listener serving `/metrics`, apart from the API
+    pub metrics_addr: SocketAddr,
+    // Database to connect to: `sqlite://...` or `postgres://...`
+    pub database_url: String,
+    // Maximum number of pooled connections (for SQLite, of the read pool: writes use a single connection)
+    pub db_max_connections: u32,
+    // How long to wait for a locked database (SQLite) or a free pooled connection (PostgreSQL)
+    pub db_busy_timeout: Duration,
+    // Maximum number of messages waiting to be sent on a single WebSocket connection
+    pub ws_queue_capacity: usize,
+    pub ws_overflow_policy: OverflowPolicy,
+    // Interval between the pings sent to each WebSocket client
+    pub ws_ping_interval: Duration,
+    // A connection on which nothing (not even a pong) is received for this long is considered dead
+    pub ws_idle_timeout: Duration,
+    // Maximum number of missed messages replayed on reconnection: beyond it the client must reload
+    pub ws_resync_limit: i64,
+    pub log_format: LogFormat,
+    // Log filter, in the `tracing` env-filter syntax (e.g. `info` or `info,server_ruggine=debug`)
+    pub log_filter: String,
+    // Interval between two samples of the process and fan-out metrics
+    pub metrics_interval: Duration,
+    // When set, the samples are also appended to this file
+    pub monitor_logfile: Option<String>,
+    // Rate limits, per client IP and per user
+    pub rate_login: RateLimit,
+    pub rate_two_factor: RateLimit,
+    pub rate_password_change: RateLimit,
+    pub rate_password_reset: RateLimit,
+    pub rate_register: RateLimit,
+    pub rate_invite: RateLimit,
+    // Shared by the messages sent over REST and over the WebSocket
+    pub rate_message: RateLimit,
+    pub rate_ws_sync: RateLimit,
+    // After a failed sign-in the next attempt on the username waits `login_delay`, doubled at each further failure.
+    // After `login_lockout_threshold` failures the username is locked for `login_lockout_duration`
+    pub login_delay: Duration,
+    pub login_lockout_threshold: u32,
+    pub login_lockout_duration: Duration,
+    // Strength rules for new passwords: length bounds and number of character classes
+    // (lowercase, uppercase, digits, symbols) to mix
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    pub password_min_classes: usize,
+    // Argon2id cost parameters: memory in KiB, iterations and lanes. Hashes made with other parameters
+    // (or with bcrypt) are upgraded at the next successful sign-in
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    // Validity of the password reset tokens
+    pub password_reset_ttl: Duration,
+    // File the default notifier appends the notifications to
+    pub outbox_path: String,
+    // Issuer shown by authenticator apps next to the TOTP codes
+    pub totp_issuer: String,
+    // Time allowed to enter the second factor after the password
+    pub two_factor_challenge_ttl: Duration,
+    // Largest avatar upload accepted, in bytes, and side of the square the avatars are resized to, in pixels
+    pub avatar_max_bytes: usize,
+    pub avatar_size: u32,
+    // Take the client IP from `X-Forwarded-For` (only behind a trusted reverse proxy)
+    pub trust_forwarded_for: bool,
+    // On shutdown, time given to the open connections to be closed before the server exits
+    pub shutdown_drain_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_addr: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            tls_cert_path: None,
+            tls_key_path: None,
+            tls_reload_interval: Duration::from_secs(60),
+            http_redirect_addr: None,
+            metrics_addr: SocketAddr::from(([127, 0, 0, 1], 9464)),
+            database_url: "sqlite://Ruggine.db".to_string(),
+            db_max_connections: 8,
+            db_busy_timeout: Duration::from_secs(5),
+            ws_queue_capacity: 256,
+            ws_overflow_policy: OverflowPolicy::DropOldest,
+            ws_ping_interval: Duration::from_secs(30),
+            ws_idle_timeout: Duration::from_secs(90),
+            ws_resync_limit: 500,
+            log_format: LogFormat::Pretty,
+            log_filter: "info".to_string(),
+            metrics_interval: Duration::from_secs(15),
+            monitor_logfile: None,
+            rate_login: RateLimit::new(10, 60),
+            rate_two_factor: RateLimit::new(5, 60),
+            rate_password_change: RateLimit::new(5, 300),
+            rate_password_reset: RateLimit::new(5, 3600),
+            rate_register: RateLimit::new(5, 3600),
+            rate_invite: RateLimit::new(30, 60),
+            rate_message: RateLimit::new(20, 10),
+            rate_ws_sync: RateLimit::new(10, 60),
+            login_delay: Duration::from_secs(1),
+            login_lockout_threshold: 5,
+            login_lockout_duration: Duration::from_secs(15 * 60),
+            password_min_length: 8,
+            password_max_length: 128,
+            password_min_classes: 3,
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            password_reset_ttl: Duration::from_secs(30 * 60),
+            outbox_path: "outbox.jsonl".to_string(),
+            totp_issuer: "Ruggine".to_string(),
+            two_factor_challenge_ttl: Duration::from_secs(5 * 60),
+            avatar_max_bytes: 5 * 1024 * 1024,
+            avatar_size: 256,
+            trust_forwarded_for: false,
+            shutdown_drain_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let default = Config::default();
+        Config {
+            bind_addr: env_or("RUGGINE_BIND_ADDR", default.bind_addr),
+            tls_cert_path: env::var("RUGGINE_TLS_CERT").ok().or(default.tls_cert_path),
+            tls_key_path: env::var("RUGGINE_TLS_KEY").ok().or(default.tls_key_path),
+            tls_reload_interval: env_secs_or("RUGGINE_TLS_RELOAD_SECS", default.tls_reload_interval),
+            http_redirect_addr: env_opt("RUGGINE_HTTP_REDIRECT_ADDR").or(default.http_redirect_addr),
+            metrics_addr: env_or("RUGGINE_METRICS_ADDR", default.metrics_addr),
+            database_url: env::var("RUGGINE_DATABASE_URL").unwrap_or(default.database_url),
+            db_max_connections: env_or("RUGGINE_DB_MAX_CONNECTIONS", default.db_max_connections).max(1),
+            db_busy_timeout: env_secs_or("RUGGINE_DB_BUSY_TIMEOUT_SECS", default.db_busy_timeout),
+            ws_queue_capacity: env_or("RUGGINE_WS_QUEUE_CAPACITY", default.ws_queue_capacity).max(1),
+            ws_overflow_policy: env_or("RUGGINE_WS_OVERFLOW_POLICY", default.ws_overflow_policy),
+            ws_ping_interval: env_secs_or("RUGGINE_WS_PING_INTERVAL_SECS", default.ws_ping_interval),
+            ws_idle_timeout: env_secs_or("RUGGINE_WS_IDLE_TIMEOUT_SECS", default.ws_idle_timeout),
+            ws_resync_limit: env_or("RUGGINE_WS_RESYNC_LIMIT", default.ws_resync_limit).max(0),
+            log_format: env_or("RUGGINE_LOG_FORMAT", default.log_format),
+            log_filter: env::var("RUGGINE_LOG").unwrap_or(default.log_filter),
+            metrics_interval: env_secs_or("RUGGINE_METRICS_INTERVAL_SECS", default.metrics_interval),
+            monitor_logfile: env::var("RUGGINE_MONITOR_LOGFILE").ok().or(default.monitor_logfile),
+            rate_login: env_or("RUGGINE_RATE_LOGIN", default.rate_login),
+            rate_two_factor: env_or("RUGGINE_RATE_TWO_FACTOR", default.rate_two_factor),
+            rate_password_change: env_or("RUGGINE_RATE_PASSWORD_CHANGE", default.rate_password_change),
+            rate_password_reset: env_or("RUGGINE_RATE_PASSWORD_RESET", default.rate_password_reset),
+            rate_register: env_or("RUGGINE_RATE_REGISTER", default.rate_register),
+            rate_invite: env_or("RUGGINE_RATE_INVITE", default.rate_invite),
+            rate_message: env_or("RUGGINE_RATE_MESSAGE", default.rate_message),
+            rate_ws_sync: env_or("RUGGINE_RATE_WS_SYNC", default.rate_ws_sync),
+            login_delay: env_secs_or("RUGGINE_LOGIN_DELAY_SECS", default.login_delay),
+            login_lockout_threshold: env_or("RUGGINE_LOGIN_LOCKOUT_THRESHOLD", default.login_lockout_threshold).max(1),
+            login_lockout_duration: env_secs_or("RUGGINE_LOGIN_LOCKOUT_SECS", default.login_lockout_duration),
+            password_min_length: env_or("RUGGINE_PASSWORD_MIN_LENGTH", default.password_min_length).max(1),
+            password_max_length: env_or("RUGGINE_PASSWORD_MAX_LENGTH", default.password_max_length),
+            password_min_classes: env_or("RUGGINE_PASSWORD_MIN_CLASSES", default.password_min_classes).min(4),
+            argon2_memory_kib: env_or("RUGGINE_ARGON2_MEMORY_KIB", default.argon2_memory_kib),
+            argon2_iterations: env_or("RUGGINE_ARGON2_ITERATIONS", default.argon2_iterations),
+            argon2_parallelism: env_or("RUGGINE_ARGON2_PARALLELISM", default.argon2_parallelism),
+            password_reset_ttl: env_secs_or("RUGGINE_PASSWORD_RESET_TTL_SECS", default.password_reset_ttl),
+            outbox_path: env::var("RUGGINE_OUTBOX").unwrap_or(default.outbox_path),
+            totp_issuer: env::var("RUGGINE_TOTP_ISSUER").unwrap_or(default.totp_issuer),
+            two_factor_challenge_ttl: env_secs_or("RUGGINE_TWO_FACTOR_CHALLENGE_SECS", default.two_factor_challenge_ttl),
+            avatar_max_bytes: env_or("RUGGINE_AVATAR_MAX_BYTES", default.avatar_max_bytes).max(1),
+            avatar_size: env_or("RUGGINE_AVATAR_SIZE", default.avatar_size).clamp(16, 1024),
+            trust_forwarded_for: env_or("RUGGINE_TRUST_FORWARDED_FOR", default.trust_forwarded_for),
+            shutdown_drain_timeout: env_secs_or("RUGGINE_SHUTDOWN_DRAIN_SECS", default.shutdown_drain_timeout),
+        }
+    }
+}
+
+impl Config {
+    // Whether the server terminates TLS itself
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert_path.is_some() && self.tls_key_path.is_some()
+    }
+}
+
+// Reads and parses an environment variable, falling back to the default when missing or invalid
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid value for {}: {}, using default", key, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+// Reads and parses an optional environment variable, ignoring it when invalid
+fn env_opt<T: FromStr>(key: &str) -> Option<T> {
+    let value = env::var(key).ok()?;
+    value.parse().map_err(|_| eprintln!("Invalid value for {}: {}, ignoring it", key, value)).ok()
+}
+
+// Reads a duration expressed in seconds from an environment variable
+fn env_secs_or(key: &str, default: Duration) -> Duration {
+    Duration::from_secs(env_or(key, default.as_secs()).max(1))
+}
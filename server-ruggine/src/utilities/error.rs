@@ -1,8 +1,17 @@
 use thiserror::Error;
+use axum::extract::rejection::{BytesRejection, JsonRejection, PathRejection, QueryRejection};
 use axum::response::{IntoResponse, Response};
-use axum::http::StatusCode;
-use serde_json::json;
+use axum::http::{header, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::error;
+use validator::ValidationErrors;
+use crate::utilities::i18n::{self, Locale, Text};
+use crate::utilities::password_policy::PasswordRule;
+use crate::utilities::validation::field_errors;
 
+// The `Display` text of each error is meant for the logs: clients get `ErrorBody`, which hides internal details
+// and is translated in their language
 #[derive(Error, Debug)]
 pub enum MyError {
     #[error("Username already exists")]
@@ -16,40 +25,229 @@ pub enum MyError {
 
     #[error("User not found")]
     UserNotFound,
-    
+
+    #[error("Users not found: {}", .0.join(", "))]
+    UsersNotFound(Vec<String>),
+
     #[error("Chat not found")]
     ChatNotFound,
-    
+
     #[error("User doesn't belong to this group")]
     UserDoesNotBelongToGroup,
-    
+
     #[error("User already in this group")]
     UserAlreadyInGroup,
 
+    #[error("Request not found")]
+    RequestNotFound,
+
+    #[error("Message already stored")]
+    DuplicateMessage,
+
+    #[error("Too many requests, retry in {0} seconds")]
+    TooManyRequests(u64),
+
+    #[error("Too many failed sign-in attempts, account locked for {0} seconds")]
+    AccountLocked(u64),
+
+    #[error("Password {0}")]
+    WeakPassword(PasswordRule),
+
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+
+    #[error("Authentication required")]
+    Unauthenticated,
+
+    #[error("Session revoked, sign in again")]
+    SessionRevoked,
+
+    #[error("Invalid or expired reset token")]
+    InvalidResetToken,
+
+    #[error("Notification failed")]
+    NotificationFailed,
+
+    #[error("Invalid two-factor code")]
+    InvalidTwoFactorCode,
+
+    #[error("No sign-in awaiting a second factor")]
+    NoPendingSignIn,
+
+    #[error("Two-factor authentication already enabled")]
+    TwoFactorAlreadyEnabled,
+
+    #[error("Two-factor authentication not enabled")]
+    TwoFactorNotEnabled,
+
+    #[error("No two-factor enrolment to confirm")]
+    NoTwoFactorEnrollment,
+
+    #[error("Invalid request: {1}")]
+    InvalidRequest(StatusCode, String),
+
+    #[error("Validation failed")]
+    Validation(#[from] ValidationErrors),
+
+    #[error("Unsupported or corrupt image")]
+    InvalidImage,
+
+    #[error("Avatar not found")]
+    AvatarNotFound,
+
+    #[error("You cannot block yourself")]
+    CannotBlockYourself,
+
+    #[error("Route not found")]
+    RouteNotFound,
+
     #[error("Unknown error")]
     Unknown,
 }
 
+// Body of every error response (under `error`) and of the WebSocket `error` events:
+// a stable code for programs, a message for humans and, for some errors, structured details
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+impl MyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MyError::UsernameExists => StatusCode::CONFLICT,
+            MyError::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::PasswordHashingFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::UserNotFound => StatusCode::NOT_FOUND,
+            MyError::UsersNotFound(_) => StatusCode::NOT_FOUND,
+            MyError::ChatNotFound => StatusCode::NOT_FOUND,
+            MyError::UserDoesNotBelongToGroup => StatusCode::FORBIDDEN,
+            MyError::UserAlreadyInGroup => StatusCode::CONFLICT,
+            MyError::RequestNotFound => StatusCode::NOT_FOUND,
+            MyError::DuplicateMessage => StatusCode::CONFLICT,
+            MyError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            MyError::AccountLocked(_) => StatusCode::LOCKED,
+            MyError::WeakPassword(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            MyError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            MyError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            MyError::SessionRevoked => StatusCode::UNAUTHORIZED,
+            MyError::InvalidResetToken => StatusCode::BAD_REQUEST,
+            MyError::NotificationFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::InvalidTwoFactorCode => StatusCode::UNAUTHORIZED,
+            MyError::NoPendingSignIn => StatusCode::UNAUTHORIZED,
+            MyError::TwoFactorAlreadyEnabled => StatusCode::CONFLICT,
+            MyError::TwoFactorNotEnabled => StatusCode::CONFLICT,
+            MyError::NoTwoFactorEnrollment => StatusCode::CONFLICT,
+            MyError::InvalidRequest(status, _) => *status,
+            MyError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            MyError::InvalidImage => StatusCode::UNPROCESSABLE_ENTITY,
+            MyError::AvatarNotFound => StatusCode::NOT_FOUND,
+            MyError::CannotBlockYourself => StatusCode::UNPROCESSABLE_ENTITY,
+            MyError::RouteNotFound => StatusCode::NOT_FOUND,
+            MyError::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // Stable identifier of the error, for clients to act upon
+    pub fn code(&self) -> &'static str {
+        match self {
+            MyError::UsernameExists => "username_exists",
+            MyError::SqlxError(_) => "database_error",
+            MyError::PasswordHashingFailed => "password_hashing_failed",
+            MyError::UserNotFound => "user_not_found",
+            MyError::UsersNotFound(_) => "users_not_found",
+            MyError::ChatNotFound => "chat_not_found",
+            MyError::UserDoesNotBelongToGroup => "not_a_member",
+            MyError::UserAlreadyInGroup => "already_a_member",
+            MyError::RequestNotFound => "request_not_found",
+            MyError::DuplicateMessage => "duplicate_message",
+            MyError::TooManyRequests(_) => "rate_limited",
+            MyError::AccountLocked(_) => "account_locked",
+            MyError::WeakPassword(_) => "weak_password",
+            MyError::InvalidCredentials => "invalid_credentials",
+            MyError::Unauthenticated => "unauthenticated",
+            MyError::SessionRevoked => "session_revoked",
+            MyError::InvalidResetToken => "invalid_reset_token",
+            MyError::NotificationFailed => "notification_failed",
+            MyError::InvalidTwoFactorCode => "invalid_two_factor_code",
+            MyError::NoPendingSignIn => "no_pending_sign_in",
+            MyError::TwoFactorAlreadyEnabled => "two_factor_already_enabled",
+            MyError::TwoFactorNotEnabled => "two_factor_not_enabled",
+            MyError::NoTwoFactorEnrollment => "no_two_factor_enrollment",
+            MyError::InvalidRequest(..) => "invalid_request",
+            MyError::Validation(_) => "validation_failed",
+            MyError::InvalidImage => "invalid_image",
+            MyError::AvatarNotFound => "avatar_not_found",
+            MyError::CannotBlockYourself => "cannot_block_yourself",
+            MyError::RouteNotFound => "route_not_found",
+            MyError::Unknown => "internal_error",
+        }
+    }
+
+    // What the client is told, in its language
+    pub fn body(&self, locale: Locale) -> ErrorBody {
+        let message = i18n::translate(locale, &Text::Error(self));
+        let details = match self {
+            MyError::TooManyRequests(retry_after) | MyError::AccountLocked(retry_after) => Some(json!({ "retry_after": retry_after })),
+            MyError::Validation(errors) => Some(json!({ "fields": field_errors(errors, locale) })),
+            // Described by the framework, in English
+            MyError::InvalidRequest(_, reason) => Some(json!({ "reason": reason })),
+            MyError::UsersNotFound(usernames) => Some(json!({ "usernames": usernames })),
+            _ => None,
+        };
+        ErrorBody { code: self.code(), message, details }
+    }
+}
+
 impl IntoResponse for MyError {
     /// Implements the `IntoResponse` trait for the `MyError` enum,
     /// allowing conversion of a `MyError` instance into an HTTP response.
+    /// Server errors are logged in full, since the client only gets their code.
+    /// The message is in the language of the request being handled
     fn into_response(self) -> Response {
-        let (status, error_message) = match &self {
-            MyError::UsernameExists => (StatusCode::CONFLICT, self.to_string()),
-            MyError::PasswordHashingFailed => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
-            MyError::SqlxError(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
-            MyError::UserNotFound => (StatusCode::NOT_FOUND, self.to_string()),
-            MyError::ChatNotFound => (StatusCode::NOT_FOUND, self.to_string()),
-            MyError::UserDoesNotBelongToGroup => (StatusCode::FORBIDDEN, self.to_string()),
-            MyError::UserAlreadyInGroup => (StatusCode::CONFLICT, self.to_string()),
-            MyError::Unknown => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
-        };
+        let status = self.status();
+        if status.is_server_error() {
+            error!(error = %self, code = self.code(), "Request failed");
+        }
+
+        let mut response = (status, axum::Json(ErrorResponse { error: self.body(i18n::current()) })).into_response();
+        if let MyError::TooManyRequests(retry_after) | MyError::AccountLocked(retry_after) = self {
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
+
+// Bodies that are not JSON, or not of the expected shape, keep the status `Json` gives them (400, 415 or 422)
+impl From<JsonRejection> for MyError {
+    fn from(rejection: JsonRejection) -> Self {
+        MyError::InvalidRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+// Bodies over the size limit get `413`
+impl From<BytesRejection> for MyError {
+    fn from(rejection: BytesRejection) -> Self {
+        MyError::InvalidRequest(rejection.status(), rejection.body_text())
+    }
+}
 
-        // Create the JSON response body with the error message.
-        let body = json!({
-            "error": error_message,
-        });
+impl From<PathRejection> for MyError {
+    fn from(rejection: PathRejection) -> Self {
+        MyError::InvalidRequest(rejection.status(), rejection.body_text())
+    }
+}
 
-        (status, axum::Json(body)).into_response()
+impl From<QueryRejection> for MyError {
+    fn from(rejection: QueryRejection) -> Self {
+        MyError::InvalidRequest(rejection.status(), rejection.body_text())
     }
 }
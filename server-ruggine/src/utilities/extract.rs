@@ -0,0 +1,11 @@
+use axum::extract::FromRequestParts;
+use crate::utilities::error::MyError;
+
+// `Path` and `Query` answering malformed parameters with the usual error body instead of plain text
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(MyError))]
+pub struct Path<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(MyError))]
+pub struct Query<T>(pub T);
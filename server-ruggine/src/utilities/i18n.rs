@@ -0,0 +1,266 @@
+use axum::{
+    extract::{Request, State, Extension},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use async_session::MemoryStore;
+use serde::{Deserialize, Serialize};
+use crate::db_mapper::Repositories;
+use crate::db_mapper::user;
+use crate::route_handlers::user_handler::session_cookie;
+use crate::utilities::error::MyError;
+use crate::utilities::password_policy::PasswordRule;
+use crate::utilities::sessions::{signed_in_user, SessionRevocations};
+use crate::utilities::validation::FieldRule;
+
+// Languages the server speaks. English is used when nothing better is known
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    It,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::It];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::It => "it",
+        }
+    }
+
+    // Matches a language tag ("it", "it-IT", "en_us") on its primary subtag
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let primary = tag.split(['-', '_']).next()?.trim();
+        Locale::ALL.into_iter().find(|locale| primary.eq_ignore_ascii_case(locale.code()))
+    }
+
+    // Picks the supported language the client prefers from an `Accept-Language` header.
+    // Languages are ranked by their `q` weight, then by their order in the header
+    pub fn negotiate(accept_language: &str) -> Option<Locale> {
+        let mut best: Option<(Locale, f32)> = None;
+        for range in accept_language.split(',') {
+            let mut parts = range.split(';');
+            let Some(locale) = parts.next().and_then(Locale::from_tag) else {
+                continue;
+            };
+            let weight = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if weight > 0.0 && best.is_none_or(|(_, best_weight)| weight > best_weight) {
+                best = Some((locale, weight));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+}
+
+tokio::task_local! {
+    // Locale of the request being handled, so that responses and errors can be rendered without threading it around
+    static LOCALE: Locale;
+}
+
+// Locale of the current request, English outside of one
+pub fn current() -> Locale {
+    LOCALE.try_with(|locale| *locale).unwrap_or_default()
+}
+
+// Language asked for by the `Accept-Language` header, if the server speaks it
+pub fn requested_locale(headers: &HeaderMap) -> Option<Locale> {
+    headers.get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Locale::negotiate)
+}
+
+// Middleware choosing the locale of each request: the preference stored by a signed-in user,
+// else the `Accept-Language` header, else English.
+// The locale is available through `current` while the request is handled, and is announced in `Content-Language`
+pub async fn negotiate_locale(
+    State(db): State<Repositories>,
+    Extension(store): Extension<MemoryStore>,
+    Extension(revocations): Extension<SessionRevocations>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut locale = None;
+    if let Some(username) = signed_in_user(&store, &revocations, session_cookie(request.headers())).await {
+        locale = user::get_locale(&db, &username).await.unwrap_or_default();
+    }
+    let locale = locale.or_else(|| requested_locale(request.headers())).unwrap_or_default();
+
+    let mut response = LOCALE.scope(locale, next.run(request)).await;
+    response.headers_mut().insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(locale.code()));
+    response
+}
+
+// Everything the server says to people, with its parameters
+pub enum Text<'a> {
+    // System messages of the chats
+    GroupCreated { user: &'a str },
+    PrivateChatStarted { user: &'a str, with: &'a str },
+    MemberJoined { user: &'a str },
+    MemberLeft { user: &'a str },
+
+    // Responses of the API
+    UserCreated,
+    LoginSuccessful,
+    TwoFactorRequired,
+    LoggedOut,
+    PasswordChanged,
+    ResetTokenSent,
+    PasswordReset,
+    TwoFactorDisabled,
+    InvitationDeclined,
+    InvitationAccepted,
+    GroupLeft,
+    AllUsersInvited,
+    UsersInvited { count: usize, not_found: &'a [String] },
+
+    Error(&'a MyError),
+    // A check failed by a field of a request
+    InvalidField(FieldRule),
+}
+
+pub fn translate(locale: Locale, text: &Text) -> String {
+    match locale {
+        Locale::En => english(text),
+        Locale::It => italian(text),
+    }
+}
+
+// Renders the text in the language of the current request
+pub fn localize(text: &Text) -> String {
+    translate(current(), text)
+}
+
+// English catalog. Error messages are the `Display` text of the errors, except for the database ones,
+// which would expose queries and schema
+fn english(text: &Text) -> String {
+    match text {
+        Text::GroupCreated { user } => format!("{} created the group", user),
+        Text::PrivateChatStarted { user, with } => format!("{} started a private chat with {}", user, with),
+        Text::MemberJoined { user } => format!("{} joined the group", user),
+        Text::MemberLeft { user } => format!("{} has left the group", user),
+
+        Text::UserCreated => "User created successfully.".to_string(),
+        Text::LoginSuccessful => "Login successful".to_string(),
+        Text::TwoFactorRequired => "Two-factor code required".to_string(),
+        Text::LoggedOut => "Logout successful.".to_string(),
+        Text::PasswordChanged => "Password changed.".to_string(),
+        Text::ResetTokenSent => "If the account exists, a reset token has been sent.".to_string(),
+        Text::PasswordReset => "Password reset.".to_string(),
+        Text::TwoFactorDisabled => "Two-factor authentication disabled.".to_string(),
+        Text::InvitationDeclined => "Invitation declined.".to_string(),
+        Text::InvitationAccepted => "Invitation accepted, you joined the group.".to_string(),
+        Text::GroupLeft => "Successfully left the group".to_string(),
+        Text::AllUsersInvited => "All users invited to the group successfully.".to_string(),
+        Text::UsersInvited { count, not_found } => format!(
+            "Invitations sent successfully to {} user(s). However, these users were not found: {}",
+            count,
+            not_found.join(", ")
+        ),
+
+        Text::Error(MyError::SqlxError(_)) => "Database error".to_string(),
+        Text::Error(error) => error.to_string(),
+        Text::InvalidField(rule) => rule.to_string(),
+    }
+}
+
+// Italian catalog
+fn italian(text: &Text) -> String {
+    match text {
+        Text::GroupCreated { user } => format!("{} ha creato il gruppo", user),
+        Text::PrivateChatStarted { user, with } => format!("{} ha avviato una chat privata con {}", user, with),
+        Text::MemberJoined { user } => format!("{} è entrato nel gruppo", user),
+        Text::MemberLeft { user } => format!("{} ha lasciato il gruppo", user),
+
+        Text::UserCreated => "Utente creato.".to_string(),
+        Text::LoginSuccessful => "Accesso effettuato".to_string(),
+        Text::TwoFactorRequired => "Codice di verifica in due passaggi richiesto".to_string(),
+        Text::LoggedOut => "Disconnessione effettuata.".to_string(),
+        Text::PasswordChanged => "Password modificata.".to_string(),
+        Text::ResetTokenSent => "Se l'account esiste, è stato inviato un codice per reimpostare la password.".to_string(),
+        Text::PasswordReset => "Password reimpostata.".to_string(),
+        Text::TwoFactorDisabled => "Verifica in due passaggi disattivata.".to_string(),
+        Text::InvitationDeclined => "Invito rifiutato.".to_string(),
+        Text::InvitationAccepted => "Invito accettato, sei entrato nel gruppo.".to_string(),
+        Text::GroupLeft => "Hai lasciato il gruppo".to_string(),
+        Text::AllUsersInvited => "Tutti gli utenti sono stati invitati nel gruppo.".to_string(),
+        Text::UsersInvited { count, not_found } => format!(
+            "Inviti inviati a {} utenti. Questi utenti però non sono stati trovati: {}",
+            count,
+            not_found.join(", ")
+        ),
+
+        Text::Error(error) => italian_error(error),
+        Text::InvalidField(rule) => italian_field_rule(*rule),
+    }
+}
+
+fn italian_error(error: &MyError) -> String {
+    match error {
+        MyError::UsernameExists => "Nome utente già in uso".to_string(),
+        MyError::SqlxError(_) => "Errore del database".to_string(),
+        MyError::PasswordHashingFailed => "Impossibile calcolare l'hash della password".to_string(),
+        MyError::UserNotFound => "Utente non trovato".to_string(),
+        MyError::UsersNotFound(usernames) => format!("Utenti non trovati: {}", usernames.join(", ")),
+        MyError::ChatNotFound => "Chat non trovata".to_string(),
+        MyError::UserDoesNotBelongToGroup => "L'utente non fa parte di questo gruppo".to_string(),
+        MyError::UserAlreadyInGroup => "L'utente fa già parte di questo gruppo".to_string(),
+        MyError::RequestNotFound => "Invito non trovato".to_string(),
+        MyError::DuplicateMessage => "Messaggio già salvato".to_string(),
+        MyError::TooManyRequests(retry_after) => format!("Troppe richieste, riprova tra {} secondi", retry_after),
+        MyError::AccountLocked(retry_after) => format!("Troppi accessi falliti, account bloccato per {} secondi", retry_after),
+        MyError::WeakPassword(rule) => match rule {
+            PasswordRule::MinLength(length) => format!("La password deve avere almeno {} caratteri", length),
+            PasswordRule::MaxLength(length) => format!("La password deve avere al massimo {} caratteri", length),
+            PasswordRule::MinClasses(classes) => format!(
+                "La password deve combinare almeno {} tra lettere minuscole, lettere maiuscole, cifre e simboli",
+                classes
+            ),
+            PasswordRule::NotUsername => "La password deve essere diversa dal nome utente".to_string(),
+        },
+        MyError::InvalidCredentials => "Credenziali non valide".to_string(),
+        MyError::Unauthenticated => "Autenticazione richiesta".to_string(),
+        MyError::SessionRevoked => "Sessione revocata, accedi di nuovo".to_string(),
+        MyError::InvalidResetToken => "Codice di reimpostazione non valido o scaduto".to_string(),
+        MyError::NotificationFailed => "Invio della notifica non riuscito".to_string(),
+        MyError::InvalidTwoFactorCode => "Codice di verifica non valido".to_string(),
+        MyError::NoPendingSignIn => "Nessun accesso in attesa del secondo fattore".to_string(),
+        MyError::TwoFactorAlreadyEnabled => "Verifica in due passaggi già attiva".to_string(),
+        MyError::TwoFactorNotEnabled => "Verifica in due passaggi non attiva".to_string(),
+        MyError::NoTwoFactorEnrollment => "Nessuna verifica in due passaggi da confermare".to_string(),
+        // The reason comes from the framework, in English: it is only given in `details`
+        MyError::InvalidRequest(..) => "Richiesta non valida".to_string(),
+        MyError::Validation(_) => "Dati non validi".to_string(),
+        MyError::InvalidImage => "Immagine non supportata o danneggiata".to_string(),
+        MyError::AvatarNotFound => "Immagine del profilo non trovata".to_string(),
+        MyError::CannotBlockYourself => "Non puoi bloccare te stesso".to_string(),
+        MyError::RouteNotFound => "Percorso non trovato".to_string(),
+        MyError::Unknown => "Errore sconosciuto".to_string(),
+    }
+}
+
+fn italian_field_rule(rule: FieldRule) -> String {
+    match rule {
+        FieldRule::Length { min: Some(min), max: Some(max) } if min == max => format!("deve avere {} caratteri", min),
+        FieldRule::Length { min: Some(min), max: Some(max) } => format!("deve avere da {} a {} caratteri", min, max),
+        FieldRule::Length { min: Some(min), max: None } => format!("deve avere almeno {} caratteri", min),
+        FieldRule::Length { min: None, max: Some(max) } => format!("deve avere al massimo {} caratteri", max),
+        FieldRule::Count { min: Some(min), max: Some(max) } => format!("deve elencare da {} a {} utenti", min, max),
+        FieldRule::Count { min: Some(min), max: None } => format!("deve elencare almeno {} utenti", min),
+        FieldRule::Count { min: None, max: Some(max) } => format!("deve elencare al massimo {} utenti", max),
+        FieldRule::Range { min: Some(min), max: Some(max) } => format!("deve essere compreso tra {} e {}", min, max),
+        FieldRule::Range { min: Some(0), max: None } => "non può essere negativo".to_string(),
+        FieldRule::Range { min: Some(min), max: None } => format!("deve essere almeno {}", min),
+        FieldRule::Range { min: None, max: Some(max) } => format!("deve essere al massimo {}", max),
+        FieldRule::UsernameCharacters => "può contenere solo lettere, cifre, '_', '.' e '-'".to_string(),
+        FieldRule::InvalidUsername => "contiene un nome utente non valido".to_string(),
+        _ => "non è valido".to_string(),
+    }
+}
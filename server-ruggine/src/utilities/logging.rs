@@ -0,0 +1,57 @@
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+use crate::utilities::config::{Config, LogFormat};
+
+// Header carrying the id of each HTTP request, generated by the server when the client does not send one
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Installs the global subscriber, with the configured format and level filter
+pub fn init(config: &Config) {
+    let filter = EnvFilter::try_new(&config.log_filter).unwrap_or_else(|e| {
+        eprintln!("Invalid log filter {}: {}, using info", config.log_filter, e);
+        EnvFilter::new("info")
+    });
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
+
+// Span wrapping an HTTP request. `username` is recorded once the request is authenticated.
+// Only the path is logged: query strings and bodies may carry personal data.
+pub fn make_request_span(request: &Request<Body>) -> Span {
+    let path = request.uri().path();
+    let route = request.extensions().get::<MatchedPath>().map(|p| p.as_str());
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "http_request",
+        request_id,
+        method = %request.method(),
+        route = route.unwrap_or(path),
+        chat_id = route.and_then(|route| path_param(route, path, ":chatId")),
+        username = tracing::field::Empty,
+    )
+}
+
+// Records the authenticated user on the current span
+pub fn record_username(username: &str) {
+    Span::current().record("username", username);
+}
+
+// Extracts a parameter from a path, given the route it matched (e.g. `/chats/:chatId/messages`)
+fn path_param<'a>(route: &str, path: &'a str, param: &str) -> Option<&'a str> {
+    route
+        .split('/')
+        .zip(path.split('/'))
+        .find_map(|(segment, value)| (segment == param).then_some(value))
+}
@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::net::TcpListener;
+use std::time::Instant;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use axum_server::Handle;
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::{info, warn};
+
+// Upper bounds (in seconds) of the latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Installs the global Prometheus recorder and describes the exported metrics.
+// The returned handle renders them for the `/metrics` endpoint, served by `serve`.
+pub fn install() -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("duration_seconds".to_string()), LATENCY_BUCKETS)
+        .expect("invalid histogram buckets")
+        .install_recorder()
+        .expect("failed to install the metrics recorder");
+
+    describe_counter!("http_requests_total", "HTTP requests handled, by route, method and status");
+    describe_histogram!("http_request_duration_seconds", Unit::Seconds, "HTTP request latency, by route and method");
+    describe_histogram!("db_query_duration_seconds", Unit::Seconds, "Database operation latency, by operation");
+    describe_counter!("chat_messages_sent_total", "Chat messages stored (REST, WebSocket and automatic)");
+    describe_counter!("ws_messages_delivered_total", "Chat messages delivered to WebSocket clients");
+    describe_gauge!("ws_connections_active", "Open WebSocket connections");
+    describe_gauge!("ws_queued_messages", "Messages waiting in the WebSocket fan-out queues");
+    describe_gauge!("ws_max_queue_depth", "Length of the longest WebSocket fan-out queue");
+    describe_counter!("ws_dropped_messages_total", "Messages discarded because a fan-out queue was full");
+    describe_counter!("ws_evictions_total", "WebSocket connections closed because their fan-out queue was full");
+    describe_counter!("rate_limited_total", "Requests and WebSocket frames rejected by the rate limiter, by rule");
+    describe_gauge!("process_cpu_usage_percent", "CPU usage of the server process");
+    describe_gauge!("process_resident_memory_bytes", Unit::Bytes, "Resident memory of the server process");
+    describe_gauge!("process_virtual_memory_bytes", Unit::Bytes, "Virtual memory of the server process");
+
+    handle
+}
+
+// Middleware counting the HTTP requests and measuring their latency.
+// Requests are labelled with the route they matched, not their path, to keep the number of series bounded
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    counter!("http_requests_total", "route" => route.clone(), "method" => method.clone(), "status" => status).increment(1);
+    histogram!("http_request_duration_seconds", "route" => route, "method" => method).record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+// Runs a database operation, recording its latency
+pub async fn time_db<T>(operation: &'static str, query: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = query.await;
+    histogram!("db_query_duration_seconds", "operation" => operation).record(start.elapsed().as_secs_f64());
+    result
+}
+
+// Handler for `GET /metrics`, in the Prometheus text format
+pub async fn metrics_handler(State(handle): State<PrometheusHandle>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], handle.render())
+}
+
+// Serves `GET /metrics` on its own listener, kept apart from the public API so that it can stay
+// on a private address (by default the loopback interface)
+pub async fn serve(listener: TcpListener, metrics: PrometheusHandle, handle: Handle) {
+    let app = Router::new().route("/metrics", get(metrics_handler)).with_state(metrics);
+
+    if let Ok(addr) = listener.local_addr() {
+        info!("Metrics available at http://{}/metrics", addr);
+    }
+    if let Err(e) = axum_server::from_tcp(listener).handle(handle).serve(app.into_make_service()).await {
+        warn!(error = %e, "Metrics listener stopped");
+    }
+}
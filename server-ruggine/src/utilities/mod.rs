@@ -1,4 +1,18 @@
 pub mod error;
 
 pub mod utils;
-pub mod monitor;
\ No newline at end of file
+pub mod monitor;
+pub mod config;
+pub mod logging;
+pub mod metrics;
+pub mod shutdown;
+pub mod tls;
+pub mod rate_limit;
+pub mod password_policy;
+pub mod notifier;
+pub mod sessions;
+pub mod totp;
+pub mod validation;
+pub mod extract;
+pub mod i18n;
+pub mod avatar;
@@ -1,43 +1,108 @@
-use std::fs::{OpenOptions};
+use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::thread;
-use std::time::Duration;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use metrics::{counter, gauge};
+use metrics_exporter_prometheus::PrometheusHandle;
 use sysinfo::{System, SystemExt, ProcessExt};
+use crate::state::EventBus;
+use crate::utilities::config::Config;
+use tracing::{error, info};
 
-pub fn start_monitoring() {
-    thread::spawn(|| {
+// Samples the process CPU and memory usage and the state of the WebSocket fan-out at regular intervals,
+// exporting them as metrics (and appending them to the monitor logfile, if configured)
+pub fn start_monitoring(events: EventBus, metrics: PrometheusHandle, config: &Config) -> Monitor {
+    let interval = config.metrics_interval;
+    let log_file_path = config.monitor_logfile.clone();
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+    let thread = thread::spawn(move || {
         let mut system = System::new_all();
 
         // Open or create the logfile
-        let log_file_path = "src/utilities/logfile";
-        let mut log_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_file_path)
-            .expect("Failed to open or create logfile");
+        let mut log_file = log_file_path.and_then(|path| open_logfile(&path));
 
         let pid = sysinfo::get_current_pid().expect("Failed to get current PID");
 
         loop {
-            // Refresh system information
-            system.refresh_all();
+            // Refresh the information about this process only
+            system.refresh_cpu();
+            system.refresh_process(pid);
 
             if let Some(process) = system.process(pid) {
                 let cpu_usage = process.cpu_usage();
-                let log_message = format!("Server CPU Usage: {:.2}%\n", cpu_usage);
-                
-                print!("{}", log_message);
-
-                // Write the log on file
-                if let Err(e) = log_file.write_all(log_message.as_bytes()) {
-                    eprintln!("Failed to write to logfile: {}", e);
-                }
+                gauge!("process_cpu_usage_percent").set(cpu_usage as f64);
+                gauge!("process_resident_memory_bytes").set(process.memory() as f64);
+                gauge!("process_virtual_memory_bytes").set(process.virtual_memory() as f64);
+                write_log(&mut log_file, &format!("Server CPU Usage: {:.2}%\n", cpu_usage));
             } else {
-                eprintln!("Process not found!");
+                error!("Process not found!");
             }
 
-            // Sleep for 2 minutes
-            thread::sleep(Duration::from_secs(120));
+            // Export the state of the WebSocket fan-out queues
+            let stats = events.stats();
+            gauge!("ws_queued_messages").set(stats.queued_messages as f64);
+            gauge!("ws_max_queue_depth").set(stats.max_queue_depth as f64);
+            counter!("ws_dropped_messages_total").absolute(stats.dropped_messages);
+            counter!("ws_evictions_total").absolute(stats.evictions);
+            write_log(&mut log_file, &format!(
+                "WebSocket connections: {}, queued messages: {}, dropped messages: {}, evictions: {}\n",
+                stats.connections, stats.queued_messages, stats.dropped_messages, stats.evictions
+            ));
+
+            // Drain the histogram samples recorded since the last run
+            metrics.run_upkeep();
+
+            // Wait for the next sample, waking up early when the monitor is stopped
+            match stop_rx.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        if let Some(file) = log_file.as_mut()
+            && let Err(e) = file.flush()
+        {
+            error!(error = %e, "Failed to flush logfile");
         }
     });
-}
\ No newline at end of file
+
+    Monitor { stop_tx, thread }
+}
+
+// Handle to the monitor thread
+pub struct Monitor {
+    stop_tx: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Monitor {
+    // Stops the monitor thread and waits for it to exit (blocking: call it outside the async runtime)
+    pub fn stop(self) {
+        let _ = self.stop_tx.send(());
+        if self.thread.join().is_err() {
+            error!("Monitor thread panicked");
+        }
+    }
+}
+
+fn open_logfile(path: &str) -> Option<File> {
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => {
+            info!(path, "Writing monitor samples to logfile");
+            Some(file)
+        }
+        Err(e) => {
+            error!(path, error = %e, "Failed to open or create logfile, file logging disabled");
+            None
+        }
+    }
+}
+
+fn write_log(log_file: &mut Option<File>, line: &str) {
+    if let Some(file) = log_file
+        && let Err(e) = file.write_all(line.as_bytes())
+    {
+        error!(error = %e, "Failed to write to logfile");
+    }
+}
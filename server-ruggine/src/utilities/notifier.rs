@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+use crate::utilities::error::MyError;
+
+// Messages sent to the users outside the chat
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notification {
+    // The token to confirm a password reset with
+    PasswordReset { token: String, expires_at: DateTime<Utc> },
+}
+
+impl Notification {
+    fn kind(&self) -> &'static str {
+        match self {
+            Notification::PasswordReset { .. } => "password_reset",
+        }
+    }
+}
+
+// Delivers notifications to the users (e-mail, SMS, ...)
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, username: &str, notification: &Notification) -> Result<(), MyError>;
+}
+
+// Default notifier, for development and offline tests: appends each notification as a JSON line
+// to a local outbox file instead of sending it
+pub struct OutboxNotifier {
+    path: PathBuf,
+    // Serializes the writes, so that lines are never interleaved
+    lock: Mutex<()>,
+}
+
+impl OutboxNotifier {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        OutboxNotifier { path: path.into(), lock: Mutex::new(()) }
+    }
+}
+
+#[async_trait]
+impl Notifier for OutboxNotifier {
+    async fn notify(&self, username: &str, notification: &Notification) -> Result<(), MyError> {
+        let line = json!({ "to": username, "created_at": Utc::now(), "notification": notification }).to_string() + "\n";
+
+        let _guard = self.lock.lock().await;
+        let result = async {
+            let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+            file.write_all(line.as_bytes()).await
+        }.await;
+
+        match result {
+            Ok(()) => {
+                // The contents may be secret (e.g. reset tokens): only the kind is logged
+                info!(to = username, kind = notification.kind(), path = %self.path.display(), "Notification written to the outbox");
+                Ok(())
+            }
+            Err(e) => {
+                error!(to = username, path = %self.path.display(), error = %e, "Failed to write to the outbox");
+                Err(MyError::NotificationFailed)
+            }
+        }
+    }
+}
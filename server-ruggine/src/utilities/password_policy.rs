@@ -0,0 +1,53 @@
+use std::fmt;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
+
+// Strength rule a password failed, with the limit it was checked against
+#[derive(Debug, Clone, Copy)]
+pub enum PasswordRule {
+    MinLength(usize),
+    MaxLength(usize),
+    MinClasses(usize),
+    NotUsername,
+}
+
+impl fmt::Display for PasswordRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordRule::MinLength(length) => write!(f, "must be at least {} characters long", length),
+            PasswordRule::MaxLength(length) => write!(f, "must be at most {} characters long", length),
+            PasswordRule::MinClasses(classes) => {
+                write!(f, "must mix at least {} of lowercase letters, uppercase letters, digits and symbols", classes)
+            }
+            PasswordRule::NotUsername => write!(f, "must differ from the username"),
+        }
+    }
+}
+
+// Checks a new password against the configured strength rules: minimum and maximum length,
+// number of character classes (lowercase, uppercase, digits, symbols), and not the username itself
+pub fn check_password(config: &Config, username: &str, password: &str) -> Result<(), MyError> {
+    let length = password.chars().count();
+    if length < config.password_min_length {
+        return Err(MyError::WeakPassword(PasswordRule::MinLength(config.password_min_length)));
+    }
+    if length > config.password_max_length {
+        return Err(MyError::WeakPassword(PasswordRule::MaxLength(config.password_max_length)));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    let used = classes.iter().filter(|&&used| used).count();
+    if used < config.password_min_classes {
+        return Err(MyError::WeakPassword(PasswordRule::MinClasses(config.password_min_classes)));
+    }
+
+    if password.eq_ignore_ascii_case(username) {
+        return Err(MyError::WeakPassword(PasswordRule::NotUsername));
+    }
+    Ok(())
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::extract::{ConnectInfo, FromRequestParts, Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use metrics::counter;
+use crate::route_handlers::user_handler::AuthUser;
+use crate::utilities::config::{Config, RateLimit};
+use crate::utilities::error::MyError;
+
+// Past this number of buckets, the ones that have refilled completely are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+// Rate-limited actions, each with its own limit and buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    Login,
+    // Second factor of the sign-in, 2FA enrolment and confirmation
+    TwoFactor,
+    PasswordChange,
+    // Reset requests and their confirmation
+    PasswordReset,
+    Register,
+    Invite,
+    // Chat messages, sent over REST or over the WebSocket
+    Message,
+    // Sync frames on the WebSocket
+    WsSync,
+}
+
+impl Rule {
+    fn name(&self) -> &'static str {
+        match self {
+            Rule::Login => "login",
+            Rule::TwoFactor => "two_factor",
+            Rule::PasswordChange => "password_change",
+            Rule::PasswordReset => "password_reset",
+            Rule::Register => "register",
+            Rule::Invite => "invite",
+            Rule::Message => "message",
+            Rule::WsSync => "ws_sync",
+        }
+    }
+}
+
+// Owner of a bucket
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    Ip(IpAddr),
+    User(String),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token buckets keyed by rule and by client IP or username, kept in memory
+pub struct RateLimiter {
+    limits: HashMap<Rule, RateLimit>,
+    trust_forwarded_for: bool,
+    buckets: Mutex<HashMap<(Rule, Subject), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        let limits = HashMap::from([
+            (Rule::Login, config.rate_login),
+            (Rule::TwoFactor, config.rate_two_factor),
+            (Rule::PasswordChange, config.rate_password_change),
+            (Rule::PasswordReset, config.rate_password_reset),
+            (Rule::Register, config.rate_register),
+            (Rule::Invite, config.rate_invite),
+            (Rule::Message, config.rate_message),
+            (Rule::WsSync, config.rate_ws_sync),
+        ]);
+        RateLimiter { limits, trust_forwarded_for: config.trust_forwarded_for, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // Takes a token from the bucket of the client IP and from the one of the user, when given.
+    // If either is empty nothing is taken, and the time until both have a token is returned
+    pub fn check(&self, rule: Rule, ip: Option<IpAddr>, user: Option<&str>) -> Result<(), Duration> {
+        let limit = self.limits[&rule];
+        if limit.is_disabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let keys: Vec<(Rule, Subject)> = ip
+            .map(Subject::Ip)
+            .into_iter()
+            .chain(user.map(|user| Subject::User(user.to_string())))
+            .map(|subject| (rule, subject))
+            .collect();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|(rule, _), bucket| refill(bucket, self.limits[rule], now) < self.limits[rule].burst as f64);
+        }
+
+        let mut wait = Duration::ZERO;
+        for key in &keys {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket { tokens: limit.burst as f64, updated: now });
+            let tokens = refill(bucket, limit, now);
+            if tokens < 1.0 {
+                wait = wait.max(limit.period.mul_f64((1.0 - tokens) / limit.burst as f64));
+            }
+        }
+        if !wait.is_zero() {
+            counter!("rate_limited_total", "rule" => rule.name()).increment(1);
+            return Err(wait);
+        }
+
+        for key in &keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes a token from the bucket of a username typed by the client, whatever its case:
+    // attempts on the same account from many addresses share it
+    pub fn check_username(&self, rule: Rule, username: &str) -> Result<(), Duration> {
+        self.check(rule, None, Some(&username.to_lowercase()))
+    }
+
+    // IP of the client: the peer of the connection or, behind a trusted proxy, the first `X-Forwarded-For` address
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+        if self.trust_forwarded_for
+            && let Some(ip) = headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        {
+            return Some(ip);
+        }
+        peer.map(|addr| addr.ip())
+    }
+
+    // Binds the limiter to a client, for the frames received on its WebSocket connection
+    pub fn for_client(self: &Arc<Self>, ip: Option<IpAddr>, user: &str) -> ClientLimiter {
+        ClientLimiter { limiter: self.clone(), ip, user: user.to_string() }
+    }
+}
+
+// Adds the tokens accumulated since the last update, up to the burst, returning the available tokens
+fn refill(bucket: &mut Bucket, limit: RateLimit, now: Instant) -> f64 {
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    let burst = limit.burst as f64;
+    bucket.tokens = (bucket.tokens + elapsed * burst / limit.period.as_secs_f64()).min(burst);
+    bucket.updated = now;
+    bucket.tokens
+}
+
+// Rate limiter of a single client (IP and username)
+#[derive(Clone)]
+pub struct ClientLimiter {
+    limiter: Arc<RateLimiter>,
+    ip: Option<IpAddr>,
+    user: String,
+}
+
+impl ClientLimiter {
+    pub fn check(&self, rule: Rule) -> Result<(), Duration> {
+        self.limiter.check(rule, self.ip, Some(&self.user))
+    }
+}
+
+// Whole seconds to announce in `Retry-After`, rounded up
+pub fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+// Middleware applying `rule` to a route, per client IP and, when the request carries a valid session, per user.
+// Over the limit the handler is not run and the client gets `429 Too Many Requests` with `Retry-After`
+pub async fn rate_limit(
+    State((limiter, rule)): State<(Arc<RateLimiter>, Rule)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+    let ip = limiter.client_ip(&parts.headers, peer);
+    let user = AuthUser::from_request_parts(&mut parts, &()).await.ok().map(|AuthUser(user)| user);
+
+    if let Err(wait) = limiter.check(rule, ip, user.as_deref()) {
+        return MyError::TooManyRequests(retry_after_secs(wait)).into_response();
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OFFICE: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    // Three sign-ins a minute: a token every 20 seconds
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(&Config { rate_login: RateLimit::new(3, 60), ..Config::default() })
+    }
+
+    // Moves the last update of every bucket back in time, as if `elapsed` had passed
+    fn wait(limiter: &RateLimiter, elapsed: Duration) {
+        for bucket in limiter.buckets.lock().unwrap().values_mut() {
+            bucket.updated -= elapsed;
+        }
+    }
+
+    #[test]
+    fn the_burst_can_be_used_up() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            assert_eq!(limiter.check(Rule::Login, Some(HOME), None), Ok(()));
+        }
+
+        // The next token comes in 20 seconds
+        let wait = limiter.check(Rule::Login, Some(HOME), None).unwrap_err();
+        assert!(wait <= Duration::from_secs(20) && wait > Duration::from_secs(19), "{:?}", wait);
+        assert_eq!(retry_after_secs(wait), 20);
+        // Refused attempts take nothing: the wait does not grow
+        assert!(limiter.check(Rule::Login, Some(HOME), None).unwrap_err() <= wait);
+    }
+
+    #[test]
+    fn tokens_come_back_over_time() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            limiter.check(Rule::Login, Some(HOME), None).unwrap();
+        }
+
+        wait(&limiter, Duration::from_secs(21));
+        assert_eq!(limiter.check(Rule::Login, Some(HOME), None), Ok(()));
+        assert!(limiter.check(Rule::Login, Some(HOME), None).is_err());
+
+        wait(&limiter, Duration::from_secs(3600));
+        for _ in 0..3 {
+            assert_eq!(limiter.check(Rule::Login, Some(HOME), None), Ok(()));
+        }
+        assert!(limiter.check(Rule::Login, Some(HOME), None).is_err());
+    }
+
+    #[test]
+    fn refill_is_proportional_and_capped() {
+        let limit = RateLimit::new(3, 60);
+        let start = Instant::now();
+        let mut bucket = Bucket { tokens: 0.0, updated: start };
+        assert_eq!(refill(&mut bucket, limit, start + Duration::from_secs(30)), 1.5);
+        assert_eq!(refill(&mut bucket, limit, start + Duration::from_secs(40)), 2.0);
+        assert_eq!(refill(&mut bucket, limit, start + Duration::from_secs(3600)), 3.0);
+        assert_eq!(bucket.updated, start + Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn ip_and_user_buckets_are_independent() {
+        let limiter = limiter();
+        for _ in 0..3 {
+            limiter.check(Rule::Login, Some(HOME), Some("alice")).unwrap();
+        }
+
+        // Another address is not limited, but the user still is
+        assert_eq!(limiter.check(Rule::Login, Some(OFFICE), None), Ok(()));
+        assert!(limiter.check(Rule::Login, Some(OFFICE), Some("alice")).is_err());
+        assert_eq!(limiter.check(Rule::Login, None, Some("bob")), Ok(()));
+        assert!(limiter.check(Rule::Login, Some(HOME), Some("bob")).is_err());
+
+        // The refused attempt took nothing from the address: two tokens are left
+        assert_eq!(limiter.check(Rule::Login, Some(OFFICE), None), Ok(()));
+        assert_eq!(limiter.check(Rule::Login, Some(OFFICE), None), Ok(()));
+        assert!(limiter.check(Rule::Login, Some(OFFICE), None).is_err());
+
+        // Each rule has its own buckets
+        assert_eq!(limiter.check(Rule::TwoFactor, Some(HOME), Some("alice")), Ok(()));
+    }
+
+    #[test]
+    fn usernames_share_a_bucket_whatever_their_case() {
+        let limiter = limiter();
+        limiter.check_username(Rule::Login, "alice").unwrap();
+        limiter.check_username(Rule::Login, "Alice").unwrap();
+        limiter.check_username(Rule::Login, "ALICE").unwrap();
+        assert!(limiter.check_username(Rule::Login, "aLiCe").is_err());
+        assert_eq!(limiter.check_username(Rule::Login, "bob"), Ok(()));
+    }
+
+    #[test]
+    fn disabled_limits_allow_everything() {
+        let limiter = RateLimiter::new(&Config { rate_login: RateLimit::new(0, 1), ..Config::default() });
+        for _ in 0..100 {
+            assert_eq!(limiter.check(Rule::Login, Some(HOME), Some("alice")), Ok(()));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up() {
+        assert_eq!(retry_after_secs(Duration::ZERO), 0);
+        assert_eq!(retry_after_secs(Duration::from_secs(2)), 2);
+        assert_eq!(retry_after_secs(Duration::from_millis(2001)), 3);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use async_session::{MemoryStore, Session, SessionStore};
+
+// Session entry holding the revocation generation of the user when the session was issued
+const GENERATION: &str = "revocation_generation";
+
+// Revoked sessions: each user has a generation, bumped whenever their sessions are revoked, and the
+// sessions issued in an earlier generation are no longer valid. Kept in memory, like the sessions themselves
+#[derive(Clone, Default)]
+pub struct SessionRevocations(Arc<Mutex<HashMap<String, u64>>>);
+
+impl SessionRevocations {
+    // Records the current generation of the user in a new session
+    pub fn stamp(&self, username: &str, session: &mut Session) {
+        let _ = session.insert(GENERATION, self.generation(username));
+    }
+
+    // Revokes every session of the user issued until now, returning the new generation
+    pub fn revoke_all(&self, username: &str) -> u64 {
+        let mut generations = self.0.lock().unwrap();
+        let generation = generations.entry(username.to_string()).or_default();
+        *generation += 1;
+        *generation
+    }
+
+    // Revokes every session of the user except `current`, which must be stored again
+    pub fn revoke_others(&self, username: &str, current: &mut Session) {
+        let generation = self.revoke_all(username);
+        let _ = current.insert(GENERATION, generation);
+    }
+
+    pub fn is_revoked(&self, username: &str, session: &Session) -> bool {
+        session.get::<u64>(GENERATION).unwrap_or_default() < self.generation(username)
+    }
+
+    fn generation(&self, username: &str) -> u64 {
+        self.0.lock().unwrap().get(username).copied().unwrap_or_default()
+    }
+}
+
+// Username of the signed-in session identified by the cookie, if any.
+// Unlike `AuthUser`, revoked sessions are left in place for the handler to reject
+pub async fn signed_in_user(store: &MemoryStore, revocations: &SessionRevocations, cookie: Option<String>) -> Option<String> {
+    let session = store.load_session(cookie?).await.ok()??;
+    let user = session.get::<String>("user")?;
+    (!revocations.is_revoked(&user, &session)).then_some(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(revocations: &SessionRevocations, username: &str) -> Session {
+        let mut session = Session::new();
+        session.insert("user", username).unwrap();
+        revocations.stamp(username, &mut session);
+        session
+    }
+
+    #[test]
+    fn revoke_all_signs_out_every_session_of_the_user() {
+        let revocations = SessionRevocations::default();
+        let (phone, laptop, other) = (session(&revocations, "alice"), session(&revocations, "alice"), session(&revocations, "bob"));
+        assert!(!revocations.is_revoked("alice", &phone));
+
+        revocations.revoke_all("alice");
+        assert!(revocations.is_revoked("alice", &phone));
+        assert!(revocations.is_revoked("alice", &laptop));
+        assert!(!revocations.is_revoked("bob", &other));
+
+        // Sessions issued right after are valid, however little time has passed
+        let again = session(&revocations, "alice");
+        assert!(!revocations.is_revoked("alice", &again));
+        revocations.revoke_all("alice");
+        assert!(revocations.is_revoked("alice", &again));
+    }
+
+    #[test]
+    fn revoke_others_keeps_the_current_session() {
+        let revocations = SessionRevocations::default();
+        let (mut current, other) = (session(&revocations, "alice"), session(&revocations, "alice"));
+
+        revocations.revoke_others("alice", &mut current);
+        assert!(!revocations.is_revoked("alice", &current));
+        assert!(revocations.is_revoked("alice", &other));
+        assert!(!revocations.is_revoked("alice", &session(&revocations, "alice")));
+
+        revocations.revoke_all("alice");
+        assert!(revocations.is_revoked("alice", &current));
+    }
+
+    #[test]
+    fn unstamped_sessions_are_revoked_with_the_others() {
+        let revocations = SessionRevocations::default();
+        let unstamped = Session::new();
+        assert!(!revocations.is_revoked("alice", &unstamped));
+        revocations.revoke_all("alice");
+        assert!(revocations.is_revoked("alice", &unstamped));
+    }
+}
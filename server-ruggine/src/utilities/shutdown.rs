@@ -0,0 +1,43 @@
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+use crate::state::EventBus;
+
+// Completes when the process receives SIGINT (Ctrl+C) or, on Unix, SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to install the Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+// Waits until every WebSocket connection has been closed, or until the deadline expires
+pub async fn drain_connections(events: &EventBus, deadline: Instant) {
+    loop {
+        let connections = events.stats().connections;
+        if connections == 0 {
+            info!("All WebSocket connections closed");
+            return;
+        }
+        if Instant::now() >= deadline {
+            warn!(connections, "Drain deadline expired, dropping the remaining WebSocket connections");
+            return;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
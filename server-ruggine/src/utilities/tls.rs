@@ -0,0 +1,87 @@
+use std::io;
+use std::net::TcpListener;
+use std::time::{Duration, SystemTime};
+use axum::{extract::Host, http::{uri::Authority, Uri}, response::Redirect, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use tokio::time::interval;
+use tracing::{error, info, warn};
+use crate::utilities::config::Config;
+
+// Loads the certificate and key configured for the server, if any, and starts watching them:
+// when either file changes the new pair is used for the next handshakes, without a restart.
+// Setting only one of the two is an error, rather than silently serving plain HTTP
+pub async fn load(config: &Config) -> io::Result<Option<RustlsConfig>> {
+    let (cert, key) = match (config.tls_cert_path.clone(), config.tls_key_path.clone()) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => return Ok(None),
+        (Some(_), None) => return Err(missing("RUGGINE_TLS_KEY", "RUGGINE_TLS_CERT")),
+        (None, Some(_)) => return Err(missing("RUGGINE_TLS_CERT", "RUGGINE_TLS_KEY")),
+    };
+
+    // Use ring as the process-wide crypto provider (an error means one is already installed)
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tls = RustlsConfig::from_pem_file(&cert, &key).await?;
+    info!(cert, key, "TLS enabled");
+
+    tokio::spawn(watch(tls.clone(), cert, key, config.tls_reload_interval));
+    Ok(Some(tls))
+}
+
+fn missing(unset: &str, set: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not set: TLS needs it as well as {}", unset, set))
+}
+
+// Polls the modification time of the certificate files, reloading them when it changes.
+// If the new files cannot be loaded (e.g. they are only half written) the previous pair stays in use
+async fn watch(tls: RustlsConfig, cert: String, key: String, every: Duration) {
+    let mut last_modified = modified(&cert, &key);
+    let mut ticker = interval(every);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let current = modified(&cert, &key);
+        if current == last_modified {
+            continue;
+        }
+        match tls.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => {
+                info!(cert, key, "TLS certificate reloaded");
+                last_modified = current;
+            }
+            Err(e) => error!(cert, key, error = %e, "Failed to reload the TLS certificate, keeping the previous one"),
+        }
+    }
+}
+
+// Most recent modification time of the two files
+fn modified(cert: &str, key: &str) -> Option<SystemTime> {
+    let mtime = |path: &str| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    mtime(cert).max(mtime(key))
+}
+
+// Serves on `listener` plain HTTP, redirecting every request to the same URL over HTTPS on `https_port`
+pub async fn serve_redirect(listener: TcpListener, https_port: u16, handle: Handle) {
+    let app = Router::new().fallback(move |Host(host): Host, uri: Uri| async move {
+        Redirect::permanent(&https_url(&host, https_port, &uri))
+    });
+
+    if let Ok(addr) = listener.local_addr() {
+        info!("Redirecting http://{} to HTTPS", addr);
+    }
+    if let Err(e) = axum_server::from_tcp(listener).handle(handle).serve(app.into_make_service()).await {
+        warn!(error = %e, "HTTP redirect listener stopped");
+    }
+}
+
+fn https_url(host: &str, https_port: u16, uri: &Uri) -> String {
+    // Drop the port of the plain HTTP listener from the Host header
+    let host = host.parse::<Authority>().map(|authority| authority.host().to_string()).unwrap_or_else(|_| host.to_string());
+    let path = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    }
+}
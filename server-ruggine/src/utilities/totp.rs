@@ -0,0 +1,130 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha1::Sha1;
+
+// Time-based one-time passwords (RFC 6238) with the parameters every authenticator app supports:
+// HMAC-SHA1, 6 digits, 30 second steps
+const STEP_SECS: u64 = 30;
+const DIGITS: usize = 6;
+// Codes of the adjacent steps are accepted too, to tolerate clock drift
+const SKEW_STEPS: u64 = 1;
+
+// A new random secret (160 bits, as recommended by RFC 4226), base32 encoded
+pub fn generate_secret() -> String {
+    BASE32_NOPAD.encode(&rand::random::<[u8; 20]>())
+}
+
+// The `otpauth://` URI authenticator apps import (usually from a QR code)
+pub fn provisioning_uri(issuer: &str, username: &str, secret: &str) -> String {
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC);
+    let username = utf8_percent_encode(username, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}"
+    )
+}
+
+// The current time step
+pub fn current_step() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / STEP_SECS
+}
+
+// Returns the time step around `now` the code was generated for, if any.
+// Callers must refuse steps already used, so that a code cannot be replayed
+pub fn matching_step(secret: &str, code: &str, now: u64) -> Option<u64> {
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    (now.saturating_sub(SKEW_STEPS)..=now + SKEW_STEPS).find(|&step| constant_time_eq(code_at(&key, step).as_bytes(), code.as_bytes()))
+}
+
+fn code_at(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation (RFC 4226, section 5.3)
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    format!("{:0DIGITS$}", binary % 10u32.pow(DIGITS as u32))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// The code an authenticator app shows for the given step
+#[cfg(test)]
+pub fn code(secret: &str, step: u64) -> String {
+    code_at(&BASE32_NOPAD.decode(secret.as_bytes()).expect("base32 secret"), step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The shared secret of the RFC 4226 and RFC 6238 test vectors
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn codes_match_the_rfc_4226_vectors() {
+        // Appendix D: HOTP values for the counters 0 to 9
+        let expected = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(code_at(RFC_KEY, counter as u64), code, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_sha1_vectors() {
+        // Appendix B, SHA-1: the 8 digit codes truncated to our 6 digits
+        let expected = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (time, code) in expected {
+            assert_eq!(code_at(RFC_KEY, time / STEP_SECS), code[2..], "time {}", time);
+        }
+    }
+
+    #[test]
+    fn codes_keep_their_leading_zeros() {
+        // 1111111109 gives 081804: 6 digits, the first being 0
+        assert_eq!(code_at(RFC_KEY, 1111111109 / STEP_SECS), "081804");
+    }
+
+    #[test]
+    fn adjacent_steps_are_accepted() {
+        let secret = BASE32_NOPAD.encode(RFC_KEY);
+        let now = 1000;
+        for step in [now - 1, now, now + 1] {
+            assert_eq!(matching_step(&secret, &code(&secret, step), now), Some(step), "step {}", step);
+        }
+        for step in [now - 2, now + 2] {
+            assert_eq!(matching_step(&secret, &code(&secret, step), now), None, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn malformed_codes_and_secrets_are_refused() {
+        let secret = BASE32_NOPAD.encode(RFC_KEY);
+        let code = code(&secret, 1000);
+        assert_eq!(matching_step(&secret, &code[..5], 1000), None);
+        assert_eq!(matching_step(&secret, &format!("{}0", code), 1000), None);
+        assert_eq!(matching_step(&secret, "12a456", 1000), None);
+        assert_eq!(matching_step("not base32!", &code, 1000), None);
+    }
+
+    #[test]
+    fn comparison_needs_equal_bytes() {
+        assert!(constant_time_eq(b"123456", b"123456"));
+        assert!(!constant_time_eq(b"123456", b"123457"));
+        assert!(!constant_time_eq(b"123456", b"12345"));
+    }
+}
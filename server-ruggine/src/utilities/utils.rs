@@ -1,9 +1,69 @@
-use bcrypt::{hash, verify, DEFAULT_COST};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use tokio::task::spawn_blocking;
+use tracing::error;
+use crate::utilities::config::Config;
+use crate::utilities::error::MyError;
 
-pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-    hash(password, DEFAULT_COST)
+// Outcome of checking a password against a stored hash
+pub struct Verification {
+    pub valid: bool,
+    // The hash uses bcrypt or other Argon2 parameters than the configured ones, and should be replaced
+    pub needs_rehash: bool,
 }
 
-pub fn verify_password(hash: &str, password: &str) -> Result<bool, bcrypt::BcryptError> {
-    verify(password, hash)
+// Hashes the password with Argon2id and the configured parameters.
+// Hashing is deliberately slow, so it runs on the blocking thread pool
+pub async fn hash_password(config: &Config, password: &str) -> Result<String, MyError> {
+    let params = argon2_params(config)?;
+    let password = password.to_string();
+    spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+        .await
+        .map_err(|_| MyError::PasswordHashingFailed)?
+        .map_err(|e| {
+            error!(error = %e, "Password hashing failed");
+            MyError::PasswordHashingFailed
+        })
+}
+
+// Checks the password against a stored hash, Argon2 (PHC string) or legacy bcrypt, on the blocking thread pool
+pub async fn verify_password(config: &Config, hash: &str, password: &str) -> Result<Verification, MyError> {
+    let params = argon2_params(config)?;
+    let (hash, password) = (hash.to_string(), password.to_string());
+    spawn_blocking(move || verify_blocking(&params, &hash, &password))
+        .await
+        .map_err(|_| MyError::PasswordHashingFailed)
+}
+
+fn verify_blocking(params: &Params, hash: &str, password: &str) -> Verification {
+    // Legacy bcrypt hashes ($2a$, $2b$, $2y$)
+    if hash.starts_with("$2") {
+        let valid = bcrypt::verify(password, hash).unwrap_or(false);
+        return Verification { valid, needs_rehash: true };
+    }
+
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        error!("Stored password hash is not in a known format");
+        return Verification { valid: false, needs_rehash: false };
+    };
+    // The parameters used to verify are the ones stored in the hash
+    let valid = Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok();
+    let outdated = parsed.algorithm != argon2::ARGON2ID_IDENT
+        || Params::try_from(&parsed).map_or(true, |stored| {
+            (stored.m_cost(), stored.t_cost(), stored.p_cost()) != (params.m_cost(), params.t_cost(), params.p_cost())
+        });
+    Verification { valid, needs_rehash: outdated }
+}
+
+fn argon2_params(config: &Config) -> Result<Params, MyError> {
+    Params::new(config.argon2_memory_kib, config.argon2_iterations, config.argon2_parallelism, None).map_err(|e| {
+        error!(error = %e, "Invalid Argon2 parameters");
+        MyError::PasswordHashingFailed
+    })
 }
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::request::Parts,
+    Json,
+};
+use serde::Serialize;
+use serde::de::{Deserialize, DeserializeOwned, Deserializer};
+use unicode_normalization::UnicodeNormalization;
+use validator::{Validate, ValidationError, ValidationErrors};
+use crate::utilities::error::MyError;
+use crate::utilities::extract::Query;
+use crate::utilities::i18n::{self, Locale, Text};
+
+// Payloads are cleaned up before being validated: text fields are trimmed and normalised to Unicode NFC,
+// so that blank values are caught and names looking the same are stored the same way.
+// Passwords are left as typed
+pub trait Normalize {
+    fn normalize(&mut self);
+}
+
+pub fn normalize_text(text: &mut String) {
+    let normalized: String = text.trim().nfc().collect();
+    *text = normalized;
+}
+
+// Extractor like `Json`, normalising and validating the payload. Invalid payloads are rejected with `422`
+// and the errors of each field; bodies that are not JSON of the right shape with the status of `Json`
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Normalize + Validate,
+    S: Send + Sync,
+{
+    type Rejection = MyError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(mut payload) = Json::<T>::from_request(req, state).await?;
+        payload.normalize();
+        payload.validate()?;
+        Ok(ValidJson(payload))
+    }
+}
+
+// Extractor like `Query`, normalising and validating the parameters the same way as `ValidJson`
+pub struct ValidQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidQuery<T>
+where
+    T: DeserializeOwned + Normalize + Validate,
+    S: Send + Sync,
+{
+    type Rejection = MyError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(mut params) = Query::<T>::from_request_parts(parts, state).await?;
+        params.normalize();
+        params.validate()?;
+        Ok(ValidQuery(params))
+    }
+}
+
+// Usernames of new accounts: 3 to 32 ASCII letters, digits, '_', '.' or '-'
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if !(3..=32).contains(&username.chars().count()) {
+        let mut error = ValidationError::new("length");
+        error.add_param("min".into(), &3);
+        error.add_param("max".into(), &32);
+        return Err(error);
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')) {
+        return Err(ValidationError::new("username_characters"));
+    }
+    Ok(())
+}
+
+// Lists of users (participants, invitees), each a valid username
+pub fn validate_usernames(usernames: &[String]) -> Result<(), ValidationError> {
+    for username in usernames {
+        validate_username(username).map_err(|_| ValidationError::new("invalid_username"))?;
+    }
+    Ok(())
+}
+
+// Check a field failed, with the limits it was checked against. Built from the code of the
+// `ValidationError` (`length` and `range` as set by `validator`, `count` for the length of lists)
+// and its parameters, and translated like the other texts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldRule {
+    // Characters of a text
+    Length { min: Option<u64>, max: Option<u64> },
+    // Items of a list of users
+    Count { min: Option<u64>, max: Option<u64> },
+    Range { min: Option<i64>, max: Option<i64> },
+    UsernameCharacters,
+    InvalidUsername,
+    Invalid,
+}
+
+impl FieldRule {
+    pub fn from_error(error: &ValidationError) -> Self {
+        let param = |name: &str| error.params.get(name).and_then(|value| value.as_f64());
+        let limits = || {
+            let equal = param("equal");
+            (equal.or(param("min")).map(|min| min as u64), equal.or(param("max")).map(|max| max as u64))
+        };
+        match error.code.as_ref() {
+            "length" => {
+                let (min, max) = limits();
+                FieldRule::Length { min, max }
+            }
+            "count" => {
+                let (min, max) = limits();
+                FieldRule::Count { min, max }
+            }
+            "range" => FieldRule::Range { min: param("min").map(|min| min as i64), max: param("max").map(|max| max as i64) },
+            "username_characters" => FieldRule::UsernameCharacters,
+            "invalid_username" => FieldRule::InvalidUsername,
+            _ => FieldRule::Invalid,
+        }
+    }
+
+    // Stable code, for programs
+    pub fn code(&self) -> &'static str {
+        match self {
+            FieldRule::Length { .. } => "length",
+            FieldRule::Count { .. } => "count",
+            FieldRule::Range { .. } => "range",
+            FieldRule::UsernameCharacters => "username_characters",
+            FieldRule::InvalidUsername => "invalid_username",
+            FieldRule::Invalid => "invalid",
+        }
+    }
+}
+
+impl fmt::Display for FieldRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FieldRule::Length { min: Some(min), max: Some(max) } if min == max => write!(f, "must be {} characters long", min),
+            FieldRule::Length { min: Some(min), max: Some(max) } => write!(f, "must be {} to {} characters long", min, max),
+            FieldRule::Length { min: Some(min), max: None } => write!(f, "must be at least {} characters long", min),
+            FieldRule::Length { min: None, max: Some(max) } => write!(f, "must be at most {} characters long", max),
+            FieldRule::Count { min: Some(min), max: Some(max) } => write!(f, "must list {} to {} users", min, max),
+            FieldRule::Count { min: Some(min), max: None } => write!(f, "must list at least {} users", min),
+            FieldRule::Count { min: None, max: Some(max) } => write!(f, "must list at most {} users", max),
+            FieldRule::Range { min: Some(min), max: Some(max) } => write!(f, "must be between {} and {}", min, max),
+            FieldRule::Range { min: Some(0), max: None } => write!(f, "must not be negative"),
+            FieldRule::Range { min: Some(min), max: None } => write!(f, "must be at least {}", min),
+            FieldRule::Range { min: None, max: Some(max) } => write!(f, "must be at most {}", max),
+            FieldRule::UsernameCharacters => write!(f, "may only contain letters, digits, '_', '.' and '-'"),
+            FieldRule::InvalidUsername => write!(f, "contains an invalid username"),
+            _ => write!(f, "is invalid"),
+        }
+    }
+}
+
+// A failed check, as reported in `details.fields`
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+// Failed checks, per field, described in the given language
+pub fn field_errors(errors: &ValidationErrors, locale: Locale) -> BTreeMap<String, Vec<FieldError>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let errors = errors
+                .iter()
+                .map(|error| {
+                    let rule = FieldRule::from_error(error);
+                    FieldError { code: rule.code(), message: i18n::translate(locale, &Text::InvalidField(rule)) }
+                })
+                .collect();
+            (field.to_string(), errors)
+        })
+        .collect()
+}
+
+// Deserializes a field that can be left out (outer None) or set to `null` (inner None), to tell
+// "leave as it is" from "clear". To be used with `#[serde(default)]`
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Validate)]
+    struct Payload {
+        #[validate(length(min = 1, max = 64))]
+        name: String,
+        #[validate(length(equal = 4))]
+        pin: String,
+        #[validate(length(max = 2, code = "count"), custom(function = "validate_usernames"))]
+        users: Vec<String>,
+        #[validate(range(min = 0))]
+        offset: i64,
+    }
+
+    fn errors() -> ValidationErrors {
+        let payload = Payload {
+            name: String::new(),
+            pin: "12".to_string(),
+            users: vec!["alice".to_string(), "bob".to_string(), "no one".to_string()],
+            offset: -1,
+        };
+        payload.validate().unwrap_err()
+    }
+
+    fn rules(field: &str) -> Vec<FieldRule> {
+        let mut rules: Vec<_> = errors().field_errors()[field].iter().map(FieldRule::from_error).collect();
+        rules.sort_by_key(FieldRule::code);
+        rules
+    }
+
+    #[test]
+    fn failed_checks_keep_their_limits() {
+        assert_eq!(rules("name"), [FieldRule::Length { min: Some(1), max: Some(64) }]);
+        assert_eq!(rules("pin"), [FieldRule::Length { min: Some(4), max: Some(4) }]);
+        assert_eq!(rules("users"), [FieldRule::Count { min: None, max: Some(2) }, FieldRule::InvalidUsername]);
+        assert_eq!(rules("offset"), [FieldRule::Range { min: Some(0), max: None }]);
+        assert_eq!(FieldRule::from_error(&validate_username("a").unwrap_err()), FieldRule::Length { min: Some(3), max: Some(32) });
+        assert_eq!(FieldRule::from_error(&validate_username("a b").unwrap_err()), FieldRule::UsernameCharacters);
+    }
+
+    #[test]
+    fn failed_checks_are_described_in_the_request_language() {
+        let english = field_errors(&errors(), Locale::En);
+        let italian = field_errors(&errors(), Locale::It);
+        assert_eq!((english["name"][0].code, english["name"][0].message.as_str()), ("length", "must be 1 to 64 characters long"));
+        assert_eq!((italian["name"][0].code, italian["name"][0].message.as_str()), ("length", "deve avere da 1 a 64 caratteri"));
+        assert_eq!(english["pin"][0].message, "must be 4 characters long");
+        assert_eq!(italian["offset"][0].message, "non può essere negativo");
+    }
+}